@@ -0,0 +1,55 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use secs::{components::Component, world::World};
+
+struct Position;
+
+impl Component for Position {}
+
+struct Velocity;
+
+impl Component for Velocity {}
+
+/// 10 000 entities, one in ten of them moving.
+fn populated_world<'a>() -> World<'a> {
+    let mut world = World::new();
+    for i in 0..10_000 {
+        if i % 10 == 0 {
+            world.spawn().with((Position, Velocity)).commit();
+        } else {
+            world.spawn().with(Position).commit();
+        }
+    }
+    world
+}
+
+fn queries(c: &mut Criterion) {
+    let mut world = populated_world();
+    let handle = world.register_query::<(Position, Velocity)>();
+
+    c.bench_function("scan query", |b| {
+        b.iter(|| {
+            let entities = world
+                .query()
+                .entities()
+                .with_component::<Position>()
+                .with_component::<Velocity>()
+                .get();
+            black_box(entities.len())
+        })
+    });
+
+    c.bench_function("cached query", |b| {
+        b.iter(|| black_box(world.query_entities(&handle).len()))
+    });
+
+    c.bench_function("cached query upkeep", |b| {
+        let entity = world.query_entities(&handle)[0];
+        b.iter(|| {
+            world.remove_component::<Velocity>(&entity);
+            world.add_component(&entity, Velocity);
+        })
+    });
+}
+
+criterion_group!(benches, queries);
+criterion_main!(benches);
@@ -1,17 +1,46 @@
 use proc_macro::TokenStream;
-use syn::{parse_macro_input, DeriveInput};
+use syn::{parse_macro_input, punctuated::Punctuated, Data, DeriveInput, Index, Token, Type};
 use quote::quote;
 
-#[proc_macro_derive(Component)]
+#[proc_macro_derive(Component, attributes(require))]
 pub fn component_derive(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput); // 1. Use syn to parse the input tokens into a syntax tree.
 
     // get the name of the type we want to implement the trait for
     let name = &input.ident;
 
+    // #[require(A, B)] lists components inserted with their defaults
+    let mut required: Vec<Type> = vec![];
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("require")) {
+        match attr.parse_args_with(Punctuated::<Type, Token![,]>::parse_terminated) {
+            Ok(types) => required.extend(types),
+            Err(err) => return err.to_compile_error().into(),
+        }
+    }
+
+    let requirements = (!required.is_empty()).then(|| {
+        quote! {
+          fn requirements() -> Vec<secs::components::require::Requirement> {
+            vec![#(secs::components::require::Requirement::of::<#required>()),*]
+          }
+        }
+    });
+
     let expanded = quote! {
       impl secs::components::Component for #name {
+        #requirements
 
+        fn clone_fn() -> Option<secs::components::clone::CloneFn> {
+          #[allow(unused_imports)]
+          use secs::components::clone::{CloneViaClone as _, CloneViaNothing as _};
+          (&&secs::components::clone::CloneProbe::<Self>::new()).clone_fn()
+        }
+
+        fn map_fn() -> Option<secs::components::clone::MapFn> {
+          #[allow(unused_imports)]
+          use secs::components::clone::{MapViaMapEntities as _, MapViaNothing as _};
+          (&&secs::components::clone::MapProbe::<Self>::new()).map_fn()
+        }
       }
     };
 
@@ -33,3 +62,178 @@ pub fn game_event_derive(input: TokenStream) -> TokenStream {
 
     TokenStream::from(expanded)
 }
+
+#[proc_macro_derive(Relation)]
+pub fn relation_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let name = &input.ident;
+
+    let expanded = quote! {
+      impl secs::relations::Relation for #name {
+        fn snapshot_fn() -> Option<secs::relations::SnapshotFn> {
+          #[allow(unused_imports)]
+          use secs::relations::{SnapshotViaClone as _, SnapshotViaNothing as _};
+          (&&secs::relations::SnapshotProbe::<Self>::new()).snapshot_fn()
+        }
+      }
+    };
+
+    TokenStream::from(expanded)
+}
+
+#[proc_macro_derive(Reflect, attributes(reflect))]
+pub fn reflect_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return syn::Error::new_spanned(name, "Reflect can only be derived for structs")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    // #[reflect(ignore)] leaves a field out, for types that can't be reflected
+    let mut ignored = vec![false; fields.len()];
+    for (i, field) in fields.iter().enumerate() {
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("reflect")) {
+            match attr.parse_args::<syn::Ident>() {
+                Ok(arg) if arg == "ignore" => ignored[i] = true,
+                Ok(arg) => return syn::Error::new_spanned(arg, "expected `ignore`").to_compile_error().into(),
+                Err(err) => return err.to_compile_error().into(),
+            }
+        }
+    }
+
+    // Named fields are reflected by name, tuple fields by index.
+    let members: Vec<(String, &Type, proc_macro2::TokenStream)> = fields
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !ignored[*i])
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => (ident.to_string(), &field.ty, quote!(#ident)),
+            None => {
+                let index = Index::from(i);
+                (i.to_string(), &field.ty, quote!(#index))
+            }
+        })
+        .collect();
+
+    let names = members.iter().map(|(name, _, _)| name);
+    let types = members.iter().map(|(_, ty, _)| ty);
+    let field_arms = members.iter().map(|(name, _, member)| quote!(#name => Some(&self.#member)));
+    let field_mut_arms = members.iter().map(|(name, _, member)| quote!(#name => Some(&mut self.#member)));
+
+    let expanded = quote! {
+      impl #impl_generics secs::reflect::Reflect for #name #ty_generics #where_clause {
+        fn type_fields() -> Vec<secs::reflect::FieldInfo> {
+          vec![#(secs::reflect::FieldInfo {
+            name: #names,
+            type_name: std::any::type_name::<#types>(),
+          }),*]
+        }
+
+        fn type_name(&self) -> &'static str {
+          std::any::type_name::<Self>()
+        }
+
+        fn fields(&self) -> Vec<secs::reflect::FieldInfo> {
+          <Self as secs::reflect::Reflect>::type_fields()
+        }
+
+        fn field(&self, name: &str) -> Option<&dyn secs::reflect::Reflect> {
+          match name {
+            #(#field_arms,)*
+            _ => None,
+          }
+        }
+
+        fn field_mut(&mut self, name: &str) -> Option<&mut dyn secs::reflect::Reflect> {
+          match name {
+            #(#field_mut_arms,)*
+            _ => None,
+          }
+        }
+
+        fn as_any(&self) -> &dyn std::any::Any {
+          self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+          self
+        }
+      }
+    };
+
+    TokenStream::from(expanded)
+}
+
+#[proc_macro_derive(Bundle)]
+pub fn bundle_derive(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return syn::Error::new_spanned(name, "Bundle can only be derived for structs")
+                .to_compile_error()
+                .into()
+        }
+    };
+
+    let types: Vec<&Type> = fields.iter().map(|field| &field.ty).collect();
+    let members: Vec<_> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let index = Index::from(i);
+                quote!(#index)
+            }
+        })
+        .collect();
+
+    let expanded = quote! {
+      impl #impl_generics secs::bundle::Bundle for #name #ty_generics #where_clause {
+        fn register(
+          component_manager: &mut secs::components::component_manager::ComponentManager,
+        ) -> Result<u32, secs::errors::EcsErrors> {
+          Ok(0 #(| <#types as secs::bundle::Bundle>::register(component_manager)?)*)
+        }
+
+        fn requirements(requirements: &mut Vec<secs::components::require::Requirement>) {
+          #(<#types as secs::bundle::Bundle>::requirements(requirements);)*
+        }
+
+        fn names<'b>(&'b self, names: &mut Vec<&'b str>) {
+          #(secs::bundle::Bundle::names(&self.#members, names);)*
+        }
+
+        fn insert(
+          self,
+          component_manager: &mut secs::components::component_manager::ComponentManager,
+          entity: &secs::entities::Entity,
+        ) {
+          #(secs::bundle::Bundle::insert(self.#members, component_manager, entity);)*
+        }
+
+        fn remove(
+          component_manager: &mut secs::components::component_manager::ComponentManager,
+          entity: &secs::entities::Entity,
+        ) {
+          #(<#types as secs::bundle::Bundle>::remove(component_manager, entity);)*
+        }
+      }
+    };
+
+    TokenStream::from(expanded)
+}
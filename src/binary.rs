@@ -0,0 +1,662 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::{BTreeMap, HashSet, VecDeque},
+};
+
+use log::info;
+
+use crate::{
+    components::{component_manager::ComponentManager, dynamic::ComponentId, Component},
+    entities::{entity_manager::EntityManager, Disabled, Entity},
+    errors::EcsErrors,
+    hierarchy::{Children, Parent},
+    name::{Name, NameMode},
+    world::World,
+};
+
+pub(crate) const MAGIC: &[u8; 4] = b"SECS";
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Appends compactly encoded values to a byte buffer. Integers are written as
+/// LEB128 varints, floats as little endian bytes.
+#[derive(Default)]
+pub struct BinaryWriter {
+    bytes: Vec<u8>,
+}
+
+impl BinaryWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_varint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                return;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    pub fn write_usize(&mut self, value: usize) {
+        self.write_varint(value as u64);
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.write_varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.write_usize(value.len());
+        self.bytes.extend_from_slice(value);
+    }
+
+    pub fn write_str(&mut self, value: &str) {
+        self.write_bytes(value.as_bytes());
+    }
+
+    pub fn write_entity(&mut self, entity: &Entity) {
+        self.write_usize(entity.0);
+    }
+
+    pub(crate) fn write_raw(&mut self, value: &[u8]) {
+        self.bytes.extend_from_slice(value);
+    }
+}
+
+/// Reads values written by `BinaryWriter`, failing instead of panicking on
+/// truncated or malformed input.
+pub struct BinaryReader<'b> {
+    bytes: &'b [u8],
+    position: usize,
+}
+
+impl<'b> BinaryReader<'b> {
+    pub fn new(bytes: &'b [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'b [u8], EcsErrors> {
+        if self.remaining() < len {
+            return Err(EcsErrors::BinaryFormat(format!(
+                "unexpected end of input at byte {}",
+                self.position
+            )));
+        }
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, EcsErrors> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, EcsErrors> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(EcsErrors::BinaryFormat(format!("invalid bool {value}"))),
+        }
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64, EcsErrors> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(EcsErrors::BinaryFormat("varint is too long".to_owned()))
+    }
+
+    pub fn read_usize(&mut self) -> Result<usize, EcsErrors> {
+        usize::try_from(self.read_varint()?)
+            .map_err(|_| EcsErrors::BinaryFormat("value does not fit usize".to_owned()))
+    }
+
+    /// Reads a length that has to be backed by at least one byte per element.
+    pub fn read_len(&mut self) -> Result<usize, EcsErrors> {
+        let len = self.read_usize()?;
+        if len > self.remaining() {
+            return Err(EcsErrors::BinaryFormat(format!(
+                "length {len} exceeds remaining input"
+            )));
+        }
+        Ok(len)
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64, EcsErrors> {
+        let value = self.read_varint()?;
+        Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, EcsErrors> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_f64(&mut self) -> Result<f64, EcsErrors> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'b [u8], EcsErrors> {
+        let len = self.read_len()?;
+        self.take(len)
+    }
+
+    pub fn read_str(&mut self) -> Result<&'b str, EcsErrors> {
+        std::str::from_utf8(self.read_bytes()?)
+            .map_err(|e| EcsErrors::BinaryFormat(e.to_string()))
+    }
+
+    pub fn read_entity(&mut self) -> Result<Entity, EcsErrors> {
+        Ok(Entity(self.read_usize()?))
+    }
+}
+
+/// Components that can be part of the binary world encoding.
+pub trait BinaryComponent: Component + Sized + 'static {
+    /// Part of the schema hash. Bump it when the encoding changes, so data
+    /// written with the old encoding is rejected instead of misread.
+    const VERSION: u32 = 0;
+
+    fn encode(&self, writer: &mut BinaryWriter);
+    fn decode(reader: &mut BinaryReader) -> Result<Self, EcsErrors>;
+}
+
+impl BinaryComponent for Parent {
+    fn encode(&self, writer: &mut BinaryWriter) {
+        writer.write_entity(&self.0);
+    }
+
+    fn decode(reader: &mut BinaryReader) -> Result<Self, EcsErrors> {
+        Ok(Parent(reader.read_entity()?))
+    }
+}
+
+impl BinaryComponent for Children {
+    fn encode(&self, writer: &mut BinaryWriter) {
+        writer.write_usize(self.0.len());
+        self.0.iter().for_each(|child| writer.write_entity(child));
+    }
+
+    fn decode(reader: &mut BinaryReader) -> Result<Self, EcsErrors> {
+        let len = reader.read_len()?;
+        let children = (0..len)
+            .map(|_| reader.read_entity())
+            .collect::<Result<_, _>>()?;
+        Ok(Children(children))
+    }
+}
+
+impl BinaryComponent for Name {
+    fn encode(&self, writer: &mut BinaryWriter) {
+        writer.write_str(&self.0);
+    }
+
+    fn decode(reader: &mut BinaryReader) -> Result<Self, EcsErrors> {
+        Ok(Name(reader.read_str()?.to_owned()))
+    }
+}
+
+impl BinaryComponent for Disabled {
+    fn encode(&self, _: &mut BinaryWriter) {}
+
+    fn decode(_: &mut BinaryReader) -> Result<Self, EcsErrors> {
+        Ok(Disabled)
+    }
+}
+
+type EncodeFn = fn(&EntityManager, &Entity) -> Option<Vec<u8>>;
+type DecodeFn = fn(&[u8]) -> Result<Box<dyn Any>, EcsErrors>;
+type RegisterFn = fn(&mut ComponentManager) -> Result<u32, EcsErrors>;
+type InsertFn = fn(&mut EntityManager, &Entity, Box<dyn Any>) -> Result<(), EcsErrors>;
+type RemoveFn = fn(&mut EntityManager, &Entity) -> Result<(), EcsErrors>;
+type ChangedFn = fn(&EntityManager, u64) -> Vec<usize>;
+type AddedFn = fn(&EntityManager, &Entity, u64) -> bool;
+
+fn encode_component<T: BinaryComponent>(entity_manager: &EntityManager, entity: &Entity) -> Option<Vec<u8>> {
+    let pool = entity_manager.component_manager.get_components::<T>().ok()?;
+    let component = pool.data.get(entity.0)?.as_ref()?;
+    let mut writer = BinaryWriter::new();
+    component.encode(&mut writer);
+    Some(writer.into_bytes())
+}
+
+fn decode_component<T: BinaryComponent>(bytes: &[u8]) -> Result<Box<dyn Any>, EcsErrors> {
+    let mut reader = BinaryReader::new(bytes);
+    let component = T::decode(&mut reader)?;
+    if reader.remaining() != 0 {
+        return Err(EcsErrors::BinaryFormat(format!(
+            "{} trailing bytes after {}",
+            reader.remaining(),
+            type_name::<T>()
+        )));
+    }
+    Ok(Box::new(component))
+}
+
+fn register_component<T: BinaryComponent>(component_manager: &mut ComponentManager) -> Result<u32, EcsErrors> {
+    component_manager.register_checked::<T>()
+}
+
+fn insert_component<T: BinaryComponent>(
+    entity_manager: &mut EntityManager,
+    entity: &Entity,
+    component: Box<dyn Any>,
+) -> Result<(), EcsErrors> {
+    entity_manager.add_component(entity, *component.downcast::<T>().unwrap())
+}
+
+fn remove_component<T: BinaryComponent>(
+    entity_manager: &mut EntityManager,
+    entity: &Entity,
+) -> Result<(), EcsErrors> {
+    if entity_manager.component_manager.get_mask::<T>().is_err() {
+        return Ok(());
+    }
+    entity_manager.remove_component::<T>(entity)
+}
+
+fn changed_slots<T: BinaryComponent>(entity_manager: &EntityManager, since: u64) -> Vec<usize> {
+    entity_manager
+        .component_manager
+        .get_components::<T>()
+        .map(|pool| pool.iter_changed(since).collect())
+        .unwrap_or_default()
+}
+
+fn added_since<T: BinaryComponent>(entity_manager: &EntityManager, entity: &Entity, since: u64) -> bool {
+    entity_manager
+        .component_manager
+        .get_components::<T>()
+        .is_ok_and(|pool| pool.added_since(entity.0, since))
+}
+
+pub(crate) struct BinaryType {
+    pub(crate) type_id: TypeId,
+    pub(crate) version: u32,
+    pub(crate) encode: EncodeFn,
+    pub(crate) decode: DecodeFn,
+    pub(crate) register: RegisterFn,
+    pub(crate) insert: InsertFn,
+    pub(crate) remove: RemoveFn,
+    pub(crate) changed: ChangedFn,
+    pub(crate) added_since: AddedFn,
+}
+
+/// Component types taking part in the binary encoding, keyed by stable names.
+/// Their position in name order is what identifies them on the wire.
+pub struct BinaryRegistry {
+    pub(crate) components: BTreeMap<String, BinaryType>,
+}
+
+impl Default for BinaryRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+type DecodedComponents = Vec<(usize, Box<dyn Any>)>;
+
+struct DecodedWorld {
+    next_id: usize,
+    freed_ids: VecDeque<usize>,
+    entities: Vec<(Entity, DecodedComponents)>,
+}
+
+impl BinaryRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self {
+            components: BTreeMap::new(),
+        };
+        registry.register::<Parent>("secs::Parent");
+        registry.register::<Children>("secs::Children");
+        registry.register::<Disabled>("secs::Disabled");
+        registry
+    }
+
+    pub fn register<T: BinaryComponent>(&mut self, name: &str) {
+        self.components.insert(
+            name.to_owned(),
+            BinaryType {
+                type_id: TypeId::of::<T>(),
+                version: T::VERSION,
+                encode: encode_component::<T>,
+                decode: decode_component::<T>,
+                register: register_component::<T>,
+                insert: insert_component::<T>,
+                remove: remove_component::<T>,
+                changed: changed_slots::<T>,
+                added_since: added_since::<T>,
+            },
+        );
+        info!("Component {} registered for binary encoding as {name}", type_name::<T>());
+    }
+
+    /// FNV-1a hash of the registered names and versions, so both ends can
+    /// tell whether they agree on the component ids and encodings used on
+    /// the wire.
+    pub fn schema_hash(&self) -> u64 {
+        let mut hash = 0xcbf29ce484222325u64;
+        for (name, binary) in self.components.iter() {
+            let bytes = name.bytes().chain(std::iter::once(0)).chain(binary.version.to_le_bytes());
+            for byte in bytes {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+
+    /// Fails when an entity holds a component that is not registered, as
+    /// its signature could not be restored from the encoded components.
+    pub fn encode(&self, entity_manager: &EntityManager) -> Result<Vec<u8>, EcsErrors> {
+        let component_manager = &entity_manager.component_manager;
+        let covered = self
+            .components
+            .values()
+            .filter_map(|binary| component_manager.get_mask_for_id(&binary.type_id).ok())
+            .fold(0, |mask, bit| mask | bit);
+        for entity in entity_manager.alive_entities() {
+            let missing = entity_manager.get_signature(&entity).copied().unwrap_or(0) & !covered;
+            if missing != 0 {
+                let id = ComponentId(missing.trailing_zeros());
+                return Err(EcsErrors::NotBinaryComponent(component_manager.component_name_by_id(id)));
+            }
+        }
+
+        let mut writer = BinaryWriter::new();
+        writer.write_raw(MAGIC);
+        writer.write_raw(&FORMAT_VERSION.to_le_bytes());
+        writer.write_raw(&self.schema_hash().to_le_bytes());
+
+        let (next_id, freed_ids) = entity_manager.id_generator().state();
+        writer.write_usize(next_id);
+        writer.write_usize(freed_ids.len());
+        freed_ids.iter().for_each(|id| writer.write_usize(*id));
+
+        for entity in entity_manager.alive_entities() {
+            let components: Vec<(usize, Vec<u8>)> = self
+                .components
+                .values()
+                .enumerate()
+                .filter_map(|(index, binary)| Some((index, (binary.encode)(entity_manager, &entity)?)))
+                .collect();
+
+            writer.write_entity(&entity);
+            writer.write_usize(components.len());
+            for (index, payload) in components {
+                writer.write_usize(index);
+                writer.write_bytes(&payload);
+            }
+        }
+
+        Ok(writer.into_bytes())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<DecodedWorld, EcsErrors> {
+        let mut reader = BinaryReader::new(bytes);
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(EcsErrors::BinaryFormat("missing header".to_owned()));
+        }
+        let version = u16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(EcsErrors::UnsupportedFormatVersion(version));
+        }
+        let schema_hash = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        if schema_hash != self.schema_hash() {
+            return Err(EcsErrors::SchemaMismatch {
+                expected: self.schema_hash(),
+                found: schema_hash,
+            });
+        }
+
+        let next_id = reader.read_usize()?;
+        let freed_count = reader.read_len()?;
+        let freed_ids = (0..freed_count)
+            .map(|_| reader.read_usize())
+            .collect::<Result<VecDeque<_>, _>>()?;
+
+        // Every id below `next_id` is either freed or encoded as an entity,
+        // which also keeps `next_id` bounded by the input size.
+        let alive_count = next_id
+            .checked_sub(freed_count)
+            .filter(|count| *count <= reader.remaining())
+            .ok_or_else(|| EcsErrors::BinaryFormat(format!("invalid entity count {next_id}")))?;
+
+        let mut seen = vec![false; next_id];
+        for id in freed_ids.iter() {
+            if *id >= next_id || std::mem::replace(&mut seen[*id], true) {
+                return Err(EcsErrors::BinaryFormat(format!("invalid freed id {id}")));
+            }
+        }
+
+        let binary_types: Vec<&BinaryType> = self.components.values().collect();
+        let mut entities = Vec::with_capacity(alive_count);
+        for _ in 0..alive_count {
+            let entity = reader.read_entity()?;
+            if entity.0 >= next_id || std::mem::replace(&mut seen[entity.0], true) {
+                return Err(EcsErrors::BinaryFormat(format!("invalid entity id {}", entity.0)));
+            }
+
+            let component_count = reader.read_len()?;
+            let mut components = Vec::with_capacity(component_count);
+            for _ in 0..component_count {
+                let index = reader.read_usize()?;
+                let binary = binary_types.get(index).ok_or_else(|| {
+                    EcsErrors::BinaryFormat(format!("unknown component index {index}"))
+                })?;
+                components.push((index, (binary.decode)(reader.read_bytes()?)?));
+            }
+            entities.push((entity, components));
+        }
+
+        if reader.remaining() != 0 {
+            return Err(EcsErrors::BinaryFormat(format!(
+                "{} trailing bytes",
+                reader.remaining()
+            )));
+        }
+
+        let mut links = HierarchyLinks::new(next_id);
+        for (entity, components) in entities.iter() {
+            links.alive[entity.0] = true;
+            components.iter().for_each(|(_, component)| links.record(entity, component.as_ref()));
+        }
+        validate_hierarchy(&links)?;
+
+        Ok(DecodedWorld {
+            next_id,
+            freed_ids,
+            entities,
+        })
+    }
+
+    /// Replaces all entities of `entity_manager` with the decoded ones. The
+    /// input is fully validated before anything is changed.
+    pub fn apply(&self, bytes: &[u8], entity_manager: &mut EntityManager) -> Result<(), EcsErrors> {
+        let decoded = self.decode(bytes)?;
+
+        // Everything that could make an insert fail is checked up front, so
+        // the world is never left half replaced.
+        let binary_types: Vec<&BinaryType> = self.components.values().collect();
+        let mut used = vec![false; binary_types.len()];
+        let mut names = HashSet::new();
+        for (_, components) in decoded.entities.iter() {
+            for (index, component) in components {
+                used[*index] = true;
+                if let Some(name) = component.downcast_ref::<Name>() {
+                    if entity_manager.name_mode == NameMode::Unique && !names.insert(name.0.as_str()) {
+                        return Err(EcsErrors::DuplicateName(name.0.clone()));
+                    }
+                }
+            }
+        }
+        for (binary, _) in binary_types.iter().zip(used).filter(|(_, used)| *used) {
+            (binary.register)(&mut entity_manager.component_manager)?;
+        }
+
+        for entity in entity_manager.alive_entities() {
+            entity_manager.remove_entity(&entity);
+        }
+
+        entity_manager.restore_ids(decoded.next_id, decoded.freed_ids);
+
+        for (entity, components) in decoded.entities {
+            for (index, component) in components {
+                (binary_types[index].insert)(entity_manager, &entity, component)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Walk {
+    Unseen,
+    InChain,
+    Done,
+}
+
+/// Parent and child links of a world about to be written, checked by
+/// `validate_hierarchy` before anything is replaced.
+pub(crate) struct HierarchyLinks {
+    pub alive: Vec<bool>,
+    pub parents: Vec<Option<Entity>>,
+    pub children: BTreeMap<Entity, Vec<Entity>>,
+}
+
+impl HierarchyLinks {
+    pub fn new(next_id: usize) -> Self {
+        Self {
+            alive: vec![false; next_id],
+            parents: vec![None; next_id],
+            children: BTreeMap::new(),
+        }
+    }
+
+    /// Records `component` of `entity` if it is a `Parent` or `Children`.
+    pub fn record(&mut self, entity: &Entity, component: &dyn Any) {
+        if let Some(parent) = component.downcast_ref::<Parent>() {
+            self.parents[entity.0] = Some(parent.0);
+        }
+        if let Some(children) = component.downcast_ref::<Children>() {
+            self.children.insert(*entity, children.0.clone());
+        }
+    }
+}
+
+/// Parents and children have to be alive entities and agree with each
+/// other, and parent chains must end, otherwise walking the hierarchy would
+/// never stop.
+pub(crate) fn validate_hierarchy(links: &HierarchyLinks) -> Result<(), EcsErrors> {
+    let invalid = |entity: &Entity, reason: &str| {
+        Err(EcsErrors::BinaryFormat(format!("entity {} {reason}", entity.0)))
+    };
+    let HierarchyLinks { alive, parents, children } = links;
+    let is_alive = |entity: &Entity| alive.get(entity.0).copied().unwrap_or(false);
+
+    // Every chain is walked once: it ends at a root or at an entity already
+    // known to lead to one, and running into an entity of the chain itself
+    // is a cycle.
+    let mut state = vec![Walk::Unseen; alive.len()];
+    for entity in (0..alive.len()).map(Entity).filter(is_alive) {
+        let mut chain = vec![];
+        let mut next = Some(entity);
+        while let Some(current) = next {
+            match state[current.0] {
+                Walk::Done => break,
+                Walk::InChain => return invalid(&entity, "is its own ancestor"),
+                Walk::Unseen => {}
+            }
+            state[current.0] = Walk::InChain;
+            chain.push(current);
+            next = parents[current.0];
+            if next.is_some_and(|parent| !is_alive(&parent)) {
+                return invalid(&current, "has a missing parent");
+            }
+        }
+        chain.iter().for_each(|e| state[e.0] = Walk::Done);
+    }
+
+    // Each child is listed once by its own parent, and each entity with a
+    // parent is listed by it.
+    let mut listed = HashSet::new();
+    for (entity, children) in children.iter() {
+        for child in children.iter() {
+            if parents.get(child.0).copied().flatten() != Some(*entity) || !listed.insert(child.0) {
+                return invalid(entity, "lists a child that is not its own");
+            }
+        }
+    }
+    for entity in (0..alive.len()).map(Entity).filter(is_alive) {
+        if parents[entity.0].is_some() && !listed.contains(&entity.0) {
+            return invalid(&entity, "is not listed by its parent");
+        }
+    }
+    Ok(())
+}
+
+impl<'a> World<'a> {
+    pub fn register_binary_component<T: BinaryComponent>(&mut self, name: &str) {
+        self.binary.register::<T>(name);
+    }
+
+    /// Encodes every entity with its registered components into the compact,
+    /// versioned binary format. The signature of an entity is not written,
+    /// it follows from its components, so every component an entity holds
+    /// has to be registered, otherwise this fails with `NotBinaryComponent`.
+    /// `Parent`, `Children` and `Disabled` are registered from the start.
+    pub fn encode_binary(&self) -> Result<Vec<u8>, EcsErrors> {
+        let bytes = self.binary.encode(self.entity_manager())?;
+        info!("World encoded into {} bytes", bytes.len());
+        Ok(bytes)
+    }
+
+    /// Replaces every entity of this world with the ones in `bytes`. Fails
+    /// without touching the world when the input is malformed or was encoded
+    /// with a different set of registered components.
+    pub fn decode_binary(&mut self, bytes: &[u8]) -> Result<(), EcsErrors> {
+        let binary = std::mem::take(&mut self.binary);
+        let result = binary.apply(bytes, self.entity_manager_mut());
+        self.binary = binary;
+        result?;
+
+        self.entity_manager_mut().stamp_all_entities();
+        self.clear_pending_entities();
+        self.resync_systems();
+        info!("World decoded from {} bytes", bytes.len());
+        Ok(())
+    }
+}
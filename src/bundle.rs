@@ -0,0 +1,183 @@
+use std::any::{type_name, Any};
+
+use log::info;
+
+use crate::{
+    components::{component_manager::ComponentManager, require::Requirement, Component},
+    entities::{entity_manager::EntityManager, Entity},
+    errors::EcsErrors,
+    name::Name,
+    world::World,
+};
+
+/// A set of components inserted and removed together.
+///
+/// Every component is a bundle of one, tuples of bundles are bundles, and
+/// structs get it with `#[derive(Bundle)]` as long as all fields are bundles.
+pub trait Bundle: 'static {
+    /// Registers the component types of the bundle and returns their
+    /// combined signature.
+    fn register(component_manager: &mut ComponentManager) -> Result<u32, EcsErrors>
+    where
+        Self: Sized;
+
+    /// Collects the required components of every component in the bundle.
+    fn requirements(requirements: &mut Vec<Requirement>)
+    where
+        Self: Sized;
+
+    /// Collects the names the bundle gives an entity, to check them before
+    /// anything is inserted.
+    fn names<'b>(&'b self, names: &mut Vec<&'b str>);
+
+    /// Writes the components to their pools without touching the signature.
+    fn insert(self, component_manager: &mut ComponentManager, entity: &Entity);
+
+    /// Empties the pool slots of the bundle's component types.
+    fn remove(component_manager: &mut ComponentManager, entity: &Entity)
+    where
+        Self: Sized;
+}
+
+impl<T: Component + 'static> Bundle for T {
+    fn register(component_manager: &mut ComponentManager) -> Result<u32, EcsErrors> {
+        component_manager.register_checked::<T>()
+    }
+
+    fn requirements(requirements: &mut Vec<Requirement>) {
+        requirements.extend(T::requirements());
+    }
+
+    fn names<'b>(&'b self, names: &mut Vec<&'b str>) {
+        if let Some(name) = (self as &dyn Any).downcast_ref::<Name>() {
+            names.push(&name.0);
+        }
+    }
+
+    fn insert(self, component_manager: &mut ComponentManager, entity: &Entity) {
+        component_manager.add_component(entity, self);
+    }
+
+    fn remove(component_manager: &mut ComponentManager, entity: &Entity) {
+        let _ = component_manager.remove::<T>(entity);
+    }
+}
+
+macro_rules! tuple_bundle {
+    ($($name:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<$($name: Bundle),*> Bundle for ($($name,)*) {
+            fn register(component_manager: &mut ComponentManager) -> Result<u32, EcsErrors> {
+                Ok(0 $(| $name::register(component_manager)?)*)
+            }
+
+            fn requirements(requirements: &mut Vec<Requirement>) {
+                $($name::requirements(requirements);)*
+            }
+
+            fn names<'b>(&'b self, names: &mut Vec<&'b str>) {
+                let ($($name,)*) = self;
+                $($name.names(names);)*
+            }
+
+            fn insert(self, component_manager: &mut ComponentManager, entity: &Entity) {
+                let ($($name,)*) = self;
+                $($name.insert(component_manager, entity);)*
+            }
+
+            fn remove(component_manager: &mut ComponentManager, entity: &Entity) {
+                $($name::remove(component_manager, entity);)*
+            }
+        }
+    };
+}
+
+tuple_bundle!(A);
+tuple_bundle!(A, B);
+tuple_bundle!(A, B, C);
+tuple_bundle!(A, B, C, D);
+tuple_bundle!(A, B, C, D, E);
+tuple_bundle!(A, B, C, D, E, F);
+tuple_bundle!(A, B, C, D, E, F, G);
+tuple_bundle!(A, B, C, D, E, F, G, H);
+tuple_bundle!(A, B, C, D, E, F, G, H, I);
+tuple_bundle!(A, B, C, D, E, F, G, H, I, J);
+tuple_bundle!(A, B, C, D, E, F, G, H, I, J, K);
+tuple_bundle!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+/// Object safe form of `Bundle`, so command buffers can hold bundles of
+/// different types.
+pub trait BoxedBundle {
+    fn insert_into(self: Box<Self>, entity_manager: &mut EntityManager, entity: &Entity) -> Result<(), EcsErrors>;
+}
+
+impl<B: Bundle> BoxedBundle for B {
+    fn insert_into(self: Box<Self>, entity_manager: &mut EntityManager, entity: &Entity) -> Result<(), EcsErrors> {
+        entity_manager.insert_bundle(entity, *self)
+    }
+}
+
+pub type RemoveBundleFn = fn(&mut EntityManager, &Entity) -> Result<(), EcsErrors>;
+
+pub(crate) fn remove_bundle<B: Bundle>(entity_manager: &mut EntityManager, entity: &Entity) -> Result<(), EcsErrors> {
+    entity_manager.remove_bundle::<B>(entity)
+}
+
+impl<'a> EntityManager<'a> {
+    pub fn insert_bundle<B: Bundle>(&mut self, entity: &Entity, bundle: B) -> Result<(), EcsErrors> {
+        if !self.is_alive(entity) {
+            return Err(EcsErrors::EntityDoesNotExist(entity.0));
+        }
+
+        let mut names = vec![];
+        bundle.names(&mut names);
+        for name in names {
+            self.check_name(entity, name)?;
+        }
+
+        // `Parent` and `Children` only change through `set_parent`.
+        let mask = B::register(&mut self.component_manager)?;
+        if mask & self.hierarchy_mask() != 0 {
+            return Err(EcsErrors::HierarchyComponent(type_name::<B>().to_owned()));
+        }
+        bundle.insert(&mut self.component_manager, entity);
+        self.set_signature(entity, self.entity_component_signatures[entity.0] | mask);
+
+        let mut requirements = vec![];
+        B::requirements(&mut requirements);
+        self.insert_required(entity, requirements);
+        Ok(())
+    }
+
+    pub fn remove_bundle<B: Bundle>(&mut self, entity: &Entity) -> Result<(), EcsErrors> {
+        if !self.is_alive(entity) {
+            return Err(EcsErrors::EntityDoesNotExist(entity.0));
+        }
+
+        let mask = B::register(&mut self.component_manager)?;
+        self.detach_hierarchy(entity, mask)?;
+        self.set_signature(entity, self.entity_component_signatures[entity.0] & !mask);
+        B::remove(&mut self.component_manager, entity);
+        Ok(())
+    }
+}
+
+impl<'a> World<'a> {
+    /// Creates an entity with all components of `bundle`, short for
+    /// `spawn().with(bundle).commit()`.
+    pub fn spawn_bundle<B: Bundle>(&mut self, bundle: B) -> Entity {
+        self.spawn().with(bundle).commit()
+    }
+
+    pub fn insert_bundle<B: Bundle>(&mut self, entity: &Entity, bundle: B) {
+        self.entity_manager_mut().insert_bundle(entity, bundle).unwrap();
+        self.sync_systems();
+        info!("Add bundle {} to {}", type_name::<B>(), self.entity_manager().label(entity));
+    }
+
+    pub fn remove_bundle<B: Bundle>(&mut self, entity: &Entity) {
+        self.entity_manager_mut().remove_bundle::<B>(entity).unwrap();
+        self.sync_systems();
+        info!("Removing bundle {} from {}", type_name::<B>(), self.entity_manager().label(entity));
+    }
+}
@@ -0,0 +1,172 @@
+use std::any::{type_name, TypeId};
+
+use log::info;
+
+use crate::{
+    bundle::Bundle,
+    components::component_manager::ComponentManager,
+    entities::{entity_manager::signature_matches, Entity},
+    errors::EcsErrors,
+    query::Query,
+    world::World,
+};
+
+/// Components an entity needs to match a registered query. Every bundle is
+/// a filter, so `(Position, Velocity)` matches entities having both.
+pub trait QueryFilter: 'static {
+    fn signature(component_manager: &mut ComponentManager) -> Result<u32, EcsErrors>;
+}
+
+impl<B: Bundle> QueryFilter for B {
+    fn signature(component_manager: &mut ComponentManager) -> Result<u32, EcsErrors> {
+        B::register(component_manager)
+    }
+}
+
+/// Refers to a query registered with `World::register_query`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct QueryHandle(usize);
+
+const NOT_MATCHED: usize = usize::MAX;
+
+/// Entities matching one signature, kept up to date as signatures change.
+/// Removal swaps the last entity into the freed slot, so the order is not
+/// stable.
+struct CachedQuery {
+    filter: TypeId,
+    signature: u32,
+    entities: Vec<Entity>,
+    /// Position of every entity in `entities`, by entity id.
+    positions: Vec<usize>,
+}
+
+impl CachedQuery {
+    fn insert(&mut self, entity: Entity) {
+        if self.positions.len() <= entity.0 {
+            self.positions.resize(entity.0 + 1, NOT_MATCHED);
+        }
+        if self.positions[entity.0] == NOT_MATCHED {
+            self.positions[entity.0] = self.entities.len();
+            self.entities.push(entity);
+        }
+    }
+
+    fn remove(&mut self, entity: &Entity) {
+        let Some(position) = self.positions.get(entity.0).copied().filter(|p| *p != NOT_MATCHED) else {
+            return;
+        };
+        self.entities.swap_remove(position);
+        if let Some(moved) = self.entities.get(position) {
+            self.positions[moved.0] = position;
+        }
+        self.positions[entity.0] = NOT_MATCHED;
+    }
+
+    fn clear(&mut self) {
+        self.entities.clear();
+        self.positions.clear();
+    }
+}
+
+/// Queries registered in a world, in registration order.
+#[derive(Default)]
+pub struct QueryCache {
+    queries: Vec<CachedQuery>,
+}
+
+impl QueryCache {
+    /// Entities matching the query. Indexing into the cache is all it
+    /// takes, nothing is scanned or allocated.
+    pub fn entities(&self, handle: &QueryHandle) -> &[Entity] {
+        &self.queries[handle.0].entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+
+    /// Moves `entity` in or out of every query after its signature went
+    /// from `old` to `new`.
+    pub(crate) fn update(&mut self, entity: Entity, old: u32, new: u32, disabled: u32) {
+        for query in self.queries.iter_mut() {
+            let matched = signature_matches(old, query.signature, disabled);
+            let matches = signature_matches(new, query.signature, disabled);
+            if matches && !matched {
+                query.insert(entity);
+            } else if matched && !matches {
+                query.remove(&entity);
+            }
+        }
+    }
+
+    /// Rebuilds every match set from `signatures`, indexed by entity id.
+    pub(crate) fn rebuild(&mut self, disabled: u32, signatures: impl Iterator<Item = (Entity, u32)> + Clone) {
+        for query in self.queries.iter_mut() {
+            query.clear();
+            let signature = query.signature;
+            signatures
+                .clone()
+                .filter(|(_, s)| signature_matches(*s, signature, disabled))
+                .for_each(|(entity, _)| query.insert(entity));
+        }
+    }
+}
+
+impl<'a> Query<'a> {
+    /// Entities of a query registered with `World::register_query`.
+    pub fn cached(&self, handle: &QueryHandle) -> &'a [Entity] {
+        self.queries.entities(handle)
+    }
+}
+
+impl<'a> World<'a> {
+    /// Registers a query whose matching entities are tracked as components
+    /// are added and removed. Registering the same filter again returns the
+    /// same handle.
+    ///
+    /// Unlike systems, cached queries do not wait for `update`: entities from
+    /// `create_entity` match right away, as they do for uncached queries.
+    pub fn register_query<F: QueryFilter>(&mut self) -> QueryHandle {
+        self.try_register_query::<F>().unwrap()
+    }
+
+    pub fn try_register_query<F: QueryFilter>(&mut self) -> Result<QueryHandle, EcsErrors> {
+        let filter = TypeId::of::<F>();
+        if let Some(index) = self.queries.queries.iter().position(|q| q.filter == filter) {
+            return Ok(QueryHandle(index));
+        }
+
+        // Pending signature changes would otherwise be applied on top of the
+        // match set built below.
+        self.sync_systems();
+        let signature = F::signature(&mut self.entity_manager_mut().component_manager)?;
+        let mut query = CachedQuery {
+            filter,
+            signature,
+            entities: vec![],
+            positions: vec![],
+        };
+        let entity_manager = self.entity_manager();
+        let disabled = entity_manager.disabled_mask();
+        entity_manager
+            .entity_component_signatures
+            .iter()
+            .enumerate()
+            .map(|(id, s)| (Entity(id), *s))
+            .filter(|(entity, _)| entity_manager.is_alive(entity))
+            .filter(|(_, s)| signature_matches(*s, signature, disabled))
+            .for_each(|(entity, _)| query.insert(entity));
+
+        self.queries.queries.push(query);
+        info!("Registered query {}", type_name::<F>());
+        Ok(QueryHandle(self.queries.queries.len() - 1))
+    }
+
+    pub fn query_entities(&self, handle: &QueryHandle) -> &[Entity] {
+        self.queries.entities(handle)
+    }
+}
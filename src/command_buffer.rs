@@ -1,17 +1,31 @@
 use std::{any::TypeId, collections::VecDeque};
 
-use super::{components::Component, entities::Entity};
+use super::{
+    bundle::{remove_bundle, BoxedBundle, Bundle, RemoveBundleFn},
+    components::{clone::ClonePolicy, Component},
+    entities::Entity,
+    prefab::Prefab,
+};
 
 
 
 pub enum WorldCommand {
     RemoveEntity(usize),
     RemoveComponent(usize, TypeId),
-    CreateEntity(Vec<Box<dyn Component>>),
-    AddComponent(usize, Box<dyn Component>),
+    Spawn(Box<dyn BoxedBundle>),
+    InsertBundle(usize, Box<dyn BoxedBundle>),
+    RemoveBundle(usize, RemoveBundleFn),
+    SetParent(usize, usize),
+    RemoveParent(usize),
+    DespawnRecursive(usize),
+    Instantiate(Prefab, Option<Box<dyn BoxedBundle>>),
+    CloneEntity(usize, ClonePolicy),
 }
 
 
+/// Changes queued by systems and event handlers. They are applied once the
+/// system returns, in the order they were issued, so later commands see the
+/// effects of earlier ones.
 #[derive(Default)]
 pub struct CommandBuffer {
     commands: VecDeque<WorldCommand>
@@ -25,26 +39,69 @@ impl CommandBuffer {
     }
 
     pub fn remove_entity(&mut self, entity: &Entity) {
-        self.commands.push_front(WorldCommand::RemoveEntity(entity.0));
+        self.commands.push_back(WorldCommand::RemoveEntity(entity.0));
     }
 
     pub fn remove_component<T: Component + 'static>(&mut self, entity: &Entity) {
-        self.commands.push_front(WorldCommand::RemoveComponent(entity.0, TypeId::of::<T>()));
+        self.commands.push_back(WorldCommand::RemoveComponent(entity.0, TypeId::of::<T>()));
     }
 
     pub fn add_component(&mut self, entity: &Entity, component: impl Component + 'static) {
-        self.commands.push_front(WorldCommand::AddComponent(entity.0, Box::new(component)));
+        self.insert_bundle(entity, component);
     }
 
-    pub fn create_component(&mut self, components: Vec<Box<dyn Component>>) {
-        self.commands.push_front(WorldCommand::CreateEntity(components));
+    pub fn spawn(&mut self, bundle: impl Bundle) {
+        self.commands.push_back(WorldCommand::Spawn(Box::new(bundle)));
+    }
+
+    pub fn insert_bundle(&mut self, entity: &Entity, bundle: impl Bundle) {
+        self.commands.push_back(WorldCommand::InsertBundle(entity.0, Box::new(bundle)));
+    }
+
+    pub fn remove_bundle<B: Bundle>(&mut self, entity: &Entity) {
+        self.commands.push_back(WorldCommand::RemoveBundle(entity.0, remove_bundle::<B>));
+    }
+
+    pub fn instantiate(&mut self, prefab: &Prefab) {
+        self.commands.push_back(WorldCommand::Instantiate(prefab.clone(), None));
+    }
+
+    /// Instantiates `prefab` with `overrides` added to the root.
+    pub fn instantiate_with(&mut self, prefab: &Prefab, overrides: impl Bundle) {
+        self.commands
+            .push_back(WorldCommand::Instantiate(prefab.clone(), Some(Box::new(overrides))));
+    }
+
+    pub fn clone_entity(&mut self, entity: &Entity) {
+        self.clone_entity_with(entity, ClonePolicy::Skip);
+    }
+
+    pub fn clone_entity_with(&mut self, entity: &Entity, policy: ClonePolicy) {
+        self.commands.push_back(WorldCommand::CloneEntity(entity.0, policy));
+    }
+
+    pub fn set_parent(&mut self, child: &Entity, parent: &Entity) {
+        self.commands.push_back(WorldCommand::SetParent(child.0, parent.0));
+    }
+
+    pub fn remove_parent(&mut self, child: &Entity) {
+        self.commands.push_back(WorldCommand::RemoveParent(child.0));
+    }
+
+    pub fn despawn_recursive(&mut self, entity: &Entity) {
+        self.commands.push_back(WorldCommand::DespawnRecursive(entity.0));
     }
 
     pub fn iterate(&self) -> impl Iterator<Item = &WorldCommand> {
         self.commands.iter()
     }
+}
+
+impl IntoIterator for CommandBuffer {
+    type Item = WorldCommand;
+    type IntoIter = std::collections::vec_deque::IntoIter<WorldCommand>;
 
-    pub fn into_iter(self) -> impl Iterator<Item = WorldCommand> {
+    fn into_iter(self) -> Self::IntoIter {
         self.commands.into_iter()
     }
 }
@@ -0,0 +1,299 @@
+use std::marker::PhantomData;
+
+use log::{info, warn};
+
+use crate::{
+    entities::{entity_manager::EntityManager, Entity, MapEntities},
+    errors::EcsErrors,
+    hierarchy::{Children, Parent},
+    world::World,
+};
+
+use super::{component_manager::ComponentManager, dynamic::ComponentId, Component};
+
+/// Takes a copy of the component with `id` off an entity.
+pub type CloneFn = fn(&ComponentManager, ComponentId, &Entity) -> Result<Box<dyn ComponentCopy>, EcsErrors>;
+
+/// Copy of one component, which can be added to any number of entities.
+pub trait ComponentCopy {
+    /// Adds another copy to `entity` and returns the bit of its component.
+    fn insert(&self, component_manager: &mut ComponentManager, entity: &Entity) -> Result<u32, EcsErrors>;
+}
+
+struct CopiedComponent<T>(T);
+
+impl<T: Component + Clone + 'static> ComponentCopy for CopiedComponent<T> {
+    fn insert(&self, component_manager: &mut ComponentManager, entity: &Entity) -> Result<u32, EcsErrors> {
+        Ok(*component_manager.add_component(entity, self.0.clone()))
+    }
+}
+
+struct CopiedDynamic {
+    id: ComponentId,
+    bytes: Vec<u8>,
+}
+
+impl ComponentCopy for CopiedDynamic {
+    fn insert(&self, component_manager: &mut ComponentManager, entity: &Entity) -> Result<u32, EcsErrors> {
+        component_manager.add_dynamic(entity, self.id, &self.bytes)?;
+        Ok(self.id.mask())
+    }
+}
+
+/// Rewrites the entity references of the component with `id` on an entity.
+pub type MapFn =
+    fn(&mut ComponentManager, ComponentId, &Entity, &mut dyn FnMut(Entity) -> Entity) -> Result<(), EcsErrors>;
+
+/// What `clone_entity` does with components that have no clone function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClonePolicy {
+    /// Leave them out of the copy.
+    #[default]
+    Skip,
+    /// Fail before anything is spawned.
+    Error,
+}
+
+pub(crate) fn clone_component<T: Component + Clone + 'static>(
+    component_manager: &ComponentManager,
+    _: ComponentId,
+    entity: &Entity,
+) -> Result<Box<dyn ComponentCopy>, EcsErrors> {
+    let component = component_manager
+        .get_components::<T>()?
+        .data
+        .get(entity.0)
+        .and_then(Option::as_ref)
+        .cloned()
+        .ok_or_else(EcsErrors::component_does_not_exist::<T>)?;
+    Ok(Box::new(CopiedComponent(component)))
+}
+
+pub(crate) fn map_component<T: Component + MapEntities + 'static>(
+    component_manager: &mut ComponentManager,
+    _: ComponentId,
+    entity: &Entity,
+    map: &mut dyn FnMut(Entity) -> Entity,
+) -> Result<(), EcsErrors> {
+    let mut pool = component_manager.get_components_mut::<T>()?;
+    if !pool.data.get(entity.0).is_some_and(Option::is_some) {
+        return Err(EcsErrors::component_does_not_exist::<T>());
+    }
+    pool.get_mut(entity.0)?.map_entities(map);
+    Ok(())
+}
+
+pub(crate) fn clone_dynamic(
+    component_manager: &ComponentManager,
+    id: ComponentId,
+    entity: &Entity,
+) -> Result<Box<dyn ComponentCopy>, EcsErrors> {
+    let bytes = component_manager
+        .get_dynamic(id)?
+        .get(entity.0)
+        .map(<[u8]>::to_vec)
+        .ok_or(EcsErrors::EntityDoesNotExist(entity.0))?;
+    Ok(Box::new(CopiedDynamic { id, bytes }))
+}
+
+/// Lets `#[derive(Component)]` pick up `Clone` without requiring it: method
+/// lookup on `&&CloneProbe<T>` finds `CloneViaClone` first when `T: Clone`
+/// and falls back to `CloneViaNothing` otherwise.
+pub struct CloneProbe<T>(PhantomData<T>);
+
+impl<T> CloneProbe<T> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+pub trait CloneViaClone {
+    fn clone_fn(&self) -> Option<CloneFn>;
+}
+
+impl<T: Component + Clone + 'static> CloneViaClone for &CloneProbe<T> {
+    fn clone_fn(&self) -> Option<CloneFn> {
+        Some(clone_component::<T>)
+    }
+}
+
+pub trait CloneViaNothing {
+    fn clone_fn(&self) -> Option<CloneFn>;
+}
+
+impl<T> CloneViaNothing for CloneProbe<T> {
+    fn clone_fn(&self) -> Option<CloneFn> {
+        None
+    }
+}
+
+/// Same lookup as `CloneProbe`, picking up `MapEntities` for
+/// `#[derive(Component)]`.
+pub struct MapProbe<T>(PhantomData<T>);
+
+impl<T> MapProbe<T> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+pub trait MapViaMapEntities {
+    fn map_fn(&self) -> Option<MapFn>;
+}
+
+impl<T: Component + MapEntities + 'static> MapViaMapEntities for &MapProbe<T> {
+    fn map_fn(&self) -> Option<MapFn> {
+        Some(map_component::<T>)
+    }
+}
+
+pub trait MapViaNothing {
+    fn map_fn(&self) -> Option<MapFn>;
+}
+
+impl<T> MapViaNothing for MapProbe<T> {
+    fn map_fn(&self) -> Option<MapFn> {
+        None
+    }
+}
+
+impl<'a> EntityManager<'a> {
+    /// Clone functions for every component of `entity` outside `skip`. With
+    /// `ClonePolicy::Error` a component without one fails the whole lookup.
+    pub(crate) fn clone_fns(
+        &self,
+        entity: &Entity,
+        policy: ClonePolicy,
+        skip: u32,
+    ) -> Result<Vec<(ComponentId, CloneFn)>, EcsErrors> {
+        // A copy can't share a name that has to stay unique.
+        let signature = *self.get_signature(entity)? & !(skip | self.unique_name_mask());
+        let mut clone_fns = vec![];
+
+        for id in (0..u32::BITS).map(ComponentId).filter(|id| signature & id.mask() != 0) {
+            match self.component_manager.clone_fn(id) {
+                Some(clone) => clone_fns.push((id, clone)),
+                None if policy == ClonePolicy::Error => {
+                    return Err(EcsErrors::NotCloneable(self.component_manager.component_name_by_id(id)));
+                }
+                None => info!(
+                    "Skipping {} when cloning {}",
+                    self.component_manager.component_name_by_id(id),
+                    self.label(entity)
+                ),
+            }
+        }
+
+        Ok(clone_fns)
+    }
+
+    /// `Parent` and `Children` bits, which copies get through `set_parent`
+    /// instead of cloning.
+    pub(crate) fn hierarchy_mask(&self) -> u32 {
+        self.component_manager.get_mask::<Parent>().copied().unwrap_or(0)
+            | self.component_manager.get_mask::<Children>().copied().unwrap_or(0)
+    }
+
+    /// Runs the clone functions found by `clone_fns` on `entity`.
+    pub(crate) fn copy_components(
+        &self,
+        clone_fns: Vec<(ComponentId, CloneFn)>,
+        entity: &Entity,
+    ) -> Result<Vec<Box<dyn ComponentCopy>>, EcsErrors> {
+        clone_fns
+            .into_iter()
+            .map(|(id, clone)| clone(&self.component_manager, id, entity))
+            .collect()
+    }
+
+    /// Adds `copies` to `entity` and returns the ids of their components.
+    pub(crate) fn insert_copies(
+        &mut self,
+        copies: &[Box<dyn ComponentCopy>],
+        entity: &Entity,
+    ) -> Result<Vec<ComponentId>, EcsErrors> {
+        let mut ids = vec![];
+        for copy in copies {
+            let mask = copy.insert(&mut self.component_manager, entity)?;
+            self.set_signature(entity, self.entity_component_signatures[entity.0] | mask);
+            ids.push(ComponentId(mask.trailing_zeros()));
+        }
+        Ok(ids)
+    }
+
+    /// Rewrites the entity references of the components `ids` of `entity`
+    /// that have a map function.
+    pub(crate) fn map_components(
+        &mut self,
+        ids: &[ComponentId],
+        entity: &Entity,
+        map: &mut dyn FnMut(Entity) -> Entity,
+    ) -> Result<(), EcsErrors> {
+        for id in ids {
+            if let Some(map_fn) = self.component_manager.map_fn(*id) {
+                map_fn(&mut self.component_manager, *id, entity, map)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> World<'a> {
+    /// Makes `T` cloneable for components that do not derive `Component` or
+    /// implement `Clone` only through a manual impl.
+    pub fn register_clone<T: Component + Clone + 'static>(&mut self) {
+        self.entity_manager_mut().component_manager.set_clone_fn::<T>(clone_component::<T>);
+        info!("Component {} registered for cloning", std::any::type_name::<T>());
+    }
+
+    /// Lets copies made from prefabs remap the entity references of `T`, for
+    /// components that implement `MapEntities` without deriving `Component`.
+    pub fn register_map_entities<T: Component + MapEntities + 'static>(&mut self) {
+        self.entity_manager_mut().component_manager.set_map_fn::<T>(map_component::<T>);
+        info!("Component {} registered for entity mapping", std::any::type_name::<T>());
+    }
+
+    /// Spawns a copy of `entity` with every cloneable component, skipping
+    /// the rest. See `clone_entity_with`.
+    pub fn clone_entity(&mut self, entity: &Entity) -> Entity {
+        self.clone_entity_with(entity, ClonePolicy::Skip).unwrap()
+    }
+
+    /// Spawns a copy of `entity`. The copy is attached to the same parent,
+    /// but children are not cloned. Like an entity from `spawn`, it joins
+    /// systems right away.
+    pub fn clone_entity_with(&mut self, entity: &Entity, policy: ClonePolicy) -> Result<Entity, EcsErrors> {
+        let hierarchy = self.entity_manager().hierarchy_mask();
+        let clone_fns = self.entity_manager().clone_fns(entity, policy, hierarchy)?;
+        let parent = self.entity_manager().parent_of(entity);
+        let copies = self.entity_manager().copy_components(clone_fns, entity)?;
+
+        let copy = self.entity_manager_mut().create_entity();
+        if let Err(err) = self.entity_manager_mut().insert_copies(&copies, &copy) {
+            self.discard_spawned(&[copy]);
+            return Err(err);
+        }
+        self.sync_systems();
+        if let Some(parent) = parent {
+            if let Err(err) = self.set_parent(&copy, &parent) {
+                self.discard_spawned(&[copy]);
+                return Err(err);
+            }
+        }
+
+        info!(
+            "Cloned {} into {}",
+            self.entity_manager().label(entity),
+            self.entity_manager().label(&copy)
+        );
+        Ok(copy)
+    }
+
+    pub(crate) fn clone_entity_command(&mut self, entity: &Entity, policy: ClonePolicy) {
+        if let Err(err) = self.clone_entity_with(entity, policy) {
+            warn!("Failed to clone {}: {err}", self.entity_manager().label(entity));
+        }
+    }
+}
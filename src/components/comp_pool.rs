@@ -1,8 +1,11 @@
-use std::{any::Any, cell::RefCell};
+use std::{
+    any::Any,
+    cell::{Cell, Ref, RefCell},
+};
 
 use crate::{entities::Entity, errors::EcsErrors};
 
-use super::Component;
+use super::{index::PoolIndex, Component};
 
 pub trait GenericCompPool {
     fn as_any(&self) -> &dyn Any;
@@ -12,10 +15,32 @@ pub trait GenericCompPool {
     fn resize(&mut self, size: usize);
     fn clear(&mut self);
     fn remove_any(&mut self, entity: &Entity);
+    fn has(&self, entity: &Entity) -> bool;
+    fn set_tick(&mut self, tick: u64);
 }
 
+/// Component storage indexed by entity id.
+///
+/// Every write through `add`, `set`, `remove`, `get_mut` or `iter_mut` stamps
+/// the slot with the current tick, which is what `changed_since` and
+/// `iter_changed` compare against. Slots going from empty to occupied are
+/// additionally stamped for `added_since`. Tick 0 means never.
+///
+/// An indexed pool updates its index right away on `add`, `set` and
+/// `remove`. Slots handed out mutably are only noted, and looked at again by
+/// `refresh_index`, which only needs shared access so lookups can run next
+/// to other readers of the pool.
 pub struct CompPool<T: Component> {
     pub data: Vec<Option<T>>,
+    changed: Vec<u64>,
+    added: Vec<u64>,
+    tick: u64,
+    index: RefCell<Option<Box<dyn PoolIndex<T>>>>,
+    /// Slots handed out by `get_mut` since the last refresh.
+    dirty: Cell<Vec<usize>>,
+    /// Set by bulk mutable access, every slot changed at or after this tick
+    /// needs a refresh.
+    dirty_since: Cell<Option<u64>>,
 }
 
 impl<T: 'static + Component> GenericCompPool for RefCell<CompPool<T>> {
@@ -36,15 +61,34 @@ impl<T: 'static + Component> GenericCompPool for RefCell<CompPool<T>> {
     }
 
     fn resize(&mut self, size: usize) {
-        self.borrow_mut().data.resize_with(size, || None)
+        let mut pool = self.borrow_mut();
+        pool.data.resize_with(size, || None);
+        pool.changed.resize(size, 0);
+        pool.added.resize(size, 0);
     }
 
     fn clear(&mut self) {
-        self.borrow_mut().data.clear();
+        let mut pool = self.borrow_mut();
+        pool.data.clear();
+        pool.changed.clear();
+        pool.added.clear();
+        pool.dirty.get_mut().clear();
+        pool.dirty_since.set(None);
+        if let Some(index) = pool.index.get_mut() {
+            index.clear();
+        }
     }
 
     fn remove_any(&mut self, entity: &Entity) {
-        self.borrow_mut().data[entity.0] = None;
+        let _ = self.borrow_mut().remove(entity.0);
+    }
+
+    fn has(&self, entity: &Entity) -> bool {
+        self.borrow().data.get(entity.0).is_some_and(|c| c.is_some())
+    }
+
+    fn set_tick(&mut self, tick: u64) {
+        self.borrow_mut().tick = tick;
     }
 }
 
@@ -53,18 +97,36 @@ impl<T: Component + 'static> CompPool<T> {
         let mut data = Vec::with_capacity(size);
         data.resize_with(size, || None);
 
-        Self { data }
+        Self {
+            data,
+            changed: vec![0; size],
+            added: vec![0; size],
+            tick: 0,
+            index: RefCell::new(None),
+            dirty: Cell::new(vec![]),
+            dirty_since: Cell::new(None),
+        }
     }
 
     pub fn add(&mut self, comp: T) {
+        if let Some(index) = self.index.get_mut() {
+            index.update(self.data.len(), Some(&comp));
+        }
         self.data.push(Some(comp));
+        self.changed.push(self.tick);
+        self.added.push(self.tick);
     }
 
     pub fn remove(&mut self, index: usize) -> Result<(), EcsErrors> {
         if self.data.get(index).is_none() {
             return Err(EcsErrors::EntityDoesNotExist(index));
         }
-        self.data[index] = None;
+        if self.data[index].take().is_some() {
+            self.changed[index] = self.tick;
+            if let Some(pool_index) = self.index.get_mut() {
+                pool_index.update(index, None);
+            }
+        }
         Ok(())
     }
 
@@ -72,7 +134,13 @@ impl<T: Component + 'static> CompPool<T> {
         if self.data.get(index).is_none() {
             return Err(EcsErrors::EntityDoesNotExist(index));
         }
-        self.data[index] = Some(comp);
+        if let Some(pool_index) = self.index.get_mut() {
+            pool_index.update(index, Some(&comp));
+        }
+        if self.data[index].replace(comp).is_none() {
+            self.added[index] = self.tick;
+        }
+        self.changed[index] = self.tick;
 
         Ok(())
     }
@@ -86,18 +154,130 @@ impl<T: Component + 'static> CompPool<T> {
     }
 
     pub fn get_mut(&mut self, index: usize) -> Result<&mut T, EcsErrors> {
-        if let Some(comp) = self.data.get_mut(index) {
-            Ok(comp.as_mut().unwrap())
-        } else {
-            Err(EcsErrors::EntityDoesNotExist(index))
+        if index >= self.data.len() {
+            return Err(EcsErrors::EntityDoesNotExist(index));
+        }
+        self.changed[index] = self.tick;
+        if self.index.get_mut().is_some() {
+            // Past one entry per slot, rescanning the change ticks is cheaper.
+            if self.dirty.get_mut().len() < self.data.len() {
+                self.dirty.get_mut().push(index);
+            } else {
+                self.mark_dirty();
+            }
+        }
+        Ok(self.data[index].as_mut().unwrap())
+    }
+
+    /// Swaps in new contents for the whole pool. Slots that hold a component
+    /// before or after are marked as changed, slots that only hold one after
+    /// as added.
+    pub fn replace(&mut self, data: Vec<Option<T>>) {
+        let len = data.len();
+        if let Some(index) = self.index.get_mut() {
+            (len..self.data.len()).for_each(|id| index.update(id, None));
+        }
+        self.dirty.get_mut().retain(|id| *id < len);
+
+        self.changed.resize(len, 0);
+        self.added.resize(len, 0);
+        for (id, comp) in data.iter().enumerate() {
+            let had = self.data.get(id).is_some_and(Option::is_some);
+            if comp.is_some() || had {
+                self.changed[id] = self.tick;
+            }
+            if comp.is_some() && !had {
+                self.added[id] = self.tick;
+            }
         }
+        self.data = data;
+        self.mark_dirty();
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &Option<T>> {
         self.data.iter()
     }
 
+    /// Marks every occupied slot as changed, as there is no telling which
+    /// ones the caller is going to write to. Empty slots are left alone, so
+    /// deltas don't report them as removed.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Option<T>> {
+        let tick = self.tick;
+        self.changed
+            .iter_mut()
+            .zip(self.data.iter())
+            .filter(|(_, comp)| comp.is_some())
+            .for_each(|(changed, _)| *changed = tick);
+        self.mark_dirty();
         self.data.iter_mut()
     }
+
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Data and change ticks split apart, for typed queries that stamp
+    /// the slots they hand out themselves.
+    pub(crate) fn slots_mut(&mut self) -> (&mut [Option<T>], &mut [u64], u64) {
+        self.mark_dirty();
+        (&mut self.data, &mut self.changed, self.tick)
+    }
+
+    fn mark_dirty(&mut self) {
+        if self.index.get_mut().is_some() {
+            let tick = self.dirty_since.get().map_or(self.tick, |since| since.min(self.tick));
+            self.dirty_since.set(Some(tick));
+        }
+    }
+
+    pub fn index(&self) -> Option<Ref<'_, dyn PoolIndex<T>>> {
+        Ref::filter_map(self.index.borrow(), |index| index.as_deref()).ok()
+    }
+
+    /// Replaces the index of the pool and fills it with the current contents.
+    pub fn set_index(&mut self, mut index: Box<dyn PoolIndex<T>>) {
+        for (id, comp) in self.data.iter().enumerate() {
+            index.update(id, comp.as_ref());
+        }
+        *self.index.get_mut() = Some(index);
+        self.dirty.get_mut().clear();
+        self.dirty_since.set(None);
+    }
+
+    /// Brings the index up to date with slots written through mutable
+    /// access since the last refresh.
+    pub fn refresh_index(&self) {
+        let mut index = self.index.borrow_mut();
+        let Some(index) = index.as_mut() else {
+            return;
+        };
+        for id in self.dirty.take() {
+            index.update(id, self.data[id].as_ref());
+        }
+        if let Some(since) = self.dirty_since.take() {
+            for (id, _) in self.changed.iter().enumerate().filter(|(_, changed)| **changed >= since) {
+                index.update(id, self.data[id].as_ref());
+            }
+        }
+    }
+
+    /// Whether the slot was written to or emptied at or after `tick`.
+    pub fn changed_since(&self, index: usize, tick: u64) -> bool {
+        self.changed.get(index).is_some_and(|changed| *changed >= tick)
+    }
+
+    /// Whether the slot went from empty to occupied at or after `tick`.
+    pub fn added_since(&self, index: usize, tick: u64) -> bool {
+        self.added.get(index).is_some_and(|added| *added >= tick)
+    }
+
+    /// Ids of the slots written to or emptied at or after `tick`, including
+    /// slots that hold no component anymore.
+    pub fn iter_changed(&self, tick: u64) -> impl Iterator<Item = usize> + '_ {
+        self.changed
+            .iter()
+            .enumerate()
+            .filter(move |(_, changed)| **changed >= tick)
+            .map(|(id, _)| id)
+    }
 }
@@ -1,43 +1,281 @@
 use std::{
-    any::TypeId,
+    alloc::Layout,
+    any::{type_name, TypeId},
     cell::{Ref, RefCell, RefMut},
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
 };
 
 use crate::{
     {entities::Entity, errors::EcsErrors},
+    relations::ReleaseFn,
 };
 
 use super::{
+    clone::{clone_dynamic, CloneFn, MapFn},
     comp_pool::{CompPool, GenericCompPool},
+    dynamic::{ComponentId, DynamicCompPool, DynamicComponentInfo},
+    require::Requirement,
     Component,
 };
 
 pub type CellComponent<T> = RefCell<CompPool<T>>;
 
 pub struct ComponentManager<'a> {
-    component_pools: HashMap<TypeId, Box<dyn GenericCompPool + 'a>>,
+    /// Pools in registration order, the pool of a component sits at the
+    /// index of its bit in `component_bit_masks`.
+    component_pools: Vec<Box<dyn GenericCompPool + 'a>>,
+    component_ids: Vec<TypeId>,
+    /// Clone functions by pool index, `None` for types that can't be cloned.
+    clone_fns: Vec<Option<CloneFn>>,
+    /// Entity mapping functions by pool index.
+    map_fns: Vec<Option<MapFn>>,
+    /// Cleanups for removed entities, see `Component::release_fn`.
+    release_fns: Vec<ReleaseFn>,
+    dynamic_components: Vec<DynamicComponentInfo>,
     pub component_bit_masks: HashMap<TypeId, u32>,
+    component_names: HashMap<TypeId, &'static str>,
+    tick: u64,
+}
+
+impl<'a> Default for ComponentManager<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> ComponentManager<'a> {
     pub fn new() -> Self {
         Self {
-            component_pools: HashMap::new(),
+            component_pools: Vec::new(),
+            component_ids: Vec::new(),
+            clone_fns: Vec::new(),
+            map_fns: Vec::new(),
+            release_fns: Vec::new(),
+            dynamic_components: Vec::new(),
             component_bit_masks: HashMap::new(),
+            component_names: HashMap::new(),
+            tick: 1,
         }
     }
 
-    pub fn add_component<T: Component + 'static>(&mut self, entity: &Entity, component: T) -> &u32 {
+    /// Current world tick. It starts at 1 so that 0 can mean "never".
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn advance_tick(&mut self) -> u64 {
+        self.tick += 1;
+        let tick = self.tick;
+        self.component_pools
+            .iter_mut()
+            .for_each(|pool| pool.set_tick(tick));
+        tick
+    }
+
+    /// Creates the pool and bit mask for `T` without adding it to any entity,
+    /// so systems can be built against it up front. Panics once every
+    /// signature bit is taken, see `register_checked`.
+    pub fn register<T: Component + 'static>(&mut self) -> &u32 {
         let comp_id = TypeId::of::<T>();
 
-        if let Entry::Vacant(e) = self.component_pools.entry(comp_id) {
-            e.insert(Box::new(RefCell::new(CompPool::<T>::new(30))));
-            let current_count = self.component_bit_masks.len();
-            self.component_bit_masks.insert(comp_id, 1 << current_count);
+        if let Entry::Vacant(e) = self.component_bit_masks.entry(comp_id) {
+            if self.component_pools.len() >= u32::BITS as usize {
+                panic!("{}", EcsErrors::TooManyComponents(type_name::<T>().to_owned()));
+            }
+            let mut pool = CompPool::<T>::new(30);
+            if let Some(index) = T::index() {
+                pool.set_index(index);
+            }
+            let mut pool: Box<dyn GenericCompPool + 'a> = Box::new(RefCell::new(pool));
+            pool.set_tick(self.tick);
+            e.insert(1 << self.component_pools.len());
+            self.component_pools.push(pool);
+            self.component_ids.push(comp_id);
+            self.clone_fns.push(T::clone_fn());
+            self.map_fns.push(T::map_fn());
+            self.release_fns.extend(T::release_fn());
+            self.component_names.insert(comp_id, type_name::<T>());
+
+            for requirement in T::requirements() {
+                (requirement.register)(self);
+            }
         }
 
-        if let Some(pool) = self.component_pools.get_mut(&comp_id) {
+        self.component_bit_masks.get(&comp_id).unwrap()
+    }
+
+    /// Same as `register`, but a type registered for the first time is
+    /// checked for cycles in its required components first, and fails if it
+    /// and its requirements don't fit into the remaining signature bits.
+    pub fn register_checked<T: Component + 'static>(&mut self) -> Result<u32, EcsErrors> {
+        if !self.component_bit_masks.contains_key(&TypeId::of::<T>()) {
+            check_requirements(TypeId::of::<T>(), type_name::<T>(), T::requirements)?;
+            let added = 1 + self.unregistered_requirements(T::requirements());
+            if self.component_pools.len() + added > u32::BITS as usize {
+                return Err(EcsErrors::TooManyComponents(type_name::<T>().to_owned()));
+            }
+        }
+        Ok(*self.register::<T>())
+    }
+
+    /// Number of distinct types among `requirements` and theirs that have
+    /// no pool yet.
+    fn unregistered_requirements(&self, mut requirements: Vec<Requirement>) -> usize {
+        let mut seen = HashSet::new();
+        while let Some(requirement) = requirements.pop() {
+            if !self.component_bit_masks.contains_key(&requirement.type_id) && seen.insert(requirement.type_id) {
+                requirements.extend((requirement.requirements)());
+            }
+        }
+        seen.len()
+    }
+
+    /// Allocates a component id and bit mask for a component type that only
+    /// exists at runtime. Registering the same name again with the same layout
+    /// returns the existing id.
+    pub fn register_dynamic(&mut self, name: &str, layout: Layout) -> Result<ComponentId, EcsErrors> {
+        if let Some(info) = self.dynamic_component(name) {
+            if info.layout != layout {
+                return Err(EcsErrors::DynamicComponentLayout(format!(
+                    "{name} is already registered with {:?}",
+                    info.layout
+                )));
+            }
+            return Ok(info.id);
+        }
+
+        if self.component_pools.len() >= u32::BITS as usize {
+            return Err(EcsErrors::TooManyComponents(name.to_owned()));
+        }
+        let id = ComponentId(self.component_pools.len() as u32);
+        let mut pool: Box<dyn GenericCompPool + 'a> = Box::new(RefCell::new(DynamicCompPool::new(layout)));
+        pool.set_tick(self.tick);
+        self.component_pools.push(pool);
+        self.clone_fns.push(Some(clone_dynamic));
+        self.map_fns.push(None);
+        self.dynamic_components.push(DynamicComponentInfo {
+            name: name.to_owned(),
+            layout,
+            id,
+        });
+
+        Ok(id)
+    }
+
+    pub fn dynamic_component(&self, name: &str) -> Option<&DynamicComponentInfo> {
+        self.dynamic_components.iter().find(|info| info.name == name)
+    }
+
+    pub fn dynamic_components(&self) -> &[DynamicComponentInfo] {
+        &self.dynamic_components
+    }
+
+    pub fn component_id<T: Component + 'static>(&self) -> Option<ComponentId> {
+        let mask = self.component_bit_masks.get(&TypeId::of::<T>())?;
+        Some(ComponentId(mask.trailing_zeros()))
+    }
+
+    pub fn clone_fn(&self, id: ComponentId) -> Option<CloneFn> {
+        self.clone_fns.get(id.0 as usize).copied().flatten()
+    }
+
+    pub fn set_clone_fn<T: Component + 'static>(&mut self, clone: CloneFn) {
+        let index = self.register::<T>().trailing_zeros() as usize;
+        self.clone_fns[index] = Some(clone);
+    }
+
+    pub(crate) fn release_fns(&self) -> Vec<ReleaseFn> {
+        self.release_fns.clone()
+    }
+
+    pub fn map_fn(&self, id: ComponentId) -> Option<MapFn> {
+        self.map_fns.get(id.0 as usize).copied().flatten()
+    }
+
+    pub fn set_map_fn<T: Component + 'static>(&mut self, map: MapFn) {
+        let index = self.register::<T>().trailing_zeros() as usize;
+        self.map_fns[index] = Some(map);
+    }
+
+    /// Type name of a static component or registered name of a dynamic one.
+    pub fn component_name_by_id(&self, id: ComponentId) -> String {
+        if let Some(info) = self.dynamic_components.iter().find(|info| info.id == id) {
+            return info.name.clone();
+        }
+        self.component_bit_masks
+            .iter()
+            .find(|(_, mask)| **mask == id.mask())
+            .map(|(comp_id, _)| self.component_name(comp_id).to_owned())
+            .unwrap_or_else(|| format!("component {}", id.0))
+    }
+
+    pub fn add_dynamic(&mut self, entity: &Entity, id: ComponentId, bytes: &[u8]) -> Result<(), EcsErrors> {
+        let pool = self.component_pools.get_mut(id.0 as usize).ok_or_else(|| unknown_id(id))?;
+        if pool.get_size() <= entity.0 {
+            pool.resize(entity.0 + 1);
+        }
+        pool.as_any()
+            .downcast_ref::<RefCell<DynamicCompPool>>()
+            .ok_or_else(|| unknown_id(id))?
+            .borrow_mut()
+            .set(entity.0, bytes)
+    }
+
+    /// Removes the component with `id` from `entity`, static or dynamic.
+    pub fn remove_with_component_id(&mut self, entity: &Entity, id: ComponentId) -> Result<(), EcsErrors> {
+        let pool = self.component_pools.get_mut(id.0 as usize).ok_or_else(|| unknown_id(id))?;
+        pool.remove_any(entity);
+        Ok(())
+    }
+
+    pub(crate) fn pool_by_id(&self, id: ComponentId) -> Option<&(dyn GenericCompPool + 'a)> {
+        self.component_pools.get(id.0 as usize).map(|pool| pool.as_ref())
+    }
+
+    pub fn get_dynamic(&self, id: ComponentId) -> Result<Ref<'_, DynamicCompPool>, EcsErrors> {
+        self.component_pools
+            .get(id.0 as usize)
+            .and_then(|pool| pool.as_any().downcast_ref::<RefCell<DynamicCompPool>>())
+            .map(|pool| pool.borrow())
+            .ok_or_else(|| unknown_id(id))
+    }
+
+    pub fn get_dynamic_mut(&self, id: ComponentId) -> Result<RefMut<'_, DynamicCompPool>, EcsErrors> {
+        self.component_pools
+            .get(id.0 as usize)
+            .and_then(|pool| pool.as_any().downcast_ref::<RefCell<DynamicCompPool>>())
+            .map(|pool| pool.borrow_mut())
+            .ok_or_else(|| unknown_id(id))
+    }
+
+    /// Registered Rust component types in registration order. Dynamic
+    /// components are listed by `dynamic_components`.
+    pub fn component_ids(&self) -> &[TypeId] {
+        &self.component_ids
+    }
+
+    pub(crate) fn pool(&self, comp_id: &TypeId) -> Option<&(dyn GenericCompPool + 'a)> {
+        let mask = self.component_bit_masks.get(comp_id)?;
+        self.component_pools
+            .get(mask.trailing_zeros() as usize)
+            .map(|pool| pool.as_ref())
+    }
+
+    pub fn component_name(&self, comp_id: &TypeId) -> &'static str {
+        self.component_names.get(comp_id).copied().unwrap_or("Unknown")
+    }
+
+    pub fn get_pool_mut(&mut self, comp_id: &TypeId) -> Option<&mut Box<dyn GenericCompPool + 'a>> {
+        let mask = self.component_bit_masks.get(comp_id)?;
+        self.component_pools.get_mut(mask.trailing_zeros() as usize)
+    }
+
+    pub fn add_component<T: Component + 'static>(&mut self, entity: &Entity, component: T) -> &u32 {
+        let comp_id = TypeId::of::<T>();
+
+        self.register::<T>();
+
+        if let Some(pool) = self.get_pool_mut(&comp_id) {
             if pool.get_size() <= entity.0 {
                 pool.resize(entity.0 + 1);
             }
@@ -54,7 +292,7 @@ impl<'a> ComponentManager<'a> {
 
     pub fn remove<T: Component + 'static>(&mut self, entity: &Entity) -> Result<(), EcsErrors> {
         let comp_id = TypeId::of::<T>();
-        if let Some(pool) = self.component_pools.get(&comp_id) {
+        if let Some(pool) = self.pool(&comp_id) {
             pool.as_any()
                 .downcast_ref::<CellComponent<T>>()
                 .unwrap()
@@ -69,7 +307,7 @@ impl<'a> ComponentManager<'a> {
     }
 
     pub fn remove_with_id(&mut self, entity: &Entity, comp_id: &TypeId) -> Result<(), EcsErrors> {
-        if let Some(pool) = self.component_pools.get_mut(&comp_id) {
+        if let Some(pool) = self.get_pool_mut(comp_id) {
             pool.remove_any(entity);
 
             Ok(())
@@ -80,7 +318,7 @@ impl<'a> ComponentManager<'a> {
 
     pub fn remove_all(&mut self, entity: &Entity) {
         self.component_pools
-            .values_mut()
+            .iter_mut()
             .for_each(|pool| pool.remove_any(entity))
     }
 
@@ -88,7 +326,7 @@ impl<'a> ComponentManager<'a> {
         &self,
     ) -> Result<Ref<'_, CompPool<T>>, EcsErrors> {
         let comp_id = TypeId::of::<T>();
-        if let Some(pool) = self.component_pools.get(&comp_id) {
+        if let Some(pool) = self.pool(&comp_id) {
             Ok(pool
                 .as_any()
                 .downcast_ref::<CellComponent<T>>()
@@ -103,7 +341,7 @@ impl<'a> ComponentManager<'a> {
         &self,
     ) -> Result<RefMut<'_, CompPool<T>>, EcsErrors> {
         let comp_id = TypeId::of::<T>();
-        if let Some(pool) = self.component_pools.get(&comp_id) {
+        if let Some(pool) = self.pool(&comp_id) {
             Ok(pool
                 .as_any()
                 .downcast_ref::<CellComponent<T>>()
@@ -132,3 +370,38 @@ impl<'a> ComponentManager<'a> {
     }
 
 }
+
+fn unknown_id(id: ComponentId) -> EcsErrors {
+    EcsErrors::ComponentDoesNotExist(format!("dynamic component {}", id.0))
+}
+
+/// Walks the required components of a type depth first and fails on the
+/// first one that leads back to a type already on the path.
+fn check_requirements(
+    type_id: TypeId,
+    name: &'static str,
+    requirements: fn() -> Vec<Requirement>,
+) -> Result<(), EcsErrors> {
+    fn visit(
+        path: &mut Vec<(TypeId, &'static str)>,
+        requirements: fn() -> Vec<Requirement>,
+    ) -> Result<(), EcsErrors> {
+        for requirement in requirements() {
+            if let Some(start) = path.iter().position(|(id, _)| *id == requirement.type_id) {
+                let cycle: Vec<&str> = path[start..]
+                    .iter()
+                    .map(|(_, name)| *name)
+                    .chain(std::iter::once(requirement.name))
+                    .collect();
+                return Err(EcsErrors::RequirementCycle(cycle.join(" -> ")));
+            }
+
+            path.push((requirement.type_id, requirement.name));
+            visit(path, requirement.requirements)?;
+            path.pop();
+        }
+        Ok(())
+    }
+
+    visit(&mut vec![(type_id, name)], requirements)
+}
@@ -0,0 +1,200 @@
+use std::{alloc::Layout, any::Any, cell::RefCell};
+
+use crate::{entities::Entity, errors::EcsErrors};
+
+use super::comp_pool::GenericCompPool;
+
+/// Index of a registered component, static or dynamic. Its bit in entity
+/// signatures is `1 << id`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct ComponentId(pub u32);
+
+impl ComponentId {
+    pub fn mask(&self) -> u32 {
+        1 << self.0
+    }
+}
+
+/// A component type defined at runtime, stored as raw bytes of `layout.size()`.
+#[derive(Debug, Clone)]
+pub struct DynamicComponentInfo {
+    pub name: String,
+    pub layout: Layout,
+    pub id: ComponentId,
+}
+
+/// Raw byte storage for one dynamic component type, indexed by entity id.
+///
+/// Values are copied in and out as bytes, so the pool never reads them with
+/// their alignment. Change ticks behave as in `CompPool`.
+pub struct DynamicCompPool {
+    size: usize,
+    data: Vec<u8>,
+    present: Vec<bool>,
+    changed: Vec<u64>,
+    added: Vec<u64>,
+    tick: u64,
+}
+
+impl GenericCompPool for RefCell<DynamicCompPool> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn is_empty(&self) -> bool {
+        self.borrow().present.is_empty()
+    }
+
+    fn get_size(&self) -> usize {
+        self.borrow().present.len()
+    }
+
+    fn resize(&mut self, size: usize) {
+        let mut pool = self.borrow_mut();
+        let bytes = size * pool.size;
+        pool.data.resize(bytes, 0);
+        pool.present.resize(size, false);
+        pool.changed.resize(size, 0);
+        pool.added.resize(size, 0);
+    }
+
+    fn clear(&mut self) {
+        let mut pool = self.borrow_mut();
+        pool.data.clear();
+        pool.present.clear();
+        pool.changed.clear();
+        pool.added.clear();
+    }
+
+    fn remove_any(&mut self, entity: &Entity) {
+        self.borrow_mut().remove(entity.0);
+    }
+
+    fn has(&self, entity: &Entity) -> bool {
+        self.borrow().present.get(entity.0).copied().unwrap_or(false)
+    }
+
+    fn set_tick(&mut self, tick: u64) {
+        self.borrow_mut().tick = tick;
+    }
+}
+
+impl DynamicCompPool {
+    pub fn new(layout: Layout) -> Self {
+        Self {
+            size: layout.size(),
+            data: vec![],
+            present: vec![],
+            changed: vec![],
+            added: vec![],
+            tick: 0,
+        }
+    }
+
+    /// Size in bytes of one component.
+    pub fn component_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        if !self.present.get(index).copied().unwrap_or(false) {
+            return None;
+        }
+        Some(&self.data[index * self.size..(index + 1) * self.size])
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut [u8]> {
+        if !self.present.get(index).copied().unwrap_or(false) {
+            return None;
+        }
+        self.changed[index] = self.tick;
+        Some(&mut self.data[index * self.size..(index + 1) * self.size])
+    }
+
+    fn check_size(&self, bytes: &[u8]) -> Result<(), EcsErrors> {
+        if bytes.len() != self.size {
+            return Err(EcsErrors::DynamicComponentLayout(format!(
+                "expected {} bytes, got {}",
+                self.size,
+                bytes.len()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, index: usize, bytes: &[u8]) -> Result<(), EcsErrors> {
+        self.check_size(bytes)?;
+        if self.present.get(index).is_none() {
+            return Err(EcsErrors::EntityDoesNotExist(index));
+        }
+
+        self.data[index * self.size..(index + 1) * self.size].copy_from_slice(bytes);
+        if !self.present[index] {
+            self.present[index] = true;
+            self.added[index] = self.tick;
+        }
+        self.changed[index] = self.tick;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if self.present.get(index).copied().unwrap_or(false) {
+            self.present[index] = false;
+            self.changed[index] = self.tick;
+        }
+    }
+
+    pub fn changed_since(&self, index: usize, tick: u64) -> bool {
+        self.changed.get(index).is_some_and(|changed| *changed >= tick)
+    }
+
+    pub fn added_since(&self, index: usize, tick: u64) -> bool {
+        self.added.get(index).is_some_and(|added| *added >= tick)
+    }
+
+    /// Bytes of every slot, `None` for empty ones.
+    pub fn values(&self) -> Vec<Option<Vec<u8>>> {
+        (0..self.present.len()).map(|id| self.get(id).map(<[u8]>::to_vec)).collect()
+    }
+
+    /// Swaps in new contents for the whole pool. Slots that hold a component
+    /// before or after are marked as changed, slots that only hold one after
+    /// as added. Fails without touching the pool unless every value is
+    /// `component_size` bytes long.
+    pub fn replace(&mut self, values: &[Option<Vec<u8>>]) -> Result<(), EcsErrors> {
+        values.iter().flatten().try_for_each(|bytes| self.check_size(bytes))?;
+
+        let len = values.len();
+        self.data.resize(len * self.size, 0);
+        self.present.resize(len, false);
+        self.changed.resize(len, 0);
+        self.added.resize(len, 0);
+        for (id, value) in values.iter().enumerate() {
+            let had = self.present[id];
+            if value.is_some() || had {
+                self.changed[id] = self.tick;
+            }
+            if value.is_some() && !had {
+                self.added[id] = self.tick;
+            }
+            if let Some(bytes) = value {
+                self.data[id * self.size..(id + 1) * self.size].copy_from_slice(bytes);
+            }
+            self.present[id] = value.is_some();
+        }
+        Ok(())
+    }
+
+    /// Entity ids and bytes of every stored component.
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &[u8])> {
+        self.present
+            .iter()
+            .enumerate()
+            .filter(|(_, present)| **present)
+            .map(|(id, _)| (Entity(id), &self.data[id * self.size..(id + 1) * self.size]))
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    any::{type_name, Any},
+    borrow::Borrow,
+    cell::Ref,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    hash::Hash,
+    ops::RangeBounds,
+};
+
+use log::info;
+
+use crate::{entities::Entity, errors::EcsErrors, query::Query, world::World};
+
+use super::{comp_pool::CompPool, component_manager::ComponentManager, Component};
+
+/// Secondary index of a component pool, mapping component values back to
+/// the entities holding them.
+///
+/// The pool calls `update` whenever a slot is set or removed. Writes through
+/// `get_mut` and other mutable access are only recorded, and applied the
+/// next time the index is queried.
+pub trait PoolIndex<T> {
+    /// Moves `entity` from its previous value to `value`.
+    fn update(&mut self, entity: usize, value: Option<&T>);
+    fn clear(&mut self);
+    fn as_any(&self) -> &dyn Any;
+}
+
+fn set_value<T: Clone>(values: &mut Vec<Option<T>>, entity: usize, value: Option<&T>) -> Option<T> {
+    if values.len() <= entity {
+        values.resize_with(entity + 1, || None);
+    }
+    std::mem::replace(&mut values[entity], value.cloned())
+}
+
+/// Equality lookups for `Hash + Eq` components.
+pub struct HashIndex<T> {
+    /// Indexed value of every entity, to find its old entry on change.
+    values: Vec<Option<T>>,
+    entities: HashMap<T, BTreeSet<usize>>,
+}
+
+impl<T> Default for HashIndex<T> {
+    fn default() -> Self {
+        Self {
+            values: vec![],
+            entities: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone + 'static> PoolIndex<T> for HashIndex<T> {
+    fn update(&mut self, entity: usize, value: Option<&T>) {
+        if self.values.get(entity).and_then(|v| v.as_ref()) == value {
+            return;
+        }
+        if let Some(old) = set_value(&mut self.values, entity, value) {
+            let entry = self.entities.get_mut(&old).unwrap();
+            entry.remove(&entity);
+            if entry.is_empty() {
+                self.entities.remove(&old);
+            }
+        }
+        if let Some(value) = value {
+            self.entities.entry(value.clone()).or_default().insert(entity);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.entities.clear();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Range lookups for `Ord` components.
+pub struct OrdIndex<T> {
+    values: Vec<Option<T>>,
+    entities: BTreeMap<T, BTreeSet<usize>>,
+}
+
+impl<T> Default for OrdIndex<T> {
+    fn default() -> Self {
+        Self {
+            values: vec![],
+            entities: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone + 'static> PoolIndex<T> for OrdIndex<T> {
+    fn update(&mut self, entity: usize, value: Option<&T>) {
+        if self.values.get(entity).and_then(|v| v.as_ref()) == value {
+            return;
+        }
+        if let Some(old) = set_value(&mut self.values, entity, value) {
+            let entry = self.entities.get_mut(&old).unwrap();
+            entry.remove(&entity);
+            if entry.is_empty() {
+                self.entities.remove(&old);
+            }
+        }
+        if let Some(value) = value {
+            self.entities.entry(value.clone()).or_default().insert(entity);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+        self.entities.clear();
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Lookup handle returned by `Query::index`. The pool stays borrowed while
+/// it exists, so it can't be written to but may be read alongside.
+pub struct ComponentIndex<'a, T: Component + 'static> {
+    pool: Ref<'a, CompPool<T>>,
+}
+
+impl<'a, T: Component + 'static> ComponentIndex<'a, T> {
+    fn index<I: 'static>(&self) -> Option<Ref<'_, I>> {
+        Ref::filter_map(self.pool.index()?, |index| index.as_any().downcast_ref::<I>()).ok()
+    }
+
+    /// Entities whose component equals `key`, in id order. Needs a hash
+    /// index, ordered ones answer the same through `range(key..=key)`.
+    pub fn get<Q>(&self, key: &Q) -> Vec<Entity>
+    where
+        T: Borrow<Q> + Hash + Eq + Clone,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.hashed()
+            .entities
+            .get(key)
+            .map(|ids| ids.iter().copied().map(Entity).collect())
+            .unwrap_or_default()
+    }
+
+    /// First entity, by id, whose component equals `key`. Needs a hash
+    /// index like `get`.
+    pub fn first<Q>(&self, key: &Q) -> Option<Entity>
+    where
+        T: Borrow<Q> + Hash + Eq + Clone,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.hashed().entities.get(key)?.first().copied().map(Entity)
+    }
+
+    /// Entities whose component lies in `range`, ordered by value and then
+    /// by id. Needs an ordered index.
+    pub fn range<Q, R>(&self, range: R) -> Vec<Entity>
+    where
+        T: Borrow<Q> + Ord + Clone,
+        Q: Ord + ?Sized,
+        R: RangeBounds<Q>,
+    {
+        self.ordered()
+            .entities
+            .range::<Q, R>(range)
+            .flat_map(|(_, ids)| ids.iter().copied().map(Entity))
+            .collect()
+    }
+
+    fn hashed(&self) -> Ref<'_, HashIndex<T>>
+    where
+        T: Hash + Eq + Clone,
+    {
+        self.index::<HashIndex<T>>()
+            .unwrap_or_else(|| panic!("{} has no hash index", type_name::<T>()))
+    }
+
+    fn ordered(&self) -> Ref<'_, OrdIndex<T>>
+    where
+        T: Ord + Clone,
+    {
+        self.index::<OrdIndex<T>>()
+            .unwrap_or_else(|| panic!("{} has no ordered index", type_name::<T>()))
+    }
+}
+
+impl<'a> Query<'a> {
+    /// Looks up entities by component value. `T` needs an index registered
+    /// with `World::register_index` or `World::register_ordered_index`.
+    pub fn index<T: Component + 'static>(&self) -> ComponentIndex<'a, T> {
+        self.try_index::<T>().unwrap()
+    }
+
+    pub fn try_index<T: Component + 'static>(&self) -> Result<ComponentIndex<'a, T>, EcsErrors> {
+        self.component_manager.index::<T>()
+    }
+}
+
+impl<'a> ComponentManager<'a> {
+    pub(crate) fn index<T: Component + 'static>(&self) -> Result<ComponentIndex<'_, T>, EcsErrors> {
+        let pool = self.get_components::<T>()?;
+        if pool.index().is_none() {
+            return Err(EcsErrors::ComponentNotIndexed(type_name::<T>().to_owned()));
+        }
+        pool.refresh_index();
+        Ok(ComponentIndex { pool })
+    }
+}
+
+impl<'a> World<'a> {
+    /// Indexes `T` by value for `query.index::<T>().get(..)`.
+    pub fn register_index<T: Component + Hash + Eq + Clone + 'static>(&mut self) {
+        self.set_index::<T>(Box::<HashIndex<T>>::default());
+    }
+
+    /// Indexes `T` by value, with range lookups through
+    /// `query.index::<T>().range(..)`.
+    pub fn register_ordered_index<T: Component + Ord + Clone + 'static>(&mut self) {
+        self.set_index::<T>(Box::<OrdIndex<T>>::default());
+    }
+
+    fn set_index<T: Component + 'static>(&mut self, index: Box<dyn PoolIndex<T>>) {
+        let component_manager = &mut self.entity_manager_mut().component_manager;
+        component_manager.register::<T>();
+        component_manager.get_components_mut::<T>().unwrap().set_index(index);
+        info!("Component {} indexed", type_name::<T>());
+    }
+}
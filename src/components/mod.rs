@@ -1,4 +1,60 @@
+pub mod clone;
 pub mod comp_pool;
 pub mod component_manager;
+pub mod dynamic;
+pub mod index;
+pub mod require;
 
-pub trait Component {}
+use clone::{CloneFn, MapFn};
+use index::PoolIndex;
+
+use crate::relations::ReleaseFn;
+use require::Requirement;
+
+pub trait Component {
+    /// Components inserted with their default value when this one is added
+    /// to an entity that does not have them yet.
+    fn requirements() -> Vec<Requirement>
+    where
+        Self: Sized,
+    {
+        Vec::new()
+    }
+
+    /// Copies the component between entities for `World::clone_entity`.
+    /// `#[derive(Component)]` fills it in for `Clone` types.
+    fn clone_fn() -> Option<CloneFn>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Rewrites the entity references of the component when entities are
+    /// copied from prefabs. `#[derive(Component)]` fills it in for
+    /// `MapEntities` types.
+    fn map_fn() -> Option<MapFn>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Runs for every entity about to be removed, for components that have
+    /// to let go of references to it.
+    fn release_fn() -> Option<ReleaseFn>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Index the pool of this component is created with, as if registered
+    /// with `World::register_index`.
+    fn index() -> Option<Box<dyn PoolIndex<Self>>>
+    where
+        Self: Sized,
+    {
+        None
+    }
+}
@@ -0,0 +1,36 @@
+use std::any::{type_name, TypeId};
+
+use crate::entities::Entity;
+
+use super::{component_manager::ComponentManager, Component};
+
+/// A component that has to be present whenever another one is, declared
+/// with `#[require(..)]` on `#[derive(Component)]`.
+#[derive(Clone, Copy)]
+pub struct Requirement {
+    pub type_id: TypeId,
+    pub name: &'static str,
+    pub(crate) register: fn(&mut ComponentManager) -> u32,
+    pub(crate) insert_default: fn(&mut ComponentManager, &Entity),
+    pub(crate) requirements: fn() -> Vec<Requirement>,
+}
+
+fn register<T: Component + 'static>(component_manager: &mut ComponentManager) -> u32 {
+    *component_manager.register::<T>()
+}
+
+fn insert_default<T: Component + Default + 'static>(component_manager: &mut ComponentManager, entity: &Entity) {
+    component_manager.add_component(entity, T::default());
+}
+
+impl Requirement {
+    pub fn of<T: Component + Default + 'static>() -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            name: type_name::<T>(),
+            register: register::<T>,
+            insert_default: insert_default::<T>,
+            requirements: T::requirements,
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use std::{
+    any::{Any, TypeId},
+    collections::{BTreeMap, HashSet, VecDeque},
+};
+
+use log::info;
+
+use crate::{
+    binary::{validate_hierarchy, BinaryReader, BinaryRegistry, BinaryWriter, HierarchyLinks, FORMAT_VERSION},
+    entities::{entity_manager::EntityManager, Entity},
+    errors::EcsErrors,
+    hierarchy::{Children, Parent},
+    name::{Name, NameMode},
+    world::World,
+};
+
+const DELTA_MAGIC: &[u8; 4] = b"SECD";
+
+/// Encoded value of a binary registered component on one entity.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ComponentDelta {
+    pub entity: Entity,
+    pub component: String,
+    pub payload: Vec<u8>,
+}
+
+/// Everything that changed in a world since a given tick, limited to
+/// components registered for binary encoding.
+///
+/// Applying it replays despawns first, then spawns, then component removals,
+/// additions and changes.
+#[derive(Debug, PartialEq, Clone)]
+pub struct WorldDelta {
+    pub since: u64,
+    pub tick: u64,
+    next_id: usize,
+    freed_ids: VecDeque<usize>,
+    pub spawned: Vec<Entity>,
+    pub despawned: Vec<Entity>,
+    pub added: Vec<ComponentDelta>,
+    pub changed: Vec<ComponentDelta>,
+    pub removed: Vec<(Entity, String)>,
+}
+
+impl WorldDelta {
+    pub fn is_empty(&self) -> bool {
+        self.spawned.is_empty()
+            && self.despawned.is_empty()
+            && self.added.is_empty()
+            && self.changed.is_empty()
+            && self.removed.is_empty()
+    }
+}
+
+impl BinaryRegistry {
+    pub fn diff(&self, entity_manager: &EntityManager, since: u64) -> WorldDelta {
+        let (next_id, freed_ids) = entity_manager.id_generator().state();
+
+        let mut delta = WorldDelta {
+            since,
+            tick: entity_manager.component_manager.tick(),
+            next_id,
+            freed_ids: freed_ids.clone(),
+            spawned: vec![],
+            despawned: vec![],
+            added: vec![],
+            changed: vec![],
+            removed: vec![],
+        };
+
+        let alive = entity_manager.alive_entities();
+        for entity in (0..next_id).map(Entity) {
+            if entity_manager.despawned_since(&entity, since) {
+                delta.despawned.push(entity);
+            }
+        }
+        for entity in alive.iter() {
+            if entity_manager.spawned_since(entity, since) {
+                delta.spawned.push(*entity);
+            }
+        }
+
+        for (name, binary) in self.components.iter() {
+            for entity in (binary.changed)(entity_manager, since).into_iter().map(Entity) {
+                // Removed entities are covered by `despawned` already.
+                if alive.binary_search(&entity).is_err() {
+                    continue;
+                }
+                let respawned = entity_manager.spawned_since(&entity, since);
+
+                match (binary.encode)(entity_manager, &entity) {
+                    Some(payload) => {
+                        let component = ComponentDelta {
+                            entity,
+                            component: name.clone(),
+                            payload,
+                        };
+                        if respawned || (binary.added_since)(entity_manager, &entity, since) {
+                            delta.added.push(component);
+                        } else {
+                            delta.changed.push(component);
+                        }
+                    }
+                    None if !respawned => delta.removed.push((entity, name.clone())),
+                    None => {}
+                }
+            }
+        }
+
+        delta
+    }
+
+    /// Applies `delta` to `entity_manager`. Every payload is decoded and
+    /// every entity, name and hierarchy link checked up front, so a delta
+    /// that does not fit the world leaves the entities untouched.
+    pub fn apply_delta(
+        &self,
+        delta: &WorldDelta,
+        entity_manager: &mut EntityManager,
+    ) -> Result<(), EcsErrors> {
+        let find = |name: &String| {
+            self.components
+                .get(name)
+                .ok_or_else(|| EcsErrors::UnknownSceneType(name.clone()))
+        };
+
+        let mut inserts: Vec<(Entity, &String, Box<dyn Any>)> = vec![];
+        for component in delta.added.iter().chain(delta.changed.iter()) {
+            let binary = find(&component.component)?;
+            inserts.push((
+                component.entity,
+                &component.component,
+                (binary.decode)(&component.payload)?,
+            ));
+        }
+        for (_, name) in delta.removed.iter() {
+            find(name)?;
+        }
+
+        self.check_delta_entities(delta, entity_manager)?;
+        self.check_delta_names(delta, &inserts, entity_manager)?;
+        self.check_delta_hierarchy(delta, &inserts, entity_manager)?;
+        for (_, name, _) in inserts.iter() {
+            (self.components[*name].register)(&mut entity_manager.component_manager)?;
+        }
+
+        for entity in delta.despawned.iter() {
+            if entity_manager.is_alive(entity) {
+                entity_manager.remove_entity(entity);
+            }
+        }
+
+        entity_manager.restore_ids(delta.next_id, delta.freed_ids.clone());
+        for entity in delta.spawned.iter() {
+            entity_manager.stamp_spawned(entity.0);
+        }
+
+        // Entities and names were checked above, so nothing below can fail.
+        // Names are only unique once every insert is done, as two entities
+        // may swap theirs.
+        let name_mode = std::mem::take(&mut entity_manager.name_mode);
+        let apply = || {
+            for (entity, name) in delta.removed.iter() {
+                (self.components[name].remove)(entity_manager, entity)?;
+            }
+            for (entity, name, component) in inserts {
+                (self.components[name].insert)(entity_manager, &entity, component)?;
+            }
+            Ok(())
+        };
+        let result = apply();
+        entity_manager.name_mode = name_mode;
+
+        result
+    }
+
+    /// Checks that the delta continues from the state of `entity_manager`:
+    /// ids only grow by entities the delta spawned or despawned, and every
+    /// entity alive afterwards, or written to, is alive in the delta's id
+    /// state.
+    fn check_delta_entities(&self, delta: &WorldDelta, entity_manager: &EntityManager) -> Result<(), EcsErrors> {
+        let invalid = |id: usize| Err(EcsErrors::BinaryFormat(format!("invalid entity id {id}")));
+
+        let (next_id, _) = entity_manager.id_generator().state();
+        if delta.next_id > next_id + delta.spawned.len() + delta.despawned.len() {
+            return Err(EcsErrors::BinaryFormat(format!("invalid entity count {}", delta.next_id)));
+        }
+
+        let freed: HashSet<usize> = delta.freed_ids.iter().copied().collect();
+        let alive_after = |id: usize| id < delta.next_id && !freed.contains(&id);
+        let despawned: HashSet<Entity> = delta.despawned.iter().copied().collect();
+
+        for entity in entity_manager.alive_entities() {
+            if !despawned.contains(&entity) && !alive_after(entity.0) {
+                return invalid(entity.0);
+            }
+        }
+        for entity in delta.spawned.iter() {
+            if entity_manager.is_alive(entity) && !despawned.contains(entity) {
+                return invalid(entity.0);
+            }
+        }
+        let written = delta
+            .added
+            .iter()
+            .chain(delta.changed.iter())
+            .map(|c| c.entity)
+            .chain(delta.removed.iter().map(|(e, _)| *e));
+        for entity in written {
+            if !alive_after(entity.0) {
+                return invalid(entity.0);
+            }
+        }
+        Ok(())
+    }
+
+    /// In `NameMode::Unique`, checks that the names after applying the delta
+    /// are still unique.
+    fn check_delta_names(
+        &self,
+        delta: &WorldDelta,
+        inserts: &[(Entity, &String, Box<dyn Any>)],
+        entity_manager: &EntityManager,
+    ) -> Result<(), EcsErrors> {
+        if entity_manager.name_mode != NameMode::Unique {
+            return Ok(());
+        }
+
+        let mut names: BTreeMap<Entity, String> = entity_manager
+            .component_manager
+            .get_components::<Name>()
+            .map(|pool| {
+                pool.data
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(id, name)| Some((Entity(id), name.as_ref()?.0.clone())))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        for entity in delta.despawned.iter() {
+            names.remove(entity);
+        }
+        for (entity, name) in delta.removed.iter() {
+            if self.components[name].type_id == TypeId::of::<Name>() {
+                names.remove(entity);
+            }
+        }
+        for (entity, _, component) in inserts {
+            if let Some(name) = component.downcast_ref::<Name>() {
+                names.insert(*entity, name.0.clone());
+            }
+        }
+
+        let mut unique = HashSet::new();
+        match names.into_values().find(|name| !unique.insert(name.clone())) {
+            Some(name) => Err(EcsErrors::DuplicateName(name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Builds the parents and children the world has after applying the
+    /// delta and validates them like `decode_binary` does. Despawns detach
+    /// their entities first, the same way `remove_entity` does.
+    fn check_delta_hierarchy(
+        &self,
+        delta: &WorldDelta,
+        inserts: &[(Entity, &String, Box<dyn Any>)],
+        entity_manager: &EntityManager,
+    ) -> Result<(), EcsErrors> {
+        let despawned: HashSet<Entity> = delta.despawned.iter().copied().collect();
+        let kept = |entity: &Entity| !despawned.contains(entity);
+
+        let mut links = HierarchyLinks::new(delta.next_id);
+        links.alive.iter_mut().for_each(|alive| *alive = true);
+        delta.freed_ids.iter().for_each(|id| links.alive[*id] = false);
+
+        // Survivors are alive in the delta's id state, `check_delta_entities`
+        // made sure of that.
+        for entity in entity_manager.alive_entities().into_iter().filter(kept) {
+            links.parents[entity.0] = entity_manager.parent_of(&entity).filter(kept);
+            let children: Vec<Entity> = entity_manager.children_of(&entity).into_iter().filter(kept).collect();
+            if !children.is_empty() {
+                links.children.insert(entity, children);
+            }
+        }
+        for (entity, name) in delta.removed.iter() {
+            let type_id = self.components[name].type_id;
+            if type_id == TypeId::of::<Parent>() {
+                links.parents[entity.0] = None;
+            } else if type_id == TypeId::of::<Children>() {
+                links.children.remove(entity);
+            }
+        }
+        for (entity, _, component) in inserts {
+            links.record(entity, component.as_ref());
+        }
+
+        validate_hierarchy(&links)
+    }
+
+    pub fn encode_delta(&self, delta: &WorldDelta) -> Vec<u8> {
+        let index_of = |name: &String| self.components.keys().position(|n| n == name).unwrap();
+
+        let mut writer = BinaryWriter::new();
+        writer.write_raw(DELTA_MAGIC);
+        writer.write_raw(&FORMAT_VERSION.to_le_bytes());
+        writer.write_raw(&self.schema_hash().to_le_bytes());
+
+        writer.write_varint(delta.since);
+        writer.write_varint(delta.tick);
+        writer.write_usize(delta.next_id);
+        writer.write_usize(delta.freed_ids.len());
+        delta.freed_ids.iter().for_each(|id| writer.write_usize(*id));
+
+        for entities in [&delta.spawned, &delta.despawned] {
+            writer.write_usize(entities.len());
+            entities.iter().for_each(|entity| writer.write_entity(entity));
+        }
+        for components in [&delta.added, &delta.changed] {
+            writer.write_usize(components.len());
+            for component in components.iter() {
+                writer.write_entity(&component.entity);
+                writer.write_usize(index_of(&component.component));
+                writer.write_bytes(&component.payload);
+            }
+        }
+        writer.write_usize(delta.removed.len());
+        for (entity, name) in delta.removed.iter() {
+            writer.write_entity(entity);
+            writer.write_usize(index_of(name));
+        }
+
+        writer.into_bytes()
+    }
+
+    pub fn decode_delta(&self, bytes: &[u8]) -> Result<WorldDelta, EcsErrors> {
+        let mut reader = BinaryReader::new(bytes);
+
+        if reader.take(DELTA_MAGIC.len())? != DELTA_MAGIC {
+            return Err(EcsErrors::BinaryFormat("missing delta header".to_owned()));
+        }
+        let version = u16::from_le_bytes(reader.take(2)?.try_into().unwrap());
+        if version != FORMAT_VERSION {
+            return Err(EcsErrors::UnsupportedFormatVersion(version));
+        }
+        let schema_hash = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        if schema_hash != self.schema_hash() {
+            return Err(EcsErrors::SchemaMismatch {
+                expected: self.schema_hash(),
+                found: schema_hash,
+            });
+        }
+
+        let names: Vec<&String> = self.components.keys().collect();
+        let read_name = |reader: &mut BinaryReader| {
+            let index = reader.read_usize()?;
+            names
+                .get(index)
+                .map(|name| (*name).clone())
+                .ok_or_else(|| EcsErrors::BinaryFormat(format!("unknown component index {index}")))
+        };
+        let read_entities = |reader: &mut BinaryReader| {
+            let len = reader.read_len()?;
+            (0..len).map(|_| reader.read_entity()).collect::<Result<Vec<_>, _>>()
+        };
+        let read_components = |reader: &mut BinaryReader| {
+            let len = reader.read_len()?;
+            (0..len)
+                .map(|_| {
+                    Ok(ComponentDelta {
+                        entity: reader.read_entity()?,
+                        component: read_name(reader)?,
+                        payload: reader.read_bytes()?.to_vec(),
+                    })
+                })
+                .collect::<Result<Vec<_>, EcsErrors>>()
+        };
+
+        let since = reader.read_varint()?;
+        let tick = reader.read_varint()?;
+        let next_id = reader.read_usize()?;
+        let freed_ids: VecDeque<usize> = read_entities(&mut reader)?.into_iter().map(|e| e.0).collect();
+        let spawned = read_entities(&mut reader)?;
+        let despawned = read_entities(&mut reader)?;
+        let added = read_components(&mut reader)?;
+        let changed = read_components(&mut reader)?;
+        let removed_len = reader.read_len()?;
+        let removed = (0..removed_len)
+            .map(|_| Ok((reader.read_entity()?, read_name(&mut reader)?)))
+            .collect::<Result<Vec<_>, EcsErrors>>()?;
+
+        if reader.remaining() != 0 {
+            return Err(EcsErrors::BinaryFormat(format!(
+                "{} trailing bytes",
+                reader.remaining()
+            )));
+        }
+
+        // Ids are handed out again from the freed ones, so one listed twice,
+        // or one that is spawned or written to, would end up shared by two
+        // entities.
+        let mut freed = HashSet::new();
+        if let Some(id) = freed_ids.iter().find(|id| !freed.insert(**id)) {
+            return Err(EcsErrors::BinaryFormat(format!("invalid freed id {id}")));
+        }
+        let alive = spawned
+            .iter()
+            .chain(added.iter().chain(changed.iter()).map(|c| &c.entity))
+            .chain(removed.iter().map(|(e, _)| e));
+        if let Some(entity) = alive.into_iter().find(|e| freed.contains(&e.0)) {
+            return Err(EcsErrors::BinaryFormat(format!("invalid freed id {}", entity.0)));
+        }
+
+        let referenced = freed_ids
+            .iter()
+            .copied()
+            .chain(spawned.iter().chain(despawned.iter()).map(|e| e.0))
+            .chain(added.iter().chain(changed.iter()).map(|c| c.entity.0))
+            .chain(removed.iter().map(|(e, _)| e.0));
+        if let Some(id) = referenced.into_iter().find(|id| *id >= next_id) {
+            return Err(EcsErrors::BinaryFormat(format!("invalid entity id {id}")));
+        }
+
+        Ok(WorldDelta {
+            since,
+            tick,
+            next_id,
+            freed_ids,
+            spawned,
+            despawned,
+            added,
+            changed,
+            removed,
+        })
+    }
+}
+
+impl<'a> World<'a> {
+    /// Collects entity and binary registered component changes made at or
+    /// after `since_tick`.
+    pub fn diff(&self, since_tick: u64) -> WorldDelta {
+        self.binary.diff(self.entity_manager(), since_tick)
+    }
+
+    /// Brings this world to the state the delta was taken in, given it was in
+    /// the state of the delta's source world at `delta.since`.
+    pub fn apply_delta(&mut self, delta: &WorldDelta) -> Result<(), EcsErrors> {
+        let binary = std::mem::take(&mut self.binary);
+        let result = binary.apply_delta(delta, self.entity_manager_mut());
+        self.binary = binary;
+        result?;
+
+        self.resync_systems();
+        info!(
+            "Applied delta from tick {} to {} with {} spawned and {} despawned entities",
+            delta.since,
+            delta.tick,
+            delta.spawned.len(),
+            delta.despawned.len()
+        );
+        Ok(())
+    }
+
+    pub fn encode_delta(&self, delta: &WorldDelta) -> Vec<u8> {
+        self.binary.encode_delta(delta)
+    }
+
+    pub fn decode_delta(&self, bytes: &[u8]) -> Result<WorldDelta, EcsErrors> {
+        self.binary.decode_delta(bytes)
+    }
+}
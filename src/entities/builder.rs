@@ -0,0 +1,60 @@
+use log::info;
+
+use crate::{
+    bundle::{BoxedBundle, Bundle},
+    errors::EcsErrors,
+    world::World,
+};
+
+use super::Entity;
+
+/// Collects the components of a new entity and spawns it with all of them
+/// at once. Nothing is allocated until `commit`, so dropping the builder
+/// leaves the world untouched.
+pub struct EntityBuilder<'w, 'a> {
+    world: &'w mut World<'a>,
+    components: Vec<Box<dyn BoxedBundle>>,
+}
+
+impl<'w, 'a> EntityBuilder<'w, 'a> {
+    pub(crate) fn new(world: &'w mut World<'a>) -> Self {
+        Self {
+            world,
+            components: vec![],
+        }
+    }
+
+    /// Adds a component, or every component of a bundle.
+    pub fn with<B: Bundle>(mut self, bundle: B) -> Self {
+        self.components.push(Box::new(bundle));
+        self
+    }
+
+    pub(crate) fn with_boxed(mut self, bundle: Box<dyn BoxedBundle>) -> Self {
+        self.components.push(bundle);
+        self
+    }
+
+    /// Spawns the entity and adds it to every system it matches right away.
+    pub fn commit(self) -> Entity {
+        self.try_commit().unwrap()
+    }
+
+    /// Same as `commit`, but if a component can't be added the entity is
+    /// removed again and never seen by any system.
+    pub fn try_commit(self) -> Result<Entity, EcsErrors> {
+        let world = self.world;
+        let entity = world.entity_manager_mut().create_entity();
+
+        for bundle in self.components {
+            if let Err(err) = bundle.insert_into(world.entity_manager_mut(), &entity) {
+                world.discard_spawned(&[entity]);
+                return Err(err);
+            }
+        }
+
+        world.sync_systems();
+        info!("Spawned {}", world.entity_manager().label(&entity));
+        Ok(entity)
+    }
+}
@@ -1,15 +1,25 @@
-use std::any::{type_name, TypeId};
-use std::collections::{HashMap, VecDeque};
+use std::any::{type_name, Any, TypeId};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 
 use log::info;
 
 use crate::components::component_manager::ComponentManager;
+use crate::components::dynamic::ComponentId;
+use crate::components::require::Requirement;
 use crate::errors::EcsErrors;
 use crate::components::Component;
+use crate::name::{Name, NameMode};
 
-use super::Entity;
+use super::{Disabled, Entity};
 
-struct EntityIdGenerator {
+/// Whether an entity with `signature` belongs to a system or query requiring
+/// `required`. Disabled entities only match when `Disabled` is required too.
+pub fn signature_matches(signature: u32, required: u32, disabled: u32) -> bool {
+    signature & required == required && (signature & disabled == 0 || required & disabled != 0)
+}
+
+#[derive(Clone)]
+pub(crate) struct EntityIdGenerator {
     current_free_id: usize,
     freed_entities: VecDeque<usize>,
 }
@@ -36,8 +46,12 @@ impl EntityIdGenerator {
         self.freed_entities.push_back(id)
     }
 
+    pub fn state(&self) -> (usize, &VecDeque<usize>) {
+        (self.current_free_id, &self.freed_entities)
+    }
+
     pub fn is_id_used(&self, id: usize) -> bool {
-        self.current_free_id >= id && !self.freed_entities.contains(&id)
+        self.current_free_id > id && !self.freed_entities.contains(&id)
     }
 }
 
@@ -45,6 +59,18 @@ pub struct EntityManager<'a> {
     id_generator: EntityIdGenerator,
     pub entity_component_signatures: Vec<u32>,
     pub component_manager: ComponentManager<'a>,
+    spawned_at: Vec<u64>,
+    despawned_at: Vec<u64>,
+    /// Signature of every entity changed since the last
+    /// `take_signature_changes`, as it was before the first change.
+    signature_changes: BTreeMap<Entity, u32>,
+    pub(crate) name_mode: NameMode,
+}
+
+impl<'a> Default for EntityManager<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> EntityManager<'a> {
@@ -53,6 +79,10 @@ impl<'a> EntityManager<'a> {
             id_generator: EntityIdGenerator::new(),
             entity_component_signatures: vec![],
             component_manager: ComponentManager::new(),
+            spawned_at: vec![],
+            despawned_at: vec![],
+            signature_changes: BTreeMap::new(),
+            name_mode: NameMode::default(),
         }
     }
 
@@ -64,18 +94,109 @@ impl<'a> EntityManager<'a> {
         } else {
             self.entity_component_signatures[entity_id] = 0;
         }
+        self.stamp_spawned(entity_id);
+
+        let entity = Entity(entity_id);
+        info!("Created {}", self.label(&entity));
+
+        entity
+    }
+
+    pub(crate) fn id_generator(&self) -> &EntityIdGenerator {
+        &self.id_generator
+    }
+
+    pub(crate) fn set_id_generator(&mut self, id_generator: EntityIdGenerator) {
+        self.id_generator = id_generator;
+    }
+
+    /// Resets the id allocation state, e.g. after entities were decoded from
+    /// another world. All signatures are expected to be cleared already.
+    pub(crate) fn restore_ids(&mut self, current_free_id: usize, freed_entities: VecDeque<usize>) {
+        self.id_generator = EntityIdGenerator {
+            current_free_id,
+            freed_entities,
+        };
+        if self.entity_component_signatures.len() < current_free_id {
+            self.entity_component_signatures.resize(current_free_id, 0);
+        }
+    }
+
+    /// Bit of `Disabled`, 0 while no entity was ever disabled.
+    pub fn disabled_mask(&self) -> u32 {
+        self.component_manager.get_mask::<Disabled>().copied().unwrap_or(0)
+    }
 
-            info!("Entity created with id = {entity_id}");
+    pub fn is_alive(&self, entity: &Entity) -> bool {
+        self.id_generator.is_id_used(entity.0)
+    }
 
-        Entity(entity_id)
+    /// Alive entities in id order. Goes over the freed ids once, where
+    /// `is_alive` searches them for every entity.
+    pub fn alive_entities(&self) -> Vec<Entity> {
+        let (next_id, freed_ids) = self.id_generator.state();
+        let mut alive = vec![true; next_id];
+        freed_ids.iter().for_each(|id| alive[*id] = false);
+        (0..next_id).filter(|id| alive[*id]).map(Entity).collect()
     }
 
     pub fn remove_entity(&mut self, entity: &Entity) {
-            info!("Removing entity id = {}", entity.0);
+            info!("Removing {}", self.label(entity));
 
-        self.entity_component_signatures[entity.0] = 0;
+        self.set_signature(entity, 0);
         self.component_manager.remove_all(entity);
         self.id_generator.free_id(entity.0);
+        self.stamp_despawned(entity.0);
+    }
+
+    pub(crate) fn stamp_spawned(&mut self, id: usize) {
+        if self.spawned_at.len() <= id {
+            self.spawned_at.resize(id + 1, 0);
+        }
+        self.spawned_at[id] = self.component_manager.tick();
+    }
+
+    fn stamp_despawned(&mut self, id: usize) {
+        if self.despawned_at.len() <= id {
+            self.despawned_at.resize(id + 1, 0);
+        }
+        self.despawned_at[id] = self.component_manager.tick();
+    }
+
+    /// Whether the entity id was created at or after `tick`.
+    pub fn spawned_since(&self, entity: &Entity, tick: u64) -> bool {
+        self.spawned_at.get(entity.0).is_some_and(|spawned| *spawned >= tick)
+    }
+
+    /// Whether the entity id was removed at or after `tick`. The id may have
+    /// been handed out again since.
+    pub fn despawned_since(&self, entity: &Entity, tick: u64) -> bool {
+        self.despawned_at.get(entity.0).is_some_and(|despawned| *despawned >= tick)
+    }
+
+    /// Stamps every entity id as either spawned or despawned at the current
+    /// tick, for when the whole entity state was replaced at once.
+    pub(crate) fn stamp_all_entities(&mut self) {
+        for id in 0..self.id_generator.state().0 {
+            if self.is_alive(&Entity(id)) {
+                self.stamp_despawned(id);
+                self.stamp_spawned(id);
+            } else {
+                self.stamp_despawned(id);
+            }
+        }
+    }
+
+    /// Replaces the signature of `entity`, remembering the old one so
+    /// systems can be updated.
+    pub(crate) fn set_signature(&mut self, entity: &Entity, signature: u32) {
+        let old = self.entity_component_signatures[entity.0];
+        self.signature_changes.entry(*entity).or_insert(old);
+        self.entity_component_signatures[entity.0] = signature;
+    }
+
+    pub(crate) fn take_signature_changes(&mut self) -> BTreeMap<Entity, u32> {
+        std::mem::take(&mut self.signature_changes)
     }
 
     pub fn add_component<T: Component + 'static>(
@@ -83,17 +204,42 @@ impl<'a> EntityManager<'a> {
         entity: &Entity,
         component: T,
     ) -> Result<(), EcsErrors> {
-        let comp_mask = self.component_manager.add_component(entity, component);
+        if let Some(name) = (&component as &dyn Any).downcast_ref::<Name>() {
+            self.check_name(entity, &name.0)?;
+        }
+        self.component_manager.register_checked::<T>()?;
+        let comp_mask = *self.component_manager.add_component(entity, component);
 
         if !self.id_generator.is_id_used(entity.0) {
             return Err(EcsErrors::EntityDoesNotExist(entity.0));
         }
 
-        self.entity_component_signatures[entity.0] |= comp_mask;
+        self.set_signature(entity, self.entity_component_signatures[entity.0] | comp_mask);
+        self.insert_required(entity, T::requirements());
 
         Ok(())
     }
 
+    /// Adds defaults for every requirement, and the requirements of those,
+    /// that the entity does not have yet.
+    pub(crate) fn insert_required(&mut self, entity: &Entity, mut requirements: Vec<Requirement>) {
+        let mut next = 0;
+        while let Some(requirement) = requirements.get(next).copied() {
+            next += 1;
+
+            let mask = (requirement.register)(&mut self.component_manager);
+            if self.entity_component_signatures[entity.0] & mask == 0 {
+                (requirement.insert_default)(&mut self.component_manager, entity);
+                self.set_signature(entity, self.entity_component_signatures[entity.0] | mask);
+                requirements.extend((requirement.requirements)());
+                info!(
+                    "Add required component {} to {}",
+                    requirement.name, self.label(entity)
+                );
+            }
+        }
+    }
+
     pub fn remove_component<T: Component + 'static>(
         &mut self,
         entity: &Entity,
@@ -102,14 +248,14 @@ impl<'a> EntityManager<'a> {
             return Err(EcsErrors::EntityDoesNotExist(entity.0));
         }
 
-        let comp_mask = self.component_manager.get_mask::<T>().unwrap();
-        self.entity_component_signatures[entity.0] &= !comp_mask;
+        let comp_mask = *self.component_manager.get_mask::<T>().unwrap();
+        self.set_signature(entity, self.entity_component_signatures[entity.0] & !comp_mask);
         let _ = self.component_manager.remove::<T>(entity);
 
         info!(
-            "Removing component {} from Entity Id = {}",
+            "Removing component {} from {}",
             type_name::<T>(),
-            entity.0
+            self.label(entity)
         );
 
         Ok(())
@@ -123,19 +269,54 @@ impl<'a> EntityManager<'a> {
             return Err(EcsErrors::EntityDoesNotExist(entity.0));
         }
 
-        let comp_mask = self.component_manager.get_mask_for_id(comp_id).unwrap();
-        self.entity_component_signatures[entity.0] &= !comp_mask;
+        let comp_mask = *self.component_manager.get_mask_for_id(comp_id).unwrap();
+        self.detach_hierarchy(entity, comp_mask)?;
+        self.set_signature(entity, self.entity_component_signatures[entity.0] & !comp_mask);
         let _ = self.component_manager.remove_with_id(entity, comp_id);
 
         info!(
-            "Removing component {} from Entity Id = {}",
+            "Removing component {} from {}",
             "Unknown",
-            entity.0
+            self.label(entity)
         );
 
         Ok(())
     }
 
+    pub fn add_dynamic_component(
+        &mut self,
+        entity: &Entity,
+        id: ComponentId,
+        bytes: &[u8],
+    ) -> Result<(), EcsErrors> {
+        if !self.id_generator.is_id_used(entity.0) {
+            return Err(EcsErrors::EntityDoesNotExist(entity.0));
+        }
+
+        self.component_manager.add_dynamic(entity, id, bytes)?;
+        self.set_signature(entity, self.entity_component_signatures[entity.0] | id.mask());
+
+        Ok(())
+    }
+
+    /// Removes a component by id, which works for static and dynamic
+    /// components alike.
+    pub fn remove_component_by_id(&mut self, entity: &Entity, id: ComponentId) -> Result<(), EcsErrors> {
+        if !self.id_generator.is_id_used(entity.0) {
+            return Err(EcsErrors::EntityDoesNotExist(entity.0));
+        }
+
+        self.component_manager.remove_with_component_id(entity, id)?;
+        self.set_signature(entity, self.entity_component_signatures[entity.0] & !id.mask());
+
+        Ok(())
+    }
+
+    pub fn has_component_id(&self, entity: &Entity, id: ComponentId) -> Result<bool, EcsErrors> {
+        let signature = self.get_signature(entity)?;
+        Ok((*signature & id.mask()) == id.mask())
+    }
+
     pub fn has_component<T: Component + 'static>(
         &self,
         entity: &Entity,
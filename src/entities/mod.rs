@@ -1,5 +1,37 @@
 
+use crate::components::{
+    clone::{clone_component, CloneFn},
+    Component,
+};
+
+pub mod builder;
 pub mod entity_manager;
 
-#[derive(Debug, PartialEq, PartialOrd, Clone, Copy, Eq, Hash)]
-pub struct Entity(pub usize);
\ No newline at end of file
+#[derive(Debug, PartialEq, PartialOrd, Ord, Clone, Copy, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Entity(pub usize);
+
+/// Marks an entity switched off with `World::disable_entity`. Systems and
+/// queries skip such entities unless they require `Disabled` themselves or
+/// opt in with `include_disabled`. Copies of a disabled entity start out
+/// disabled as well.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Disabled;
+
+impl Component for Disabled {
+    fn clone_fn() -> Option<CloneFn> {
+        Some(clone_component::<Disabled>)
+    }
+}
+
+/// Implemented by components holding references to other entities, so the
+/// references can be rewritten when those entities are recreated under new ids.
+pub trait MapEntities {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity);
+}
+
+/// Implemented by components pointing at one other entity, like a target or
+/// an owner, so typed queries can join through them with `Related`.
+pub trait EntityRef {
+    fn entity(&self) -> Entity;
+}
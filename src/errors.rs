@@ -10,7 +10,67 @@ pub enum EcsErrors {
     EntityDoesNotExist(usize),
     
     #[error("Component {0} does not exist")]
-    ComponentDoesNotExist(String)
+    ComponentDoesNotExist(String),
+
+    #[error("Entity {1} can not become parent of entity {0} as it would create a cycle")]
+    HierarchyCycle(usize, usize),
+
+    #[error("Scene could not be read or written: {0}")]
+    SceneFormat(String),
+
+    #[error("Scene type {0} is not registered")]
+    UnknownSceneType(String),
+
+    #[error("Binary world data is malformed: {0}")]
+    BinaryFormat(String),
+
+    #[error("Binary world format version {0} is not supported")]
+    UnsupportedFormatVersion(u16),
+
+    #[error("Binary world was encoded with schema {found:#x}, expected {expected:#x}")]
+    SchemaMismatch { expected: u64, found: u64 },
+
+    #[error("Frame {0} is no longer buffered for rollback")]
+    FrameNotBuffered(u64),
+
+    #[error("World state of frame {frame} diverged, local hash {local:#x} and remote hash {remote:#x}")]
+    Desync { frame: u64, local: u64, remote: u64 },
+
+    #[error("Reflected value has no field at path {0}")]
+    InvalidReflectPath(String),
+
+    #[error("Reflected value could not be set: {0}")]
+    ReflectValue(String),
+
+    #[error("Dynamic component layout does not match: {0}")]
+    DynamicComponentLayout(String),
+
+    #[error("Component {0} can not be registered, all component bits are in use")]
+    TooManyComponents(String),
+
+    #[error("Required components form a cycle: {0}")]
+    RequirementCycle(String),
+
+    #[error("Component {0} can not be cloned")]
+    NotCloneable(String),
+
+    #[error("Entity {0} is listed twice or does not match the query")]
+    InvalidQueryEntity(usize),
+
+    #[error("Component {0} has no index")]
+    ComponentNotIndexed(String),
+
+    #[error("Another entity is already named {0:?}")]
+    DuplicateName(String),
+
+    #[error("Prefab has no entity {0}")]
+    InvalidPrefabEntity(usize),
+
+    #[error("Component {0} is not registered for binary encoding")]
+    NotBinaryComponent(String),
+
+    #[error("Bundle {0} holds Parent or Children, which only set_parent can add")]
+    HierarchyComponent(String),
 }
 
 impl EcsErrors {
@@ -14,8 +14,6 @@ type GameEventHanlder<T> = fn(&T, &Query, &mut CommandBuffer);
 trait EventHandlerStorage {
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
-    fn is_empty(&self) -> bool;
-    fn remove_any(&mut self, type_id: &TypeId);
 }
 
 type GameHandlerVec<T> = Vec<GameEventHanlder<T>>;
@@ -28,14 +26,6 @@ impl<T: GameEvent + 'static> EventHandlerStorage for GameHandlerVec<T> {
     fn as_any_mut(&mut self) -> &mut dyn Any {
         self
     }
-
-    fn is_empty(&self) -> bool {
-        self.is_empty()
-    }
-
-    fn remove_any(&mut self, _type_id: &TypeId) {
-        todo!()
-    }
 }
 
 pub struct WorldEvents {
@@ -47,6 +37,7 @@ pub struct EventEmitter {
 }
 
 pub trait WorldEventSubscriber {
+    /// Handlers of an event run in the order they were subscribed.
     fn subscribe<T: GameEvent + 'static>(&mut self, handler: GameEventHanlder<T>);
 }
 
@@ -68,6 +59,12 @@ impl WorldEvents {
     }
 }
 
+impl Default for WorldEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl WorldEventSubscriber for WorldEvents {
     fn subscribe<T: GameEvent + 'static>(&mut self, handler: GameEventHanlder<T>) {
         let id = TypeId::of::<T>();
@@ -116,22 +113,23 @@ mod test {
     use crate::{command_buffer::CommandBuffer, query::Query, world::World};
     use ecs_macro::GameEvent;
 
-    use super::WorldEvents;
+    use super::{WorldEventSubscriber, WorldEvents};
 
     #[derive(GameEvent)]
     struct SomethingHappend;
 
     fn handle_something_happend(
-        event: &SomethingHappend,
-        query: Query,
-        cmd_buffer: &mut CommandBuffer,
+        _event: &SomethingHappend,
+        _query: &Query,
+        _cmd_buffer: &mut CommandBuffer,
     ) {
     }
 
     #[test]
     fn register_handler() {
-        let world = World::new();
+        let _world = World::new();
         let mut events = WorldEvents::new();
+        events.subscribe(handle_something_happend);
     }
 
     #[test]
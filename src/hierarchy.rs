@@ -0,0 +1,190 @@
+use log::info;
+
+use crate::{
+    components::Component,
+    entities::{entity_manager::EntityManager, Entity, MapEntities},
+    errors::EcsErrors,
+};
+
+/// Points from a child entity to the entity it is attached to. Only
+/// `set_parent` creates it, so both ends of the link stay in sync.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parent(pub(crate) Entity);
+
+impl Component for Parent {}
+
+impl Parent {
+    pub fn get(&self) -> Entity {
+        self.0
+    }
+}
+
+impl MapEntities for Parent {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        self.0 = map(self.0);
+    }
+}
+
+/// Entities attached to this entity, in the order they were attached.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Children(pub(crate) Vec<Entity>);
+
+impl Component for Children {}
+
+impl MapEntities for Children {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        self.0.iter_mut().for_each(|child| *child = map(*child));
+    }
+}
+
+impl Children {
+    pub fn as_slice(&self) -> &[Entity] {
+        &self.0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<'a> EntityManager<'a> {
+    pub fn parent_of(&self, entity: &Entity) -> Option<Entity> {
+        let parents = self.component_manager.get_components::<Parent>().ok()?;
+        parents.data.get(entity.0)?.as_ref().map(|parent| parent.0)
+    }
+
+    pub fn children_of(&self, entity: &Entity) -> Vec<Entity> {
+        let Ok(children) = self.component_manager.get_components::<Children>() else {
+            return vec![];
+        };
+
+        children
+            .data
+            .get(entity.0)
+            .and_then(|c| c.as_ref())
+            .map(|c| c.0.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn ancestors_of(&self, entity: &Entity) -> Vec<Entity> {
+        let mut ancestors = vec![];
+        let mut current = *entity;
+        while let Some(parent) = self.parent_of(&current) {
+            ancestors.push(parent);
+            current = parent;
+        }
+        ancestors
+    }
+
+    /// Returns all entities below `entity` in depth-first pre-order.
+    pub fn descendants_of(&self, entity: &Entity) -> Vec<Entity> {
+        let mut descendants = vec![];
+        let mut stack: Vec<Entity> = self.children_of(entity).into_iter().rev().collect();
+
+        while let Some(current) = stack.pop() {
+            descendants.push(current);
+            stack.extend(self.children_of(&current).into_iter().rev());
+        }
+        descendants
+    }
+
+    pub fn set_parent(&mut self, child: &Entity, parent: &Entity) -> Result<(), EcsErrors> {
+        if !self.is_alive(child) {
+            return Err(EcsErrors::EntityDoesNotExist(child.0));
+        }
+        if !self.is_alive(parent) {
+            return Err(EcsErrors::EntityDoesNotExist(parent.0));
+        }
+        if child == parent || self.ancestors_of(parent).contains(child) {
+            return Err(EcsErrors::HierarchyCycle(child.0, parent.0));
+        }
+
+        self.remove_parent(child)?;
+        self.add_component(child, Parent(*parent))?;
+
+        let has_children = self
+            .component_manager
+            .get_components::<Children>()
+            .map(|c| c.data.get(parent.0).is_some_and(|c| c.is_some()))
+            .unwrap_or(false);
+
+        if has_children {
+            self.component_manager
+                .get_components_mut::<Children>()?
+                .get_mut(parent.0)?
+                .0
+                .push(*child);
+        } else {
+            self.add_component(parent, Children(vec![*child]))?;
+        }
+
+        info!("{} attached to parent {}", self.label(child), self.label(parent));
+        Ok(())
+    }
+
+    pub fn remove_parent(&mut self, child: &Entity) -> Result<(), EcsErrors> {
+        let Some(parent) = self.parent_of(child) else {
+            return Ok(());
+        };
+
+        self.remove_component::<Parent>(child)?;
+
+        // A parent without `Children`, e.g. after a partial restore, has
+        // nothing left to detach.
+        let is_empty = match self.component_manager.get_components_mut::<Children>() {
+            Ok(mut pool) if pool.data.get(parent.0).is_some_and(|c| c.is_some()) => {
+                let children = pool.get_mut(parent.0)?;
+                children.0.retain(|c| c != child);
+                children.is_empty()
+            }
+            _ => false,
+        };
+        if is_empty {
+            self.remove_component::<Children>(&parent)?;
+        }
+
+        info!("{} detached from parent {}", self.label(child), self.label(&parent));
+        Ok(())
+    }
+
+    /// Detaches `entity` from its parent if `mask` covers `Parent`, and from
+    /// its children if it covers `Children`. Every removal path runs this
+    /// first, so dropping either component takes the other end along.
+    pub(crate) fn detach_hierarchy(&mut self, entity: &Entity, mask: u32) -> Result<(), EcsErrors> {
+        let parent_mask = self.component_manager.get_mask::<Parent>().copied().unwrap_or(0);
+        let children_mask = self.component_manager.get_mask::<Children>().copied().unwrap_or(0);
+
+        if mask & parent_mask != 0 {
+            self.remove_parent(entity)?;
+        }
+        if mask & children_mask != 0 {
+            for child in self.children_of(entity) {
+                self.remove_parent(&child)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Unlinks `entity` from its parent and orphans its children, so that no
+    /// `Parent` or `Children` component refers to it once it is removed.
+    pub(crate) fn detach_from_hierarchy(&mut self, entity: &Entity) {
+        let _ = self.remove_parent(entity);
+
+        for child in self.children_of(entity) {
+            let _ = self.remove_component::<Parent>(&child);
+        }
+        if !self.children_of(entity).is_empty() {
+            let _ = self.remove_component::<Children>(entity);
+        }
+    }
+}
@@ -1,11 +1,32 @@
+extern crate self as secs;
+
+pub mod binary;
+pub mod bundle;
+pub mod cached_query;
 pub mod command_buffer;
 pub mod components;
+pub mod delta;
 pub mod entities;
 pub mod errors;
+pub mod prefab;
 pub mod query;
+pub mod reflect;
+pub mod relations;
 pub mod resources;
+pub mod rollback;
+#[cfg(feature = "serde")]
+pub mod scene;
+pub mod snapshot;
+pub mod sorted_view;
 pub mod events;
+pub mod hierarchy;
+pub mod name;
 mod tests;
+#[cfg(feature = "spatial")]
+pub mod spatial;
+#[cfg(feature = "transform")]
+pub mod transform;
+pub mod typed_query;
 pub mod world;
 pub use ecs_macro;
 pub mod system;
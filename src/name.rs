@@ -0,0 +1,143 @@
+use std::{any::TypeId, borrow::Borrow, collections::HashSet, fmt};
+
+use log::info;
+
+use crate::{
+    components::{
+        clone::{clone_component, CloneFn},
+        component_manager::CellComponent,
+        index::{HashIndex, PoolIndex},
+        Component,
+    },
+    entities::{entity_manager::EntityManager, Entity},
+    errors::EcsErrors,
+    world::World,
+};
+
+/// Human readable name of an entity, shown in logs and looked up with
+/// `World::find_by_name`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Name(pub String);
+
+impl Component for Name {
+    fn clone_fn() -> Option<CloneFn> {
+        Some(clone_component::<Name>)
+    }
+
+    fn index() -> Option<Box<dyn PoolIndex<Self>>> {
+        Some(Box::<HashIndex<Name>>::default())
+    }
+}
+
+impl Borrow<str> for Name {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Whether several entities may share a name.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum NameMode {
+    #[default]
+    Shared,
+    /// Naming an entity after another one fails with `DuplicateName`, however
+    /// the `Name` is added. Copies of named entities are left unnamed. Names
+    /// changed in place through mutable pool access are not checked.
+    Unique,
+}
+
+/// Formats as `Entity 423 "Player"`, or `Entity 423` for unnamed entities.
+pub struct EntityLabel {
+    id: usize,
+    name: Option<String>,
+}
+
+impl fmt::Display for EntityLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Entity {}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " {name:?}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> EntityManager<'a> {
+    /// Id and name of `entity` for log lines. Only looks the name up when
+    /// formatted into a log line that is actually written, and leaves it out
+    /// while the names are borrowed mutably.
+    pub fn label(&self, entity: &Entity) -> EntityLabel {
+        let name = self
+            .component_manager
+            .pool(&TypeId::of::<Name>())
+            .and_then(|pool| pool.as_any().downcast_ref::<CellComponent<Name>>()?.try_borrow().ok())
+            .and_then(|names| names.data.get(entity.0)?.as_ref().map(|n| n.0.clone()));
+        EntityLabel { id: entity.0, name }
+    }
+
+    /// Every entity called `name`, in id order.
+    pub fn find_all_by_name(&self, name: &str) -> Vec<Entity> {
+        self.component_manager
+            .index::<Name>()
+            .map(|index| index.get(name))
+            .unwrap_or_default()
+    }
+
+    /// Fails in `NameMode::Unique` if an entity other than `entity` is
+    /// already called `name`.
+    pub(crate) fn check_name(&self, entity: &Entity, name: &str) -> Result<(), EcsErrors> {
+        if self.name_mode == NameMode::Unique && self.find_all_by_name(name).iter().any(|e| e != entity) {
+            return Err(EcsErrors::DuplicateName(name.to_owned()));
+        }
+        Ok(())
+    }
+
+    /// Bit of `Name` while names are unique, which cloning leaves out.
+    pub(crate) fn unique_name_mask(&self) -> u32 {
+        match self.name_mode {
+            NameMode::Shared => 0,
+            NameMode::Unique => self.component_manager.get_mask::<Name>().copied().unwrap_or(0),
+        }
+    }
+}
+
+impl<'a> World<'a> {
+    /// Switching to `NameMode::Unique` fails with `DuplicateName` while two
+    /// entities share a name.
+    pub fn set_name_mode(&mut self, mode: NameMode) -> Result<(), EcsErrors> {
+        if mode == NameMode::Unique {
+            let query = self.query();
+            if let Ok(names) = query.components().try_get::<Name>() {
+                let mut seen = HashSet::new();
+                if let Some(name) = names.iter().flatten().find(|name| !seen.insert(name.0.as_str())) {
+                    return Err(EcsErrors::DuplicateName(name.0.clone()));
+                }
+            }
+        }
+        self.entity_manager_mut().name_mode = mode;
+        Ok(())
+    }
+
+    /// Names `entity`, replacing its previous name.
+    pub fn set_name(&mut self, entity: &Entity, name: impl Into<String>) {
+        self.try_set_name(entity, name).unwrap()
+    }
+
+    pub fn try_set_name(&mut self, entity: &Entity, name: impl Into<String>) -> Result<(), EcsErrors> {
+        self.entity_manager_mut().add_component(entity, Name(name.into()))?;
+        self.sync_systems();
+        info!("Named {}", self.entity_manager().label(entity));
+        Ok(())
+    }
+
+    /// Lowest id entity called `name`.
+    pub fn find_by_name(&self, name: &str) -> Option<Entity> {
+        self.find_all_by_name(name).first().copied()
+    }
+
+    /// Every entity called `name`, in id order.
+    pub fn find_all_by_name(&self, name: &str) -> Vec<Entity> {
+        self.entity_manager().find_all_by_name(name)
+    }
+}
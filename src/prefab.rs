@@ -0,0 +1,405 @@
+use std::{collections::HashMap, rc::Rc};
+
+use log::{info, warn};
+
+use crate::{
+    bundle::{BoxedBundle, Bundle},
+    components::{
+        clone::{ClonePolicy, ComponentCopy},
+        Component,
+    },
+    entities::{Entity, MapEntities},
+    errors::EcsErrors,
+    world::World,
+};
+
+/// One component of a prefab entity, added to every instance.
+pub trait PrefabComponent {
+    /// Adds a copy of the component to `entity`. `entity_map` maps the
+    /// entity references of the template to the new instances.
+    fn instantiate(
+        &self,
+        world: &mut World,
+        entity: &Entity,
+        entity_map: &HashMap<Entity, Entity>,
+    ) -> Result<(), EcsErrors>;
+}
+
+struct ValueComponent<T>(T);
+
+impl<T: Component + Clone + 'static> PrefabComponent for ValueComponent<T> {
+    fn instantiate(&self, world: &mut World, entity: &Entity, _: &HashMap<Entity, Entity>) -> Result<(), EcsErrors> {
+        world.entity_manager_mut().add_component(entity, self.0.clone())
+    }
+}
+
+struct MappedComponent<T>(T);
+
+impl<T: Component + MapEntities + Clone + 'static> PrefabComponent for MappedComponent<T> {
+    fn instantiate(
+        &self,
+        world: &mut World,
+        entity: &Entity,
+        entity_map: &HashMap<Entity, Entity>,
+    ) -> Result<(), EcsErrors> {
+        let mut component = self.0.clone();
+        let mut missing = None;
+        component.map_entities(&mut |e| match entity_map.get(&e) {
+            Some(mapped) => *mapped,
+            None if e.0 & LOCAL != 0 => {
+                missing = Some(e.0 & !LOCAL);
+                e
+            }
+            None => e,
+        });
+        if let Some(index) = missing {
+            return Err(EcsErrors::InvalidPrefabEntity(index));
+        }
+        world.entity_manager_mut().add_component(entity, component)
+    }
+}
+
+/// Cloneable components of an entity, copied when the prefab was made.
+/// References to the other copied entities, `sources[i]` standing for
+/// `Prefab::local(i)`, are remapped to the new instances.
+struct CopiedComponents {
+    components: Vec<Box<dyn ComponentCopy>>,
+    sources: Rc<Vec<Entity>>,
+}
+
+impl PrefabComponent for CopiedComponents {
+    fn instantiate(
+        &self,
+        world: &mut World,
+        entity: &Entity,
+        entity_map: &HashMap<Entity, Entity>,
+    ) -> Result<(), EcsErrors> {
+        let entity_manager = world.entity_manager_mut();
+        let ids = entity_manager.insert_copies(&self.components, entity)?;
+        entity_manager.map_components(&ids, entity, &mut |e| {
+            match self.sources.iter().position(|source| *source == e) {
+                Some(index) => entity_map[&Prefab::local(index)],
+                None => e,
+            }
+        })
+    }
+}
+
+/// Bit marking an entity id as a reference to a prefab entity.
+const LOCAL: usize = 1 << (usize::BITS - 1);
+
+/// Entity references a group of prefab entities use for each other: `keys[i]`
+/// refers to the prefab entity at `start + i`.
+#[derive(Clone)]
+struct Scope {
+    start: usize,
+    keys: Rc<Vec<Entity>>,
+}
+
+#[derive(Clone)]
+struct PrefabEntity {
+    components: Vec<Rc<dyn PrefabComponent>>,
+    parent: Option<usize>,
+    /// `None` while the entity belongs to the prefab it was defined in.
+    scope: Option<Scope>,
+}
+
+/// Template for a group of entities that can be spawned any number of times.
+///
+/// The first entity is the root. Inside a prefab, `Prefab::local(i)` refers
+/// to the i-th entity of that prefab and is remapped to the matching instance
+/// when spawned, other entity references are kept as they are. Nested
+/// prefabs keep their own numbering.
+#[derive(Clone)]
+pub struct Prefab {
+    entities: Vec<PrefabEntity>,
+}
+
+impl Default for Prefab {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Prefab {
+    pub fn new() -> Self {
+        Self {
+            entities: vec![PrefabEntity {
+                components: vec![],
+                parent: None,
+                scope: None,
+            }],
+        }
+    }
+
+    /// Copies `entity` and its descendants. Their cloneable components are
+    /// copied right away, so the source entities can be changed or removed
+    /// afterwards. References between the copied entities are remapped to
+    /// the new instances for components with a map function, other
+    /// references are copied as they are.
+    pub fn from_entity(world: &World, entity: &Entity) -> Result<Self, EcsErrors> {
+        let entity_manager = world.entity_manager();
+        if !entity_manager.is_alive(entity) {
+            return Err(EcsErrors::EntityDoesNotExist(entity.0));
+        }
+
+        let mut sources = vec![*entity];
+        sources.extend(entity_manager.descendants_of(entity));
+        let sources = Rc::new(sources);
+        let hierarchy = entity_manager.hierarchy_mask();
+
+        let mut entities = vec![];
+        for (index, source) in sources.iter().enumerate() {
+            let clone_fns = entity_manager.clone_fns(source, ClonePolicy::Skip, hierarchy)?;
+            let copied = CopiedComponents {
+                components: entity_manager.copy_components(clone_fns, source)?,
+                sources: sources.clone(),
+            };
+            entities.push(PrefabEntity {
+                components: vec![Rc::new(copied) as Rc<dyn PrefabComponent>],
+                parent: match index {
+                    0 => None,
+                    _ => entity_manager
+                        .parent_of(source)
+                        .and_then(|parent| sources.iter().position(|s| *s == parent)),
+                },
+                scope: None,
+            });
+        }
+
+        Ok(Self { entities })
+    }
+
+    /// Reference to the `index`-th entity of the prefab, for components added
+    /// with `with_mapped`. Instantiating fails with `InvalidPrefabEntity` if
+    /// the prefab has no such entity.
+    pub fn local(index: usize) -> Entity {
+        Entity(LOCAL | index)
+    }
+
+    /// Number of entities spawned per instance.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Adds `component` to the root entity.
+    pub fn with<T: Component + Clone + 'static>(self, component: T) -> Self {
+        self.with_component(Rc::new(ValueComponent(component)))
+    }
+
+    /// Adds a component holding entity references to the root entity.
+    pub fn with_mapped<T: Component + MapEntities + Clone + 'static>(self, component: T) -> Self {
+        self.with_component(Rc::new(MappedComponent(component)))
+    }
+
+    pub fn with_component(mut self, component: Rc<dyn PrefabComponent>) -> Self {
+        self.entities[0].components.push(component);
+        self
+    }
+
+    /// Nests `child` below the root. Its entities follow the ones already in
+    /// this prefab, so the child root becomes `Entity(self.len())`.
+    pub fn with_child(mut self, child: &Prefab) -> Self {
+        let offset = self.entities.len();
+        let keys = Rc::new((0..child.len()).map(Prefab::local).collect::<Vec<_>>());
+
+        for (index, entity) in child.entities.iter().enumerate() {
+            let scope = match &entity.scope {
+                Some(scope) => Scope {
+                    start: scope.start + offset,
+                    keys: scope.keys.clone(),
+                },
+                None => Scope {
+                    start: offset,
+                    keys: keys.clone(),
+                },
+            };
+            let parent = match entity.parent {
+                Some(parent) => Some(parent + offset),
+                None if index == 0 => Some(0),
+                None => None,
+            };
+
+            self.entities.push(PrefabEntity {
+                components: entity.components.clone(),
+                parent,
+                scope: Some(scope),
+            });
+        }
+        self
+    }
+
+    /// Spawns the entities, removing them again if a component, parent or
+    /// override can not be added.
+    fn instantiate(
+        &self,
+        world: &mut World,
+        overrides: Option<Box<dyn BoxedBundle>>,
+    ) -> Result<Vec<Entity>, EcsErrors> {
+        let spawned: Vec<Entity> = self
+            .entities
+            .iter()
+            .map(|_| world.create_entity().finish_entity())
+            .collect();
+
+        let result = self.fill(world, &spawned).and_then(|_| match overrides {
+            Some(overrides) => overrides.insert_into(world.entity_manager_mut(), &spawned[0]),
+            None => Ok(()),
+        });
+        if let Err(err) = result {
+            world.discard_spawned(&spawned);
+            return Err(err);
+        }
+        Ok(spawned)
+    }
+
+    fn fill(&self, world: &mut World, spawned: &[Entity]) -> Result<(), EcsErrors> {
+        let own_scope: HashMap<Entity, Entity> =
+            spawned.iter().enumerate().map(|(i, e)| (Prefab::local(i), *e)).collect();
+        for (index, prefab_entity) in self.entities.iter().enumerate() {
+            let scoped;
+            let entity_map = match &prefab_entity.scope {
+                Some(scope) => {
+                    scoped = scope
+                        .keys
+                        .iter()
+                        .enumerate()
+                        .map(|(i, key)| (*key, spawned[scope.start + i]))
+                        .collect();
+                    &scoped
+                }
+                None => &own_scope,
+            };
+
+            for component in prefab_entity.components.iter() {
+                component.instantiate(world, &spawned[index], entity_map)?;
+            }
+        }
+
+        for (index, prefab_entity) in self.entities.iter().enumerate() {
+            if let Some(parent) = prefab_entity.parent {
+                world.set_parent(&spawned[index], &spawned[parent])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+mod scene {
+    use std::{collections::HashMap, rc::Rc};
+
+    use serde_json::Value;
+
+    use super::{Prefab, PrefabComponent, PrefabEntity, Scope};
+    use crate::{
+        entities::Entity,
+        errors::EcsErrors,
+        hierarchy::Parent,
+        scene::{from_value, Scene, SceneFormat, CHILDREN, PARENT},
+        world::World,
+    };
+
+    struct SceneComponent {
+        name: String,
+        value: Value,
+    }
+
+    impl PrefabComponent for SceneComponent {
+        fn instantiate(
+            &self,
+            world: &mut World,
+            entity: &Entity,
+            entity_map: &HashMap<Entity, Entity>,
+        ) -> Result<(), EcsErrors> {
+            let scenes = std::mem::take(&mut world.scenes);
+            let result = scenes.load_component(world, &self.name, entity, self.value.clone(), entity_map);
+            world.scenes = scenes;
+            result
+        }
+    }
+
+    impl Prefab {
+        /// Builds a prefab from a saved scene. The first scene entity is the
+        /// root and references between scene entities are remapped per
+        /// instance. Parents outside the scene are dropped. Component names
+        /// are resolved when instantiating.
+        pub fn from_scene(data: &str, format: SceneFormat) -> Result<Self, EcsErrors> {
+            let scene = Scene::parse(data, format)?;
+            if scene.entities.is_empty() {
+                return Err(EcsErrors::SceneFormat("prefab scene has no entities".to_owned()));
+            }
+
+            let keys = Rc::new(scene.entities.iter().map(|e| e.entity).collect::<Vec<_>>());
+            let mut entities = vec![];
+            for mut scene_entity in scene.entities {
+                scene_entity.components.remove(CHILDREN);
+                let parent = match scene_entity.components.remove(PARENT) {
+                    Some(value) => {
+                        let parent = from_value::<Parent>(value)?.0;
+                        keys.iter().position(|key| *key == parent)
+                    }
+                    None => None,
+                };
+                entities.push(PrefabEntity {
+                    components: scene_entity
+                        .components
+                        .into_iter()
+                        .map(|(name, value)| Rc::new(SceneComponent { name, value }) as Rc<dyn PrefabComponent>)
+                        .collect(),
+                    parent,
+                    scope: Some(Scope {
+                        start: 0,
+                        keys: keys.clone(),
+                    }),
+                });
+            }
+
+            Ok(Self { entities })
+        }
+    }
+}
+
+impl<'a> World<'a> {
+    /// Spawns a copy of every prefab entity and returns the root. Like
+    /// `create_entity`, the new entities join systems on the next `update`.
+    pub fn instantiate(&mut self, prefab: &Prefab) -> Entity {
+        self.try_instantiate(prefab).unwrap()
+    }
+
+    /// Same as `instantiate`, with `overrides` added to the root on top of
+    /// the prefab components.
+    pub fn instantiate_with<B: Bundle>(&mut self, prefab: &Prefab, overrides: B) -> Entity {
+        self.try_instantiate_with(prefab, overrides).unwrap()
+    }
+
+    pub fn try_instantiate(&mut self, prefab: &Prefab) -> Result<Entity, EcsErrors> {
+        self.spawn_prefab(prefab, None)
+    }
+
+    pub fn try_instantiate_with<B: Bundle>(&mut self, prefab: &Prefab, overrides: B) -> Result<Entity, EcsErrors> {
+        self.spawn_prefab(prefab, Some(Box::new(overrides)))
+    }
+
+    fn spawn_prefab(&mut self, prefab: &Prefab, overrides: Option<Box<dyn BoxedBundle>>) -> Result<Entity, EcsErrors> {
+        let spawned = prefab.instantiate(self, overrides);
+        self.sync_systems();
+        let spawned = spawned?;
+        info!(
+            "Instantiated prefab with {} entities, root {}",
+            spawned.len(),
+            self.entity_manager().label(&spawned[0])
+        );
+        Ok(spawned[0])
+    }
+
+    pub(crate) fn instantiate_command(&mut self, prefab: &Prefab, overrides: Option<Box<dyn BoxedBundle>>) {
+        if let Err(err) = self.spawn_prefab(prefab, overrides) {
+            warn!("Failed to instantiate prefab: {err}");
+        }
+    }
+}
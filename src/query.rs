@@ -3,18 +3,30 @@ use std::{
     cell::{Ref,  RefMut},
 };
 
-use crate::components::Component;
+use crate::{
+    components::{
+        dynamic::{ComponentId, DynamicCompPool},
+        Component,
+    },
+    errors::EcsErrors,
+};
 
 use super::{
+    cached_query::QueryCache,
     components::{comp_pool::CompPool, component_manager::ComponentManager},
-    entities::{entity_manager::EntityManager, Entity},
+    entities::{
+        entity_manager::{signature_matches, EntityManager},
+        Entity,
+    },
+    relations::{Relation, RelationQuery, Relations},
     resources::{Resource, Resources},
 };
 
 pub struct Query<'a> {
-    component_manager: &'a ComponentManager<'a>,
-    entity_manager: &'a EntityManager<'a>,
+    pub(crate) component_manager: &'a ComponentManager<'a>,
+    pub(crate) entity_manager: &'a EntityManager<'a>,
     pub resources: &'a Resources,
+    pub(crate) queries: &'a QueryCache,
 }
 
 pub struct ComponentQuery<'a> {
@@ -23,7 +35,8 @@ pub struct ComponentQuery<'a> {
 
 pub struct EntityQuery<'a> {
     signature: u32,
-    component_manager: &'a ComponentManager<'a>,
+    include_disabled: bool,
+    pub(crate) component_manager: &'a ComponentManager<'a>,
     entity_manager: &'a EntityManager<'a>,
 }
 
@@ -32,11 +45,13 @@ impl<'a> Query<'a> {
         entity_manager: &'a EntityManager,
         component_manager: &'a ComponentManager,
         resources: &'a Resources,
+        queries: &'a QueryCache,
     ) -> Self {
         Self {
             entity_manager,
             component_manager,
             resources,
+            queries,
         }
     }
 
@@ -49,16 +64,43 @@ impl<'a> Query<'a> {
     pub fn entities(&self) -> EntityQuery<'a> {
         EntityQuery {
             signature: 0,
+            include_disabled: false,
             entity_manager: self.entity_manager,
             component_manager: self.component_manager,
         }
     }
 
-    pub fn resource<T: Any>(&self) -> Ref<Resource> {
+    pub fn tick(&self) -> u64 {
+        self.component_manager.tick()
+    }
+
+    pub fn parent(&self, entity: &Entity) -> Option<Entity> {
+        self.entity_manager.parent_of(entity)
+    }
+
+    pub fn children(&self, entity: &Entity) -> Vec<Entity> {
+        self.entity_manager.children_of(entity)
+    }
+
+    /// Walks up from `entity`, nearest parent first.
+    pub fn ancestors(&self, entity: &Entity) -> Vec<Entity> {
+        self.entity_manager.ancestors_of(entity)
+    }
+
+    /// All entities below `entity`, depth-first in pre-order.
+    pub fn descendants(&self, entity: &Entity) -> Vec<Entity> {
+        self.entity_manager.descendants_of(entity)
+    }
+
+    pub fn relations<R: Relation>(&self) -> RelationQuery<'a, R> {
+        RelationQuery::new(self.component_manager)
+    }
+
+    pub fn resource<T: Any>(&self) -> Ref<'_, Resource> {
         self.resources.get::<T>().borrow()
     }
 
-    pub fn resource_mut<T: Any>(&self) -> RefMut<Resource> {
+    pub fn resource_mut<T: Any>(&self) -> RefMut<'_, Resource> {
         self.resources.get::<T>().borrow_mut()
     }
 }
@@ -71,6 +113,22 @@ impl<'a> ComponentQuery<'a> {
     pub fn get_mut<T: Component + 'static>(self) -> RefMut<'a, CompPool<T>> {
         self.component_manager.get_components_mut::<T>().unwrap()
     }
+
+    pub fn try_get<T: Component + 'static>(self) -> Result<Ref<'a, CompPool<T>>, EcsErrors> {
+        self.component_manager.get_components::<T>()
+    }
+
+    pub fn try_get_mut<T: Component + 'static>(self) -> Result<RefMut<'a, CompPool<T>>, EcsErrors> {
+        self.component_manager.get_components_mut::<T>()
+    }
+
+    pub fn get_dynamic(self, id: ComponentId) -> Ref<'a, DynamicCompPool> {
+        self.component_manager.get_dynamic(id).unwrap()
+    }
+
+    pub fn get_dynamic_mut(self, id: ComponentId) -> RefMut<'a, DynamicCompPool> {
+        self.component_manager.get_dynamic_mut(id).unwrap()
+    }
 }
 
 impl<'a> EntityQuery<'a> {
@@ -80,14 +138,36 @@ impl<'a> EntityQuery<'a> {
         self
     }
 
+    /// Filters by component id, so dynamic and static components can be
+    /// mixed in one query.
+    pub fn with_component_id(mut self, id: ComponentId) -> Self {
+        self.signature |= id.mask();
+        self
+    }
+
+    pub fn with_relation<R: Relation>(self) -> Self {
+        self.with_component::<Relations<R>>()
+    }
+
+    /// Also returns entities disabled with `World::disable_entity`.
+    pub fn include_disabled(mut self) -> Self {
+        self.include_disabled = true;
+        self
+    }
+
     pub fn get(self) -> Vec<Entity> {
         let signature = self.signature;
+        let disabled = if self.include_disabled {
+            0
+        } else {
+            self.entity_manager.disabled_mask()
+        };
 
         self.entity_manager
             .entity_component_signatures
             .iter()
             .enumerate()
-            .filter(|(_, sig)| (**sig & signature) == signature)
+            .filter(|(_, sig)| signature_matches(**sig, signature, disabled))
             .map(|(id, _)| Entity(id))
             .collect()
     }
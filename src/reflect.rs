@@ -0,0 +1,491 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
+};
+
+use log::info;
+
+use crate::{
+    components::{comp_pool::CompPool, Component},
+    entities::Entity,
+    errors::EcsErrors,
+    resources::Resources,
+    world::World,
+};
+
+/// Name and type of one field of a reflected value. Tuple struct fields are
+/// named by their index.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FieldInfo {
+    pub name: &'static str,
+    pub type_name: &'static str,
+}
+
+/// Access to the fields of a value by name, without knowing its type at
+/// compile time. Derive it with `#[derive(Reflect)]`, leaving out fields
+/// that can't be reflected with `#[reflect(ignore)]`.
+///
+/// Paths are field names joined by dots, like `"offset.x"`.
+pub trait Reflect: Any {
+    fn type_fields() -> Vec<FieldInfo>
+    where
+        Self: Sized;
+    fn type_name(&self) -> &'static str;
+    fn fields(&self) -> Vec<FieldInfo>;
+    fn field(&self, name: &str) -> Option<&dyn Reflect>;
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Human readable value, for editors and consoles.
+    fn value(&self) -> String {
+        let fields: Vec<String> = self
+            .fields()
+            .iter()
+            .map(|f| format!("{}: {}", f.name, self.field(f.name).unwrap().value()))
+            .collect();
+        format!("{} {{ {} }}", short_name(self.type_name()), fields.join(", "))
+    }
+
+    /// Parses `text` into this value. Only values without fields can be set
+    /// from text, structs are set field by field.
+    fn set_value(&mut self, text: &str) -> Result<(), EcsErrors> {
+        Err(EcsErrors::ReflectValue(format!(
+            "{} can not be set from {text:?}",
+            self.type_name()
+        )))
+    }
+}
+
+impl dyn Reflect {
+    pub fn path(&self, path: &str) -> Option<&dyn Reflect> {
+        split_path(path).try_fold(self, |value, name| value.field(name))
+    }
+
+    pub fn path_mut(&mut self, path: &str) -> Option<&mut dyn Reflect> {
+        split_path(path).try_fold(self, |value, name| value.field_mut(name))
+    }
+
+    pub fn get<T: Any>(&self, path: &str) -> Option<&T> {
+        self.path(path)?.as_any().downcast_ref()
+    }
+
+    pub fn set<T: Any>(&mut self, path: &str, value: T) -> Result<(), EcsErrors> {
+        let field = self
+            .path_mut(path)
+            .ok_or_else(|| EcsErrors::InvalidReflectPath(path.to_owned()))?;
+        let field_type = field.type_name();
+        let field = field.as_any_mut().downcast_mut::<T>().ok_or_else(|| {
+            EcsErrors::ReflectValue(format!(
+                "{path} is {field_type}, not {}",
+                type_name::<T>()
+            ))
+        })?;
+        *field = value;
+        Ok(())
+    }
+
+    pub fn set_path_value(&mut self, path: &str, text: &str) -> Result<(), EcsErrors> {
+        self.path_mut(path)
+            .ok_or_else(|| EcsErrors::InvalidReflectPath(path.to_owned()))?
+            .set_value(text)
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('.').filter(|name| !name.is_empty())
+}
+
+fn short_name(name: &str) -> &str {
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+macro_rules! reflect_value {
+    ($($ty:ty),*) => {
+        $(
+            impl Reflect for $ty {
+                fn type_fields() -> Vec<FieldInfo> {
+                    vec![]
+                }
+
+                fn type_name(&self) -> &'static str {
+                    type_name::<$ty>()
+                }
+
+                fn fields(&self) -> Vec<FieldInfo> {
+                    vec![]
+                }
+
+                fn field(&self, _name: &str) -> Option<&dyn Reflect> {
+                    None
+                }
+
+                fn field_mut(&mut self, _name: &str) -> Option<&mut dyn Reflect> {
+                    None
+                }
+
+                fn as_any(&self) -> &dyn Any {
+                    self
+                }
+
+                fn as_any_mut(&mut self) -> &mut dyn Any {
+                    self
+                }
+
+                fn value(&self) -> String {
+                    format!("{:?}", self)
+                }
+
+                fn set_value(&mut self, text: &str) -> Result<(), EcsErrors> {
+                    *self = text.parse().map_err(|e| {
+                        EcsErrors::ReflectValue(format!("{text:?} is not a {}: {e}", type_name::<$ty>()))
+                    })?;
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+reflect_value!(bool, char, u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64, String);
+
+impl Reflect for Entity {
+    fn type_fields() -> Vec<FieldInfo> {
+        vec![]
+    }
+
+    fn type_name(&self) -> &'static str {
+        type_name::<Entity>()
+    }
+
+    fn fields(&self) -> Vec<FieldInfo> {
+        vec![]
+    }
+
+    fn field(&self, _name: &str) -> Option<&dyn Reflect> {
+        None
+    }
+
+    fn field_mut(&mut self, _name: &str) -> Option<&mut dyn Reflect> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn value(&self) -> String {
+        format!("Entity({})", self.0)
+    }
+
+    fn set_value(&mut self, text: &str) -> Result<(), EcsErrors> {
+        self.0.set_value(text)
+    }
+}
+
+fn index_field(name: &str) -> Option<usize> {
+    name.parse().ok()
+}
+
+fn list_value<'v>(items: impl Iterator<Item = &'v dyn Reflect>) -> String {
+    let items: Vec<String> = items.map(|item| item.value()).collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// Elements are fields named by their index, like `"scale.0"`. They are not
+/// listed in `fields`, which only describes named fields.
+impl<T: Reflect, const N: usize> Reflect for [T; N] {
+    fn type_fields() -> Vec<FieldInfo> {
+        vec![]
+    }
+
+    fn type_name(&self) -> &'static str {
+        type_name::<Self>()
+    }
+
+    fn fields(&self) -> Vec<FieldInfo> {
+        vec![]
+    }
+
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        self.get(index_field(name)?).map(|item| item as &dyn Reflect)
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        self.get_mut(index_field(name)?).map(|item| item as &mut dyn Reflect)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn value(&self) -> String {
+        list_value(self.iter().map(|item| item as &dyn Reflect))
+    }
+}
+
+/// Elements are fields named by their index, as for arrays.
+impl<T: Reflect> Reflect for Vec<T> {
+    fn type_fields() -> Vec<FieldInfo> {
+        vec![]
+    }
+
+    fn type_name(&self) -> &'static str {
+        type_name::<Self>()
+    }
+
+    fn fields(&self) -> Vec<FieldInfo> {
+        vec![]
+    }
+
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        self.get(index_field(name)?).map(|item| item as &dyn Reflect)
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        self.get_mut(index_field(name)?).map(|item| item as &mut dyn Reflect)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn value(&self) -> String {
+        list_value(self.iter().map(|item| item as &dyn Reflect))
+    }
+}
+
+/// The value of `Some` is field `"0"`, `None` has no fields.
+impl<T: Reflect> Reflect for Option<T> {
+    fn type_fields() -> Vec<FieldInfo> {
+        vec![]
+    }
+
+    fn type_name(&self) -> &'static str {
+        type_name::<Self>()
+    }
+
+    fn fields(&self) -> Vec<FieldInfo> {
+        vec![]
+    }
+
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        match (name, self) {
+            ("0", Some(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        match (name, self) {
+            ("0", Some(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn value(&self) -> String {
+        match self {
+            Some(value) => format!("Some({})", value.value()),
+            None => "None".to_owned(),
+        }
+    }
+}
+
+type ReflectFn = for<'p> fn(&'p World, &Entity) -> Option<Ref<'p, dyn Reflect>>;
+type ReflectMutFn = for<'p> fn(&'p World, &Entity) -> Option<RefMut<'p, dyn Reflect>>;
+type ReflectResourceFn = for<'p> fn(&'p Resources) -> Option<Ref<'p, dyn Reflect>>;
+type ReflectResourceMutFn = for<'p> fn(&'p Resources) -> Option<RefMut<'p, dyn Reflect>>;
+
+fn reflect_component<'p, T: Component + Reflect>(world: &'p World, entity: &Entity) -> Option<Ref<'p, dyn Reflect>> {
+    let pool = world.entity_manager().component_manager.pool(&TypeId::of::<T>())?;
+    let pool = pool.as_any().downcast_ref::<RefCell<CompPool<T>>>()?;
+    Ref::filter_map(pool.borrow(), |pool| {
+        pool.data.get(entity.0)?.as_ref().map(|c| c as &dyn Reflect)
+    })
+    .ok()
+}
+
+fn reflect_component_mut<'p, T: Component + Reflect>(
+    world: &'p World,
+    entity: &Entity,
+) -> Option<RefMut<'p, dyn Reflect>> {
+    let pool = world.entity_manager().component_manager.pool(&TypeId::of::<T>())?;
+    let pool = pool.as_any().downcast_ref::<RefCell<CompPool<T>>>()?;
+    RefMut::filter_map(pool.borrow_mut(), |pool| {
+        pool.data.get(entity.0)?.as_ref()?;
+        pool.get_mut(entity.0).ok().map(|c| c as &mut dyn Reflect)
+    })
+    .ok()
+}
+
+fn reflect_resource<T: Reflect>(resources: &Resources) -> Option<Ref<'_, dyn Reflect>> {
+    let resource = resources.try_get::<T>()?;
+    Some(Ref::map(resource.borrow(), |r| r.get::<T>() as &dyn Reflect))
+}
+
+fn reflect_resource_mut<T: Reflect>(resources: &Resources) -> Option<RefMut<'_, dyn Reflect>> {
+    let resource = resources.try_get::<T>()?;
+    Some(RefMut::map(resource.borrow_mut(), |r| r.get_mut::<T>() as &mut dyn Reflect))
+}
+
+/// Reflection data of a registered type.
+pub struct ReflectType {
+    pub type_name: &'static str,
+    pub fields: Vec<FieldInfo>,
+    reflect: ReflectFn,
+    reflect_mut: ReflectMutFn,
+}
+
+pub struct ReflectResourceType {
+    pub type_name: &'static str,
+    pub fields: Vec<FieldInfo>,
+    reflect: ReflectResourceFn,
+    reflect_mut: ReflectResourceMutFn,
+}
+
+/// Component and resource types that can be inspected by name, keyed by
+/// `TypeId`.
+#[derive(Default)]
+pub struct ReflectRegistry {
+    components: HashMap<TypeId, ReflectType>,
+    resources: HashMap<TypeId, ReflectResourceType>,
+    /// Registration order, used when listing types.
+    component_order: Vec<TypeId>,
+    resource_order: Vec<TypeId>,
+}
+
+impl ReflectRegistry {
+    pub fn register_component<T: Component + Reflect>(&mut self) {
+        let id = TypeId::of::<T>();
+        if !self.components.contains_key(&id) {
+            self.component_order.push(id);
+        }
+        self.components.insert(
+            id,
+            ReflectType {
+                type_name: type_name::<T>(),
+                fields: T::type_fields(),
+                reflect: reflect_component::<T>,
+                reflect_mut: reflect_component_mut::<T>,
+            },
+        );
+    }
+
+    pub fn register_resource<T: Reflect>(&mut self) {
+        let id = TypeId::of::<T>();
+        if !self.resources.contains_key(&id) {
+            self.resource_order.push(id);
+        }
+        self.resources.insert(
+            id,
+            ReflectResourceType {
+                type_name: type_name::<T>(),
+                fields: T::type_fields(),
+                reflect: reflect_resource::<T>,
+                reflect_mut: reflect_resource_mut::<T>,
+            },
+        );
+    }
+
+    pub fn component(&self, id: &TypeId) -> Option<&ReflectType> {
+        self.components.get(id)
+    }
+
+    pub fn resource(&self, id: &TypeId) -> Option<&ReflectResourceType> {
+        self.resources.get(id)
+    }
+
+    /// Finds a component by its full type name or by the name without path.
+    pub fn component_by_name(&self, name: &str) -> Option<&ReflectType> {
+        self.component_order
+            .iter()
+            .map(|id| &self.components[id])
+            .find(|t| t.type_name == name || short_name(t.type_name) == name)
+    }
+
+    pub fn resource_by_name(&self, name: &str) -> Option<&ReflectResourceType> {
+        self.resource_order
+            .iter()
+            .map(|id| &self.resources[id])
+            .find(|t| t.type_name == name || short_name(t.type_name) == name)
+    }
+
+    pub fn components(&self) -> impl Iterator<Item = &ReflectType> {
+        self.component_order.iter().map(|id| &self.components[id])
+    }
+
+    pub fn resources(&self) -> impl Iterator<Item = &ReflectResourceType> {
+        self.resource_order.iter().map(|id| &self.resources[id])
+    }
+}
+
+impl<'a> World<'a> {
+    pub fn register_reflect_component<T: Component + Reflect>(&mut self) {
+        self.reflection.register_component::<T>();
+        info!("Component {} registered for reflection", type_name::<T>());
+    }
+
+    pub fn register_reflect_resource<T: Reflect>(&mut self) {
+        self.reflection.register_resource::<T>();
+        info!("Resource {} registered for reflection", type_name::<T>());
+    }
+
+    pub fn reflect_registry(&self) -> &ReflectRegistry {
+        &self.reflection
+    }
+
+    /// Names of the reflected components `entity` has, in registration order.
+    pub fn reflected_components(&self, entity: &Entity) -> Vec<&'static str> {
+        self.reflection
+            .components()
+            .filter(|t| (t.reflect)(self, entity).is_some())
+            .map(|t| t.type_name)
+            .collect()
+    }
+
+    /// Component `type_name` of `entity`, where `type_name` is the full type
+    /// name or the name without path.
+    pub fn get_component_reflect(&self, entity: &Entity, type_name: &str) -> Option<Ref<'_, dyn Reflect>> {
+        (self.reflection.component_by_name(type_name)?.reflect)(self, entity)
+    }
+
+    /// Mutable version of `get_component_reflect`. The component counts as
+    /// changed for change detection.
+    pub fn get_component_reflect_mut(
+        &self,
+        entity: &Entity,
+        type_name: &str,
+    ) -> Option<RefMut<'_, dyn Reflect>> {
+        (self.reflection.component_by_name(type_name)?.reflect_mut)(self, entity)
+    }
+
+    pub fn get_resource_reflect(&self, type_name: &str) -> Option<Ref<'_, dyn Reflect>> {
+        (self.reflection.resource_by_name(type_name)?.reflect)(self.resources())
+    }
+
+    pub fn get_resource_reflect_mut(&self, type_name: &str) -> Option<RefMut<'_, dyn Reflect>> {
+        (self.reflection.resource_by_name(type_name)?.reflect_mut)(self.resources())
+    }
+}
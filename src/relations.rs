@@ -0,0 +1,289 @@
+use std::{any::type_name, cell::Ref, marker::PhantomData};
+
+use log::info;
+
+use crate::{
+    components::{
+        clone::{map_component, MapFn},
+        comp_pool::CompPool,
+        component_manager::ComponentManager,
+        Component,
+    },
+    entities::{entity_manager::EntityManager, Entity, MapEntities},
+    errors::EcsErrors,
+    snapshot::SnapshotRegistry,
+};
+
+/// What happens to the source side of a relation when its target is removed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CleanupPolicy {
+    /// Only the pair pointing at the removed target is dropped.
+    RemoveRelation,
+    /// Every entity relating to the removed target is removed as well.
+    RemoveSource,
+}
+
+pub trait Relation: 'static {
+    const ON_TARGET_REMOVED: CleanupPolicy = CleanupPolicy::RemoveRelation;
+
+    /// Registers `Relations<Self>` for snapshots once the relation is used.
+    /// `#[derive(Relation)]` fills it in for `Clone` types.
+    fn snapshot_fn() -> Option<SnapshotFn>
+    where
+        Self: Sized,
+    {
+        None
+    }
+}
+
+pub type SnapshotFn = fn(&mut SnapshotRegistry);
+
+fn register_snapshot<R: Relation + Clone>(registry: &mut SnapshotRegistry) {
+    registry.register_component::<Relations<R>>();
+}
+
+/// Lets `#[derive(Relation)]` pick up `Clone` the same way `CloneProbe`
+/// does for components.
+pub struct SnapshotProbe<R>(PhantomData<R>);
+
+impl<R> SnapshotProbe<R> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+pub trait SnapshotViaClone {
+    fn snapshot_fn(&self) -> Option<SnapshotFn>;
+}
+
+impl<R: Relation + Clone> SnapshotViaClone for &SnapshotProbe<R> {
+    fn snapshot_fn(&self) -> Option<SnapshotFn> {
+        Some(register_snapshot::<R>)
+    }
+}
+
+pub trait SnapshotViaNothing {
+    fn snapshot_fn(&self) -> Option<SnapshotFn>;
+}
+
+impl<R> SnapshotViaNothing for SnapshotProbe<R> {
+    fn snapshot_fn(&self) -> Option<SnapshotFn> {
+        None
+    }
+}
+
+/// Lets go of an entity that is about to be removed and returns the entities
+/// that have to be removed along with it.
+pub type ReleaseFn = fn(&mut EntityManager, &Entity) -> Vec<Entity>;
+
+fn release_target<R: Relation>(entity_manager: &mut EntityManager, target: &Entity) -> Vec<Entity> {
+    entity_manager.release_relation_target::<R>(target)
+}
+
+/// All targets an entity relates to through `R`, with the value of each pair.
+/// Stored as a regular component, so every relation type takes one bit of
+/// the entity signature regardless of how many targets it has.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Relations<R: Relation> {
+    pairs: Vec<(Entity, R)>,
+}
+
+/// The cleanup of `R` comes with the pool, so relations restored from
+/// snapshots, scenes or binary data get it as well.
+impl<R: Relation> Component for Relations<R> {
+    fn map_fn() -> Option<MapFn> {
+        Some(map_component::<Self>)
+    }
+
+    fn release_fn() -> Option<ReleaseFn> {
+        Some(release_target::<R>)
+    }
+}
+
+impl<R: Relation> MapEntities for Relations<R> {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        self.pairs.iter_mut().for_each(|(target, _)| *target = map(*target));
+    }
+}
+
+impl<R: Relation> Relations<R> {
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &R)> {
+        self.pairs.iter().map(|(target, relation)| (*target, relation))
+    }
+
+    pub fn targets(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.pairs.iter().map(|(target, _)| *target)
+    }
+
+    pub fn get(&self, target: &Entity) -> Option<&R> {
+        self.pairs
+            .iter()
+            .find(|(t, _)| t == target)
+            .map(|(_, relation)| relation)
+    }
+
+    pub fn contains(&self, target: &Entity) -> bool {
+        self.get(target).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+pub struct RelationQuery<'a, R: Relation> {
+    component_manager: &'a ComponentManager<'a>,
+    relation: PhantomData<R>,
+}
+
+impl<'a, R: Relation> RelationQuery<'a, R> {
+    pub fn new(component_manager: &'a ComponentManager<'a>) -> Self {
+        Self {
+            component_manager,
+            relation: PhantomData,
+        }
+    }
+
+    fn pool(&self) -> Option<Ref<'a, CompPool<Relations<R>>>> {
+        self.component_manager.get_components::<Relations<R>>().ok()
+    }
+
+    pub fn get(&self, source: &Entity) -> Option<Ref<'a, Relations<R>>> {
+        let pool = self.pool()?;
+        Ref::filter_map(pool, |pool| pool.data.get(source.0)?.as_ref()).ok()
+    }
+
+    pub fn contains(&self, source: &Entity, target: &Entity) -> bool {
+        self.get(source)
+            .is_some_and(|relations| relations.contains(target))
+    }
+
+    pub fn targets_of(&self, source: &Entity) -> Vec<Entity> {
+        self.get(source)
+            .map(|relations| relations.targets().collect())
+            .unwrap_or_default()
+    }
+
+    /// Entities that relate to `target` through `R`.
+    pub fn sources_of(&self, target: &Entity) -> Vec<Entity> {
+        self.pairs()
+            .into_iter()
+            .filter(|(_, t)| t == target)
+            .map(|(source, _)| source)
+            .collect()
+    }
+
+    /// Every `(source, target)` pair of `R`, i.e. a wildcard match on both sides.
+    pub fn pairs(&self) -> Vec<(Entity, Entity)> {
+        let Some(pool) = self.pool() else {
+            return vec![];
+        };
+
+        pool.iter()
+            .enumerate()
+            .filter_map(|(id, relations)| relations.as_ref().map(|r| (Entity(id), r)))
+            .flat_map(|(source, relations)| relations.targets().map(move |t| (source, t)))
+            .collect()
+    }
+}
+
+impl<'a> EntityManager<'a> {
+    fn has_relations<R: Relation>(&self, source: &Entity) -> bool {
+        self.component_manager
+            .get_components::<Relations<R>>()
+            .is_ok_and(|pool| pool.data.get(source.0).is_some_and(|r| r.is_some()))
+    }
+
+    /// Relates `source` to `target` through `R`, replacing the value of an
+    /// already existing pair.
+    pub(crate) fn add_relation<R: Relation>(
+        &mut self,
+        source: &Entity,
+        relation: R,
+        target: &Entity,
+    ) -> Result<(), EcsErrors> {
+        if !self.is_alive(source) {
+            return Err(EcsErrors::EntityDoesNotExist(source.0));
+        }
+        if !self.is_alive(target) {
+            return Err(EcsErrors::EntityDoesNotExist(target.0));
+        }
+
+        if self.has_relations::<R>(source) {
+            let mut pool = self.component_manager.get_components_mut::<Relations<R>>()?;
+            let relations = pool.get_mut(source.0)?;
+            match relations.pairs.iter_mut().find(|(t, _)| t == target) {
+                Some(pair) => pair.1 = relation,
+                None => relations.pairs.push((*target, relation)),
+            }
+        } else {
+            self.add_component(
+                source,
+                Relations {
+                    pairs: vec![(*target, relation)],
+                },
+            )?;
+        }
+
+        info!(
+            "{} related to {} by {}",
+            self.label(source),
+            self.label(target),
+            type_name::<R>()
+        );
+        Ok(())
+    }
+
+    pub fn remove_relation<R: Relation>(
+        &mut self,
+        source: &Entity,
+        target: &Entity,
+    ) -> Result<(), EcsErrors> {
+        if !self.is_alive(source) {
+            return Err(EcsErrors::EntityDoesNotExist(source.0));
+        }
+        if !self.has_relations::<R>(source) {
+            return Ok(());
+        }
+
+        let is_empty = {
+            let mut pool = self.component_manager.get_components_mut::<Relations<R>>()?;
+            let relations = pool.get_mut(source.0)?;
+            relations.pairs.retain(|(t, _)| t != target);
+            relations.is_empty()
+        };
+        if is_empty {
+            self.remove_component::<Relations<R>>(source)?;
+        }
+
+        info!(
+            "{} no longer related to {} by {}",
+            self.label(source),
+            self.label(target),
+            type_name::<R>()
+        );
+        Ok(())
+    }
+
+    /// Applies `R::ON_TARGET_REMOVED` for a target that is about to be removed
+    /// and returns the sources that have to be removed along with it.
+    pub(crate) fn release_relation_target<R: Relation>(&mut self, target: &Entity) -> Vec<Entity> {
+        let sources = RelationQuery::<R>::new(&self.component_manager).sources_of(target);
+
+        match R::ON_TARGET_REMOVED {
+            CleanupPolicy::RemoveRelation => {
+                for source in sources {
+                    let _ = self.remove_relation::<R>(&source, target);
+                }
+                vec![]
+            }
+            CleanupPolicy::RemoveSource => sources,
+        }
+    }
+}
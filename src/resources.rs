@@ -1,19 +1,25 @@
 use std::{
-    any::{Any, TypeId},
-    collections::HashMap, cell::RefCell,
+    any::{type_name, Any, TypeId},
+    cell::RefCell,
 };
 
 pub struct Resource {
     data: Box<dyn Any>,
+    name: &'static str,
 }
 
 impl Resource {
     fn new<T: Any>(data: T) -> Self {
         Self {
             data: Box::new(data),
+            name: type_name::<T>(),
         }
     }
 
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
     pub fn get<T: Any>(&self) -> &T {
         self.data.downcast_ref().unwrap()
     }
@@ -23,33 +29,60 @@ impl Resource {
     }
 }
 
+/// Resources in the order their types were first added. Replacing a
+/// resource keeps its position.
 pub struct Resources {
-    data: HashMap<TypeId, RefCell<Resource>>,
+    data: Vec<(TypeId, RefCell<Resource>)>,
+}
+
+impl Default for Resources {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Resources {
     pub fn new() -> Self {
         Self {
-            data: HashMap::new(),
+            data: Vec::new(),
         }
     }
 
     pub fn add(&mut self, resource: impl Any) {
         let type_id = resource.type_id();
-        self.data.insert(type_id, RefCell::new(Resource::new(resource)));
+        let resource = RefCell::new(Resource::new(resource));
+        match self.index(&type_id) {
+            Some(index) => self.data[index].1 = resource,
+            None => self.data.push((type_id, resource)),
+        }
     }
 
     pub fn get<T: Any>(&self) -> &RefCell<Resource> {
-        let type_id = TypeId::of::<T>();
+        self.try_get::<T>()
+            .unwrap_or_else(|| panic!("Resource {} does not exist", type_name::<T>()))
+    }
 
-        self.data
-            .get(&type_id)
-            .unwrap()
+    pub fn try_get<T: Any>(&self) -> Option<&RefCell<Resource>> {
+        self.index(&TypeId::of::<T>()).map(|index| &self.data[index].1)
+    }
+
+    pub fn contains<T: Any>(&self) -> bool {
+        self.index(&TypeId::of::<T>()).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&TypeId, &RefCell<Resource>)> {
+        self.data.iter().map(|(id, resource)| (id, resource))
     }
 
     pub fn delete<T: Any>(&mut self) {
         let type_id = TypeId::of::<T>();
 
-        self.data.remove(&type_id);
+        if let Some(index) = self.index(&type_id) {
+            self.data.remove(index);
+        }
+    }
+
+    fn index(&self, type_id: &TypeId) -> Option<usize> {
+        self.data.iter().position(|(id, _)| id == type_id)
     }
 }
@@ -0,0 +1,300 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::VecDeque,
+    hash::{Hash, Hasher},
+};
+
+use log::{info, warn};
+
+use crate::{
+    components::{component_manager::ComponentManager, Component},
+    entities::Entity,
+    errors::EcsErrors,
+    resources::Resources,
+    snapshot::WorldSnapshot,
+    world::World,
+};
+
+/// FNV-1a hasher used for world state hashes. Unlike `DefaultHasher` its
+/// output is stable across platforms and compiler versions, so hashes can be
+/// compared between peers.
+pub struct StateHasher(u64);
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+/// Writes integers as little endian bytes, whatever the byte order of the
+/// target.
+macro_rules! write_le {
+    ($($method:ident: $ty:ty),*) => {
+        $(fn $method(&mut self, value: $ty) {
+            self.write(&value.to_le_bytes());
+        })*
+    };
+}
+
+impl Hasher for StateHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    write_le!(
+        write_u8: u8, write_u16: u16, write_u32: u32, write_u64: u64, write_u128: u128,
+        write_i8: i8, write_i16: i16, write_i32: i32, write_i64: i64, write_i128: i128
+    );
+
+    // Fixed width, so the hash does not depend on the pointer size.
+    fn write_usize(&mut self, value: usize) {
+        self.write(&(value as u64).to_le_bytes());
+    }
+
+    fn write_isize(&mut self, value: isize) {
+        self.write(&(value as i64).to_le_bytes());
+    }
+}
+
+type HashComponentFn = fn(&ComponentManager, &Entity, &mut StateHasher) -> bool;
+type HashResourceFn = fn(&Resources, &mut StateHasher) -> bool;
+
+fn hash_component<T: Component + Hash + 'static>(
+    manager: &ComponentManager,
+    entity: &Entity,
+    hasher: &mut StateHasher,
+) -> bool {
+    let Ok(pool) = manager.get_components::<T>() else {
+        return false;
+    };
+    match pool.data.get(entity.0).and_then(|c| c.as_ref()) {
+        Some(component) => {
+            component.hash(hasher);
+            true
+        }
+        None => false,
+    }
+}
+
+fn hash_resource<T: Hash + Any>(resources: &Resources, hasher: &mut StateHasher) -> bool {
+    match resources.try_get::<T>() {
+        Some(resource) => {
+            resource.borrow().get::<T>().hash(hasher);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Component and resource types that take part in world state hashes, in
+/// registration order.
+#[derive(Default)]
+pub struct StateHashRegistry {
+    components: Vec<(TypeId, HashComponentFn)>,
+    resources: Vec<(TypeId, HashResourceFn)>,
+}
+
+impl StateHashRegistry {
+    pub fn register_component<T: Component + Hash + 'static>(&mut self) {
+        if !self.components.iter().any(|(id, _)| *id == TypeId::of::<T>()) {
+            self.components.push((TypeId::of::<T>(), hash_component::<T>));
+        }
+    }
+
+    pub fn register_resource<T: Hash + Any>(&mut self) {
+        if !self.resources.iter().any(|(id, _)| *id == TypeId::of::<T>()) {
+            self.resources.push((TypeId::of::<T>(), hash_resource::<T>));
+        }
+    }
+
+    /// Hashes the ids of all alive entities, then every registered component
+    /// by entity id and every registered resource.
+    pub fn hash(&self, world: &World) -> u64 {
+        let entity_manager = world.entity_manager();
+        let component_manager = &entity_manager.component_manager;
+        let alive = entity_manager.alive_entities();
+
+        let mut hasher = StateHasher::default();
+        alive.len().hash(&mut hasher);
+        alive.iter().for_each(|entity| entity.0.hash(&mut hasher));
+
+        for (index, (_, hash)) in self.components.iter().enumerate() {
+            index.hash(&mut hasher);
+            for entity in alive.iter() {
+                entity.0.hash(&mut hasher);
+                hash(component_manager, entity, &mut hasher).hash(&mut hasher);
+            }
+        }
+        for (index, (_, hash)) in self.resources.iter().enumerate() {
+            index.hash(&mut hasher);
+            hash(world.resources(), &mut hasher).hash(&mut hasher);
+        }
+
+        hasher.finish()
+    }
+}
+
+struct RollbackFrame<I> {
+    frame: u64,
+    /// World state before the frame ran.
+    snapshot: WorldSnapshot,
+    input: I,
+    /// World state hash after the frame ran.
+    hash: u64,
+}
+
+/// Keeps the last `capacity` frames of a simulation so it can be rewound and
+/// re-simulated when a late input arrives.
+///
+/// A frame is one call of `schedule` with the frame input added as a
+/// resource. Frames are numbered from 0 by the rollback itself, independent
+/// of the world tick, which keeps advancing during re-simulation.
+pub struct Rollback<I: Clone + Any> {
+    capacity: usize,
+    frames: VecDeque<RollbackFrame<I>>,
+    frame: u64,
+    schedule: fn(&mut World),
+}
+
+impl<I: Clone + Any> Rollback<I> {
+    pub fn new(capacity: usize, schedule: fn(&mut World)) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            frames: VecDeque::new(),
+            frame: 0,
+            schedule,
+        }
+    }
+
+    /// Number of the next frame `advance` will run.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Oldest frame that can still be restored.
+    pub fn oldest_frame(&self) -> Option<u64> {
+        self.frames.front().map(|f| f.frame)
+    }
+
+    pub fn hash(&self, frame: u64) -> Option<u64> {
+        self.get(frame).map(|f| f.hash)
+    }
+
+    pub fn input(&self, frame: u64) -> Option<&I> {
+        self.get(frame).map(|f| &f.input)
+    }
+
+    /// Runs one frame with `input` and returns the world state hash after it.
+    pub fn advance(&mut self, world: &mut World, input: I) -> u64 {
+        let snapshot = world.snapshot();
+        if !snapshot.is_complete() {
+            warn!(
+                "Rollback frame {} skips components {:?} and resources {:?}",
+                self.frame,
+                snapshot.skipped_components(),
+                snapshot.skipped_resources()
+            );
+        }
+
+        world.add_resource(input.clone());
+        (self.schedule)(world);
+        let hash = world.state_hash();
+
+        self.frames.push_back(RollbackFrame {
+            frame: self.frame,
+            snapshot,
+            input,
+            hash,
+        });
+        if self.frames.len() > self.capacity {
+            self.frames.pop_front();
+        }
+        self.frame += 1;
+        hash
+    }
+
+    /// Puts the world back to the state before `frame` ran and forgets that
+    /// frame and all later ones.
+    pub fn restore(&mut self, world: &mut World, frame: u64) -> Result<(), EcsErrors> {
+        let index = self.index(frame)?;
+        world.restore(&self.frames[index].snapshot);
+        self.frames.truncate(index);
+        self.frame = frame;
+
+        info!("Rolled back to frame {frame}");
+        Ok(())
+    }
+
+    /// Replaces the input of `frame` and re-simulates up to the current frame
+    /// with the stored inputs of the later frames. Returns the new hash of the
+    /// latest frame.
+    pub fn correct_input(&mut self, world: &mut World, frame: u64, input: I) -> Result<u64, EcsErrors> {
+        let index = self.index(frame)?;
+        let mut inputs: Vec<I> = self.frames.iter().skip(index).map(|f| f.input.clone()).collect();
+        inputs[0] = input;
+
+        self.restore(world, frame)?;
+        let resimulated = inputs.len();
+        let hash = inputs
+            .into_iter()
+            .map(|input| self.advance(world, input))
+            .last()
+            .unwrap();
+
+        info!("Re-simulated {resimulated} frames from frame {frame}");
+        Ok(hash)
+    }
+
+    /// Compares the local hash of `frame` with one computed elsewhere.
+    pub fn verify(&self, frame: u64, hash: u64) -> Result<(), EcsErrors> {
+        let local = self.frames[self.index(frame)?].hash;
+        if local != hash {
+            return Err(EcsErrors::Desync {
+                frame,
+                local,
+                remote: hash,
+            });
+        }
+        Ok(())
+    }
+
+    fn get(&self, frame: u64) -> Option<&RollbackFrame<I>> {
+        self.index(frame).ok().map(|index| &self.frames[index])
+    }
+
+    fn index(&self, frame: u64) -> Result<usize, EcsErrors> {
+        self.frames
+            .iter()
+            .position(|f| f.frame == frame)
+            .ok_or(EcsErrors::FrameNotBuffered(frame))
+    }
+}
+
+impl<'a> World<'a> {
+    /// Registers `T` for snapshots and state hashes, which is what a
+    /// `Rollback` needs to rewind and verify it.
+    pub fn register_rollback_component<T: Component + Clone + Hash + 'static>(&mut self) {
+        self.register_snapshot_component::<T>();
+        self.state_hashers.register_component::<T>();
+        info!("Component {} registered for rollback", type_name::<T>());
+    }
+
+    pub fn register_rollback_resource<T: Clone + Hash + Any>(&mut self) {
+        self.register_snapshot_resource::<T>();
+        self.state_hashers.register_resource::<T>();
+        info!("Resource {} registered for rollback", type_name::<T>());
+    }
+
+    /// Platform independent hash of alive entity ids and all components and
+    /// resources registered for rollback.
+    pub fn state_hash(&self) -> u64 {
+        self.state_hashers.hash(self)
+    }
+}
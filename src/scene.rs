@@ -0,0 +1,345 @@
+use std::{
+    any::{type_name, Any},
+    collections::{BTreeMap, HashMap, HashSet},
+};
+
+use log::{info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    components::Component,
+    entities::{Entity, MapEntities},
+    errors::EcsErrors,
+    hierarchy::{Children, Parent},
+    query::Query,
+    world::World,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SceneFormat {
+    Json,
+    Ron,
+}
+
+/// Components and resources of a set of entities keyed by their registered
+/// names. Entity ids are the ones the entities had when the scene was saved.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Scene {
+    pub entities: Vec<SceneEntity>,
+    #[serde(default)]
+    pub resources: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SceneEntity {
+    pub entity: Entity,
+    pub components: BTreeMap<String, Value>,
+}
+
+impl Scene {
+    pub fn write(&self, format: SceneFormat) -> Result<String, EcsErrors> {
+        match format {
+            SceneFormat::Json => {
+                serde_json::to_string_pretty(self).map_err(|e| EcsErrors::SceneFormat(e.to_string()))
+            }
+            SceneFormat::Ron => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+                .map_err(|e| EcsErrors::SceneFormat(e.to_string())),
+        }
+    }
+
+    pub fn parse(data: &str, format: SceneFormat) -> Result<Self, EcsErrors> {
+        match format {
+            SceneFormat::Json => {
+                serde_json::from_str(data).map_err(|e| EcsErrors::SceneFormat(e.to_string()))
+            }
+            SceneFormat::Ron => ron::from_str(data).map_err(|e| EcsErrors::SceneFormat(e.to_string())),
+        }
+    }
+}
+
+/// Scene names of the hierarchy components, which loading rebuilds through
+/// `set_parent` instead of adding them as they are.
+pub(crate) const PARENT: &str = "secs::Parent";
+pub(crate) const CHILDREN: &str = "secs::Children";
+
+type SaveFn = fn(&Query, &Entity) -> Option<Result<Value, EcsErrors>>;
+type LoadFn = fn(&mut World, &Entity, Value, &HashMap<Entity, Entity>) -> Result<(), EcsErrors>;
+type SaveResourceFn = fn(&Query) -> Option<Result<Value, EcsErrors>>;
+type LoadResourceFn = fn(Value) -> Result<Box<dyn Any>, EcsErrors>;
+type AddResourceFn = fn(&mut World, Box<dyn Any>);
+
+fn to_value<T: Serialize>(value: &T) -> Result<Value, EcsErrors> {
+    serde_json::to_value(value).map_err(|e| EcsErrors::SceneFormat(e.to_string()))
+}
+
+pub(crate) fn from_value<T: DeserializeOwned>(value: Value) -> Result<T, EcsErrors> {
+    serde_json::from_value(value).map_err(|e| EcsErrors::SceneFormat(e.to_string()))
+}
+
+fn save_component<T: Component + Serialize + 'static>(
+    query: &Query,
+    entity: &Entity,
+) -> Option<Result<Value, EcsErrors>> {
+    let pool = query.components().try_get::<T>().ok()?;
+    let component = pool.data.get(entity.0)?.as_ref()?;
+    Some(to_value(component))
+}
+
+fn load_component<T: Component + DeserializeOwned + 'static>(
+    world: &mut World,
+    entity: &Entity,
+    value: Value,
+    _entity_map: &HashMap<Entity, Entity>,
+) -> Result<(), EcsErrors> {
+    world.entity_manager_mut().add_component(entity, from_value::<T>(value)?)?;
+    world.sync_systems();
+    Ok(())
+}
+
+fn load_mapped_component<T: Component + MapEntities + DeserializeOwned + 'static>(
+    world: &mut World,
+    entity: &Entity,
+    value: Value,
+    entity_map: &HashMap<Entity, Entity>,
+) -> Result<(), EcsErrors> {
+    let mut component = from_value::<T>(value)?;
+    component.map_entities(&mut |e| entity_map.get(&e).copied().unwrap_or(e));
+    world.entity_manager_mut().add_component(entity, component)?;
+    world.sync_systems();
+    Ok(())
+}
+
+fn save_resource<T: Serialize + Any>(query: &Query) -> Option<Result<Value, EcsErrors>> {
+    let resource = query.resources.try_get::<T>()?;
+    let value = to_value(resource.borrow().get::<T>());
+    Some(value)
+}
+
+fn load_resource<T: DeserializeOwned + Any>(value: Value) -> Result<Box<dyn Any>, EcsErrors> {
+    Ok(Box::new(from_value::<T>(value)?))
+}
+
+fn add_resource<T: Any>(world: &mut World, resource: Box<dyn Any>) {
+    world.add_resource(*resource.downcast::<T>().unwrap());
+}
+
+/// Components and resources that can be written to and read from scenes,
+/// keyed by names that stay stable across builds.
+pub struct SceneRegistry {
+    components: BTreeMap<String, (SaveFn, LoadFn)>,
+    resources: BTreeMap<String, (SaveResourceFn, LoadResourceFn, AddResourceFn)>,
+}
+
+impl Default for SceneRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self {
+            components: BTreeMap::new(),
+            resources: BTreeMap::new(),
+        };
+        registry.register_mapped_component::<Parent>(PARENT);
+        registry.register_mapped_component::<Children>(CHILDREN);
+        registry
+    }
+
+    pub fn register_component<T: Component + Serialize + DeserializeOwned + 'static>(
+        &mut self,
+        name: &str,
+    ) {
+        self.components
+            .insert(name.to_owned(), (save_component::<T>, load_component::<T>));
+        info!("Component {} registered for scenes as {name}", type_name::<T>());
+    }
+
+    /// Same as `register_component`, but `Entity` references inside the
+    /// component are remapped to the newly created entities on load.
+    pub fn register_mapped_component<
+        T: Component + MapEntities + Serialize + DeserializeOwned + 'static,
+    >(
+        &mut self,
+        name: &str,
+    ) {
+        self.components
+            .insert(name.to_owned(), (save_component::<T>, load_mapped_component::<T>));
+        info!("Component {} registered for scenes as {name}", type_name::<T>());
+    }
+
+    pub fn register_resource<T: Serialize + DeserializeOwned + Any>(&mut self, name: &str) {
+        self.resources
+            .insert(name.to_owned(), (save_resource::<T>, load_resource::<T>, add_resource::<T>));
+        info!("Resource {} registered for scenes as {name}", type_name::<T>());
+    }
+
+    pub fn save(&self, query: &Query, entities: &[Entity]) -> Result<Scene, EcsErrors> {
+        let mut scene = Scene::default();
+
+        for entity in entities {
+            let mut components = BTreeMap::new();
+            for (name, (save, _)) in self.components.iter() {
+                if let Some(value) = save(query, entity) {
+                    components.insert(name.clone(), value?);
+                }
+            }
+            scene.entities.push(SceneEntity {
+                entity: *entity,
+                components,
+            });
+        }
+
+        for (name, (save, _, _)) in self.resources.iter() {
+            if let Some(value) = save(query) {
+                scene.resources.insert(name.clone(), value?);
+            }
+        }
+
+        Ok(scene)
+    }
+
+    /// Adds the component saved as `name` to `entity`.
+    pub(crate) fn load_component(
+        &self,
+        world: &mut World,
+        name: &str,
+        entity: &Entity,
+        value: Value,
+        entity_map: &HashMap<Entity, Entity>,
+    ) -> Result<(), EcsErrors> {
+        let (_, load) = self
+            .components
+            .get(name)
+            .ok_or_else(|| EcsErrors::UnknownSceneType(name.to_owned()))?;
+        load(world, entity, value, entity_map)
+    }
+
+    /// Spawns one new entity per scene entity and returns them in scene order.
+    /// Nothing stays spawned and no resource is replaced if a component or
+    /// resource fails to load.
+    pub fn load(&self, world: &mut World, mut scene: Scene) -> Result<Vec<Entity>, EcsErrors> {
+        for name in scene.entities.iter().flat_map(|e| e.components.keys()) {
+            if !self.components.contains_key(name) {
+                return Err(EcsErrors::UnknownSceneType(name.clone()));
+            }
+        }
+        let mut seen = HashSet::new();
+        if let Some(scene_entity) = scene.entities.iter().find(|e| !seen.insert(e.entity)) {
+            return Err(EcsErrors::SceneFormat(format!(
+                "entity {} is listed twice",
+                scene_entity.entity.0
+            )));
+        }
+
+        let mut resources = vec![];
+        for (name, value) in std::mem::take(&mut scene.resources) {
+            let (_, load, add) = self
+                .resources
+                .get(&name)
+                .ok_or_else(|| EcsErrors::UnknownSceneType(name.clone()))?;
+            resources.push((load(value)?, add));
+        }
+
+        let entity_map: HashMap<Entity, Entity> = scene
+            .entities
+            .iter()
+            .map(|scene_entity| (scene_entity.entity, world.create_entity().finish_entity()))
+            .collect();
+
+        let spawned: Vec<Entity> = scene.entities.iter().map(|e| entity_map[&e.entity]).collect();
+        if let Err(err) = self.load_contents(world, scene, &entity_map) {
+            world.discard_spawned(&spawned);
+            return Err(err);
+        }
+
+        for (resource, add) in resources {
+            add(world, resource);
+        }
+        Ok(spawned)
+    }
+
+    fn load_contents(
+        &self,
+        world: &mut World,
+        scene: Scene,
+        entity_map: &HashMap<Entity, Entity>,
+    ) -> Result<(), EcsErrors> {
+        let mut parents = vec![];
+        for scene_entity in scene.entities {
+            let entity = entity_map[&scene_entity.entity];
+            for (name, value) in scene_entity.components {
+                match name.as_str() {
+                    CHILDREN => {}
+                    PARENT => parents.push((entity, from_value::<Parent>(value)?.0)),
+                    _ => {
+                        let (_, load) = self.components[&name];
+                        load(world, &entity, value, entity_map)?;
+                    }
+                }
+            }
+        }
+
+        for (child, parent) in parents {
+            match entity_map.get(&parent) {
+                Some(parent) => world.set_parent(&child, parent)?,
+                // Outside the scene, only parents the world already had are
+                // kept. The old id may have been reused for a loaded entity.
+                None if world.entity_manager().is_alive(&parent) && !entity_map.values().any(|e| *e == parent) => {
+                    world.set_parent(&child, &parent)?
+                }
+                None => warn!(
+                    "Dropping parent {} of {}, it does not exist",
+                    parent.0,
+                    world.entity_manager().label(&child)
+                ),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> World<'a> {
+    pub fn register_scene_component<T: Component + Serialize + DeserializeOwned + 'static>(
+        &mut self,
+        name: &str,
+    ) {
+        self.scenes.register_component::<T>(name);
+    }
+
+    pub fn register_scene_mapped_component<
+        T: Component + MapEntities + Serialize + DeserializeOwned + 'static,
+    >(
+        &mut self,
+        name: &str,
+    ) {
+        self.scenes.register_mapped_component::<T>(name);
+    }
+
+    pub fn register_scene_resource<T: Serialize + DeserializeOwned + Any>(&mut self, name: &str) {
+        self.scenes.register_resource::<T>(name);
+    }
+
+    /// Writes the registered components of `entities` and all registered
+    /// resources to a human readable scene.
+    pub fn save_scene(&self, entities: &[Entity], format: SceneFormat) -> Result<String, EcsErrors> {
+        let scene = self.scenes.save(&self.query(), entities)?;
+        info!("Saving scene with {} entities", scene.entities.len());
+        scene.write(format)
+    }
+
+    /// Spawns the entities of a scene as new entities, remapping entity
+    /// references between them. References to entities outside the scene
+    /// are kept as they are. Returns the new entities in scene order.
+    pub fn load_scene(&mut self, data: &str, format: SceneFormat) -> Result<Vec<Entity>, EcsErrors> {
+        let scene = Scene::parse(data, format)?;
+        info!("Loading scene with {} entities", scene.entities.len());
+
+        let scenes = std::mem::take(&mut self.scenes);
+        let spawned = scenes.load(self, scene);
+        self.scenes = scenes;
+        spawned
+    }
+}
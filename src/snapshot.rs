@@ -0,0 +1,259 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::{BTreeSet, HashMap, HashSet},
+};
+
+use log::{info, warn};
+
+use crate::{
+    components::{component_manager::ComponentManager, dynamic::ComponentId, Component},
+    entities::{
+        entity_manager::{EntityIdGenerator, EntityManager},
+        Disabled, Entity,
+    },
+    hierarchy::{Children, Parent},
+    name::Name,
+    resources::Resources,
+};
+
+type ClonePoolFn = fn(&ComponentManager) -> Option<Box<dyn Any>>;
+type RestorePoolFn = fn(&ComponentManager, &dyn Any);
+type CloneResourceFn = fn(&Resources) -> Option<Box<dyn Any>>;
+type RestoreResourceFn = fn(&mut Resources, Option<&dyn Any>);
+
+fn clone_pool<T: Component + Clone + 'static>(manager: &ComponentManager) -> Option<Box<dyn Any>> {
+    let pool = manager.get_components::<T>().ok()?;
+    Some(Box::new(pool.data.clone()))
+}
+
+fn restore_pool<T: Component + Clone + 'static>(manager: &ComponentManager, data: &dyn Any) {
+    let data = data.downcast_ref::<Vec<Option<T>>>().unwrap();
+    if let Ok(mut pool) = manager.get_components_mut::<T>() {
+        pool.replace(data.clone());
+    }
+}
+
+fn clone_resource<T: Clone + Any>(resources: &Resources) -> Option<Box<dyn Any>> {
+    let resource = resources.try_get::<T>()?;
+    let data = resource.borrow().get::<T>().clone();
+    Some(Box::new(data))
+}
+
+fn restore_resource<T: Clone + Any>(resources: &mut Resources, data: Option<&dyn Any>) {
+    match data {
+        Some(data) => resources.add(data.downcast_ref::<T>().unwrap().clone()),
+        None => resources.delete::<T>(),
+    }
+}
+
+/// Component and resource types that opted in to being captured by
+/// `World::snapshot`, in registration order. The components of the crate
+/// itself are registered from the start.
+pub struct SnapshotRegistry {
+    components: Vec<(TypeId, (ClonePoolFn, RestorePoolFn))>,
+    resources: Vec<(TypeId, (CloneResourceFn, RestoreResourceFn))>,
+}
+
+impl Default for SnapshotRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Complete copy of the entity and component state of a world at one point.
+///
+/// Component pools and resources are only captured for registered types, the
+/// rest is listed in `skipped_components` and `skipped_resources`. Dynamic
+/// components are always captured as bytes.
+pub struct WorldSnapshot {
+    id_generator: EntityIdGenerator,
+    signatures: Vec<u32>,
+    known_components: HashSet<TypeId>,
+    pools: HashMap<TypeId, Box<dyn Any>>,
+    dynamic_pools: HashMap<ComponentId, Vec<Option<Vec<u8>>>>,
+    resources: HashMap<TypeId, Box<dyn Any>>,
+    pub(crate) entities_to_add: BTreeSet<Entity>,
+    pub(crate) entities_to_remove: BTreeSet<Entity>,
+    skipped_components: Vec<&'static str>,
+    skipped_resources: Vec<&'static str>,
+}
+
+impl WorldSnapshot {
+    pub fn skipped_components(&self) -> &[&'static str] {
+        &self.skipped_components
+    }
+
+    pub fn skipped_resources(&self) -> &[&'static str] {
+        &self.skipped_resources
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.skipped_components.is_empty() && self.skipped_resources.is_empty()
+    }
+}
+
+impl SnapshotRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self {
+            components: vec![],
+            resources: vec![],
+        };
+        registry.register_component::<Parent>();
+        registry.register_component::<Children>();
+        registry.register_component::<Disabled>();
+        registry.register_component::<Name>();
+        registry
+    }
+
+    pub fn is_component_registered<T: Component + 'static>(&self) -> bool {
+        self.component(&TypeId::of::<T>()).is_some()
+    }
+
+    pub fn register_component<T: Component + Clone + 'static>(&mut self) {
+        let fns = (clone_pool::<T> as ClonePoolFn, restore_pool::<T> as RestorePoolFn);
+        match self.components.iter_mut().find(|(id, _)| *id == TypeId::of::<T>()) {
+            Some((_, registered)) => *registered = fns,
+            None => self.components.push((TypeId::of::<T>(), fns)),
+        }
+        info!("Component {} can be snapshot", type_name::<T>());
+    }
+
+    pub fn register_resource<T: Clone + Any>(&mut self) {
+        let fns = (clone_resource::<T> as CloneResourceFn, restore_resource::<T> as RestoreResourceFn);
+        match self.resources.iter_mut().find(|(id, _)| *id == TypeId::of::<T>()) {
+            Some((_, registered)) => *registered = fns,
+            None => self.resources.push((TypeId::of::<T>(), fns)),
+        }
+        info!("Resource {} can be snapshot", type_name::<T>());
+    }
+
+    pub fn snapshot(&self, entity_manager: &EntityManager, resources: &Resources) -> WorldSnapshot {
+        let component_manager = &entity_manager.component_manager;
+
+        let mut pools = HashMap::new();
+        let mut skipped_components = vec![];
+        for comp_id in component_manager.component_ids() {
+            match self.component(comp_id) {
+                Some((clone, _)) => {
+                    if let Some(data) = clone(component_manager) {
+                        pools.insert(*comp_id, data);
+                    }
+                }
+                None => skipped_components.push(component_manager.component_name(comp_id)),
+            }
+        }
+
+        let dynamic_pools = component_manager
+            .dynamic_components()
+            .iter()
+            .filter_map(|info| Some((info.id, component_manager.get_dynamic(info.id).ok()?.values())))
+            .collect();
+
+        let mut captured_resources = HashMap::new();
+        let mut skipped_resources = vec![];
+        for (resource_id, resource) in resources.iter() {
+            if !self.resources.iter().any(|(id, _)| id == resource_id) {
+                skipped_resources.push(resource.borrow().name());
+            }
+        }
+        for (resource_id, (clone, _)) in self.resources.iter() {
+            if let Some(data) = clone(resources) {
+                captured_resources.insert(*resource_id, data);
+            }
+        }
+
+        skipped_components.sort();
+        skipped_resources.sort();
+
+        WorldSnapshot {
+            id_generator: entity_manager.id_generator().clone(),
+            signatures: entity_manager.entity_component_signatures.clone(),
+            known_components: component_manager.component_bit_masks.keys().copied().collect(),
+            pools,
+            dynamic_pools,
+            resources: captured_resources,
+            entities_to_add: BTreeSet::new(),
+            entities_to_remove: BTreeSet::new(),
+            skipped_components,
+            skipped_resources,
+        }
+    }
+
+    /// Puts entities, signatures, registered pools and resources back to the
+    /// snapshot state. Pools of types registered after the snapshot was taken
+    /// are emptied, skipped pools lose the components of entities whose
+    /// restored signature lacks them.
+    pub fn restore(
+        &self,
+        snapshot: &WorldSnapshot,
+        entity_manager: &mut EntityManager,
+        resources: &mut Resources,
+    ) {
+        entity_manager.set_id_generator(snapshot.id_generator.clone());
+        entity_manager.entity_component_signatures = snapshot.signatures.clone();
+
+        let component_manager = &mut entity_manager.component_manager;
+        let comp_ids: Vec<TypeId> = component_manager.component_ids().to_vec();
+        let mut skipped = 0;
+        for comp_id in comp_ids {
+            if let Some(data) = snapshot.pools.get(&comp_id) {
+                let (_, restore) = self.component(&comp_id).unwrap();
+                restore(component_manager, data.as_ref());
+            } else {
+                // Skipped pools keep their data only where the restored
+                // signature still holds the component.
+                let mask = *component_manager.component_bit_masks.get(&comp_id).unwrap();
+                let known = snapshot.known_components.contains(&comp_id);
+                skipped |= mask;
+                let pool = component_manager.get_pool_mut(&comp_id).unwrap();
+                for id in 0..pool.get_size() {
+                    let kept = snapshot.signatures.get(id).is_some_and(|s| s & mask == mask);
+                    if !known || !kept {
+                        pool.remove_any(&Entity(id));
+                    }
+                }
+            }
+        }
+
+        let dynamic_ids: Vec<ComponentId> = component_manager.dynamic_components().iter().map(|i| i.id).collect();
+        for id in dynamic_ids {
+            let Ok(mut pool) = component_manager.get_dynamic_mut(id) else {
+                continue;
+            };
+            let restored = match snapshot.dynamic_pools.get(&id) {
+                Some(values) => pool.replace(values),
+                None => pool.replace(&[]),
+            };
+            // Values of another layout can't come back, so the pool is
+            // emptied and handled like a skipped one below.
+            if let Err(err) = restored {
+                warn!("{} not restored: {err}", component_manager.component_name_by_id(id));
+                let _ = pool.replace(&[]);
+                skipped |= id.mask();
+            }
+        }
+
+        // Skipped pools can't bring back components removed since the
+        // snapshot, so their bits go wherever the slot is empty.
+        let component_manager = &entity_manager.component_manager;
+        for (id, signature) in entity_manager.entity_component_signatures.iter_mut().enumerate() {
+            let bits = *signature & skipped;
+            for bit in (0..u32::BITS).filter(|bit| bits & (1 << bit) != 0) {
+                if !component_manager.pool_by_id(ComponentId(bit)).is_some_and(|pool| pool.has(&Entity(id))) {
+                    *signature &= !(1 << bit);
+                }
+            }
+        }
+
+        for (resource_id, (_, restore)) in self.resources.iter() {
+            restore(resources, snapshot.resources.get(resource_id).map(|r| r.as_ref()));
+        }
+    }
+
+    fn component(&self, comp_id: &TypeId) -> Option<&(ClonePoolFn, RestorePoolFn)> {
+        self.components
+            .iter()
+            .find(|(id, _)| id == comp_id)
+            .map(|(_, fns)| fns)
+    }
+}
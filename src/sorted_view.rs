@@ -0,0 +1,134 @@
+use std::{borrow::Borrow, collections::BTreeSet, marker::PhantomData};
+
+use crate::{
+    components::Component,
+    entities::{Disabled, Entity},
+    errors::EcsErrors,
+    query::{EntityQuery, Query},
+};
+
+impl<'a> EntityQuery<'a> {
+    /// Matching entities having `T`, ordered by the `K` each `T` borrows as,
+    /// equal keys by id. `K` can be `T` itself for `Ord` components.
+    pub fn sorted_by_key<T, K>(self) -> Vec<Entity>
+    where
+        T: Component + Borrow<K> + 'static,
+        K: Ord + ?Sized,
+    {
+        self.try_sorted_by_key::<T, K>().unwrap()
+    }
+
+    pub fn try_sorted_by_key<T, K>(self) -> Result<Vec<Entity>, EcsErrors>
+    where
+        T: Component + Borrow<K> + 'static,
+        K: Ord + ?Sized,
+    {
+        let component_manager = self.component_manager;
+        let pool = component_manager.get_components::<T>()?;
+        let mut entities = self.with_component::<T>().get();
+        // Stable, so entities with equal keys stay in id order.
+        entities.sort_by(|a, b| {
+            let [a, b] = [a, b].map(|e| pool.data[e.0].as_ref().unwrap().borrow());
+            a.cmp(b)
+        });
+        Ok(entities)
+    }
+}
+
+/// Entities having `T`, kept ordered like `EntityQuery::sorted_by_key`.
+///
+/// `refresh` only re-sorts the entities whose `T` was written to or removed
+/// since the previous refresh, so keeping a view around is cheaper than
+/// sorting every frame. Held by whoever needs the order, e.g. a system.
+pub struct SortedView<T, K> {
+    order: BTreeSet<(K, Entity)>,
+    /// Key every entity is stored under in `order`, by id.
+    keys: Vec<Option<K>>,
+    /// Tick of the last refresh, `None` before the first one.
+    refreshed: Option<u64>,
+    component: PhantomData<T>,
+}
+
+impl<T, K> Default for SortedView<T, K> {
+    fn default() -> Self {
+        Self {
+            order: BTreeSet::new(),
+            keys: vec![],
+            refreshed: None,
+            component: PhantomData,
+        }
+    }
+}
+
+impl<T, K> SortedView<T, K>
+where
+    T: Component + Borrow<K> + 'static,
+    K: Ord + Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Entities as of the last refresh, in key order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Entity> + '_ {
+        self.order.iter().map(|(_, entity)| *entity)
+    }
+
+    pub fn entities(&self) -> Vec<Entity> {
+        self.iter().collect()
+    }
+
+    /// Moves the entities whose `T` changed, or that were disabled or
+    /// enabled, since the previous refresh into place. Disabled entities are
+    /// left out like in every other query.
+    pub fn refresh(&mut self, query: &Query) {
+        self.try_refresh(query).unwrap()
+    }
+
+    pub fn try_refresh(&mut self, query: &Query) -> Result<(), EcsErrors> {
+        let pool = query.components().try_get::<T>()?;
+        // A pool swapped for a shorter one, e.g. by a snapshot restore, has
+        // no change ticks left for the ids it lost.
+        for id in pool.data.len()..self.keys.len() {
+            if let Some(old) = self.keys[id].take() {
+                self.order.remove(&(old, Entity(id)));
+            }
+        }
+        self.keys.resize(pool.data.len(), None);
+
+        let since = self.refreshed.unwrap_or(0);
+        let disabled = query.entity_manager.disabled_mask();
+        let signatures = &query.entity_manager.entity_component_signatures;
+        let toggled: Vec<usize> = query
+            .components()
+            .try_get::<Disabled>()
+            .map(|pool| pool.iter_changed(since).collect())
+            .unwrap_or_default();
+        let ids: BTreeSet<usize> = pool.iter_changed(since).chain(toggled).collect();
+
+        for id in ids.range(..self.keys.len()).copied() {
+            let enabled = signatures.get(id).is_some_and(|signature| signature & disabled == 0);
+            let key = pool.data[id].as_ref().filter(|_| enabled).map(|c| c.borrow());
+            if self.keys[id].as_ref() == key {
+                continue;
+            }
+            if let Some(old) = self.keys[id].take() {
+                self.order.remove(&(old, Entity(id)));
+            }
+            if let Some(key) = key {
+                self.order.insert((key.clone(), Entity(id)));
+                self.keys[id] = Some(key.clone());
+            }
+        }
+        self.refreshed = Some(query.tick());
+        Ok(())
+    }
+}
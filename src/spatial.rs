@@ -0,0 +1,610 @@
+use std::{
+    collections::{BTreeSet, BinaryHeap, HashMap},
+    marker::PhantomData,
+};
+
+use log::{info, warn};
+
+use crate::{
+    command_buffer::CommandBuffer,
+    components::Component,
+    entities::{Disabled, Entity},
+    events::EventEmitter,
+    query::Query,
+    system::{System, SystemBuilder},
+    world::World,
+};
+
+pub type Point = [f32; 2];
+
+/// Component whose value places an entity in a `SpatialIndex`.
+pub trait SpatialPosition: Component + 'static {
+    fn position(&self) -> Point;
+}
+
+fn distance_squared(a: Point, b: Point) -> f32 {
+    let [dx, dy] = [a[0] - b[0], a[1] - b[1]];
+    dx * dx + dy * dy
+}
+
+/// Axis aligned box, bounds included.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub fn new(min: Point, max: Point) -> Self {
+        Self { min, max }
+    }
+
+    /// Smallest box holding the circle around `center`.
+    pub fn around(center: Point, radius: f32) -> Self {
+        Self {
+            min: [center[0] - radius, center[1] - radius],
+            max: [center[0] + radius, center[1] + radius],
+        }
+    }
+
+    pub fn contains(&self, point: Point) -> bool {
+        (self.min[0]..=self.max[0]).contains(&point[0]) && (self.min[1]..=self.max[1]).contains(&point[1])
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min[0] <= other.max[0]
+            && other.min[0] <= self.max[0]
+            && self.min[1] <= other.max[1]
+            && other.min[1] <= self.max[1]
+    }
+
+    /// Squared distance from `point` to the closest point of the box, 0 when
+    /// it is inside.
+    pub fn distance_squared(&self, point: Point) -> f32 {
+        let dx = (self.min[0] - point[0]).max(point[0] - self.max[0]).max(0.0);
+        let dy = (self.min[1] - point[1]).max(point[1] - self.max[1]).max(0.0);
+        dx * dx + dy * dy
+    }
+
+    fn center(&self) -> Point {
+        [(self.min[0] + self.max[0]) / 2.0, (self.min[1] + self.max[1]) / 2.0]
+    }
+
+    /// Quarters of the box, in the order of `quadrant`.
+    fn quarters(&self) -> [Aabb; 4] {
+        let [cx, cy] = self.center();
+        [
+            Aabb::new(self.min, [cx, cy]),
+            Aabb::new([cx, self.min[1]], [self.max[0], cy]),
+            Aabb::new([self.min[0], cy], [cx, self.max[1]]),
+            Aabb::new([cx, cy], self.max),
+        ]
+    }
+
+    fn quadrant(&self, point: Point) -> usize {
+        let [cx, cy] = self.center();
+        (point[0] >= cx) as usize + 2 * (point[1] >= cy) as usize
+    }
+}
+
+/// Storage answering proximity queries, kept in sync by `SpatialIndex`.
+pub trait SpatialStructure: 'static {
+    fn insert(&mut self, entity: Entity, point: Point);
+    /// `point` is where `entity` was inserted.
+    fn remove(&mut self, entity: Entity, point: Point);
+    /// Calls `f` for every entity inside `aabb`, in no particular order.
+    fn for_each_in(&self, aabb: &Aabb, f: &mut dyn FnMut(Entity, Point));
+    /// Up to `k` entities closest to `point`, closest first. Equally distant
+    /// entities are ordered by id.
+    fn nearest(&self, point: Point, k: usize) -> Vec<(f32, Entity)>;
+}
+
+#[derive(PartialEq)]
+struct Candidate(f32, Entity);
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+/// The `k` best candidates seen so far, worst on top.
+struct Nearest {
+    k: usize,
+    heap: BinaryHeap<Candidate>,
+}
+
+impl Nearest {
+    fn new(k: usize) -> Self {
+        Self {
+            k,
+            heap: BinaryHeap::with_capacity(k + 1),
+        }
+    }
+
+    fn offer(&mut self, distance: f32, entity: Entity) {
+        let candidate = Candidate(distance, entity);
+        if self.heap.len() < self.k {
+            self.heap.push(candidate);
+        } else if self.heap.peek().is_some_and(|worst| candidate < *worst) {
+            self.heap.pop();
+            self.heap.push(candidate);
+        }
+    }
+
+    /// Squared distance past which nothing can make it in anymore.
+    fn bound(&self) -> f32 {
+        match self.heap.peek() {
+            Some(worst) if self.heap.len() == self.k => worst.0,
+            _ => f32::INFINITY,
+        }
+    }
+
+    fn into_sorted(self) -> Vec<(f32, Entity)> {
+        self.heap.into_sorted_vec().into_iter().map(|c| (c.0, c.1)).collect()
+    }
+}
+
+/// Buckets entities by square cells of a fixed size. Cheap to update and
+/// best when entities are spread evenly and queries are about the size of a
+/// cell.
+pub struct UniformGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<(Entity, Point)>>,
+    len: usize,
+}
+
+impl UniformGrid {
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0.0, "Grid cell size has to be positive");
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    fn cell(&self, point: Point) -> (i32, i32) {
+        (
+            (point[0] / self.cell_size).floor() as i32,
+            (point[1] / self.cell_size).floor() as i32,
+        )
+    }
+
+    fn offer_cell(&self, cell: (i32, i32), point: Point, nearest: &mut Nearest) -> usize {
+        let Some(entries) = self.cells.get(&cell) else {
+            return 0;
+        };
+        for (entity, p) in entries {
+            nearest.offer(distance_squared(point, *p), *entity);
+        }
+        entries.len()
+    }
+}
+
+impl SpatialStructure for UniformGrid {
+    fn insert(&mut self, entity: Entity, point: Point) {
+        let cell = self.cell(point);
+        self.cells.entry(cell).or_default().push((entity, point));
+        self.len += 1;
+    }
+
+    fn remove(&mut self, entity: Entity, point: Point) {
+        let cell = self.cell(point);
+        let Some(entries) = self.cells.get_mut(&cell) else {
+            return;
+        };
+        if let Some(position) = entries.iter().position(|(e, _)| *e == entity) {
+            entries.swap_remove(position);
+            self.len -= 1;
+        }
+        if entries.is_empty() {
+            self.cells.remove(&cell);
+        }
+    }
+
+    fn for_each_in(&self, aabb: &Aabb, f: &mut dyn FnMut(Entity, Point)) {
+        let (x0, y0) = self.cell(aabb.min);
+        let (x1, y1) = self.cell(aabb.max);
+        let covered = (x1 as i64 - x0 as i64 + 1) * (y1 as i64 - y0 as i64 + 1);
+
+        let mut visit = |entries: &Vec<(Entity, Point)>| {
+            entries
+                .iter()
+                .filter(|(_, p)| aabb.contains(*p))
+                .for_each(|(e, p)| f(*e, *p))
+        };
+        // Boxes covering more cells than are occupied are cheaper to answer
+        // by looking at every occupied cell.
+        if covered > self.cells.len() as i64 {
+            self.cells.values().for_each(visit);
+        } else {
+            for x in x0..=x1 {
+                for y in y0..=y1 {
+                    if let Some(entries) = self.cells.get(&(x, y)) {
+                        visit(entries);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Searches rings of cells around `point` until the ring is further
+    /// away than the k-th best candidate.
+    fn nearest(&self, point: Point, k: usize) -> Vec<(f32, Entity)> {
+        let mut nearest = Nearest::new(k);
+        if k == 0 {
+            return vec![];
+        }
+
+        let (cx, cy) = self.cell(point);
+        let mut visited = 0;
+        let mut ring = 0;
+        while visited < self.len {
+            if ring > 0 && 8 * ring as usize > self.cells.len() {
+                // Sparse grid, take whatever is left in one go.
+                for (&(x, y), entries) in self.cells.iter() {
+                    if (x - cx).abs().max((y - cy).abs()) >= ring {
+                        entries
+                            .iter()
+                            .for_each(|(e, p)| nearest.offer(distance_squared(point, *p), *e));
+                    }
+                }
+                break;
+            }
+
+            if ring == 0 {
+                visited += self.offer_cell((cx, cy), point, &mut nearest);
+            } else {
+                for dx in -ring..=ring {
+                    visited += self.offer_cell((cx + dx, cy - ring), point, &mut nearest);
+                    visited += self.offer_cell((cx + dx, cy + ring), point, &mut nearest);
+                }
+                for dy in -ring + 1..ring {
+                    visited += self.offer_cell((cx - ring, cy + dy), point, &mut nearest);
+                    visited += self.offer_cell((cx + ring, cy + dy), point, &mut nearest);
+                }
+            }
+
+            // Anything not visited yet lies outside the searched square.
+            let size = self.cell_size;
+            let reach = (point[0] - (cx - ring) as f32 * size)
+                .min((cx + ring + 1) as f32 * size - point[0])
+                .min(point[1] - (cy - ring) as f32 * size)
+                .min((cy + ring + 1) as f32 * size - point[1]);
+            if nearest.bound() < reach * reach {
+                break;
+            }
+            ring += 1;
+        }
+        nearest.into_sorted()
+    }
+}
+
+const NODE_CAPACITY: usize = 8;
+const MAX_DEPTH: usize = 16;
+
+struct Node {
+    bounds: Aabb,
+    /// Only leaves hold entities.
+    entries: Vec<(Entity, Point)>,
+    children: Option<Box<[Node; 4]>>,
+}
+
+impl Node {
+    fn leaf(bounds: Aabb) -> Self {
+        Self {
+            bounds,
+            entries: vec![],
+            children: None,
+        }
+    }
+
+    fn insert(&mut self, entity: Entity, point: Point, depth: usize) {
+        if let Some(children) = self.children.as_mut() {
+            let quadrant = self.bounds.quadrant(point);
+            return children[quadrant].insert(entity, point, depth + 1);
+        }
+
+        self.entries.push((entity, point));
+        if self.entries.len() > NODE_CAPACITY && depth < MAX_DEPTH {
+            let mut children = Box::new(self.bounds.quarters().map(Node::leaf));
+            for (entity, point) in self.entries.drain(..) {
+                children[self.bounds.quadrant(point)].insert(entity, point, depth + 1);
+            }
+            self.children = Some(children);
+        }
+    }
+
+    fn remove(&mut self, entity: Entity, point: Point) -> bool {
+        let Some(children) = self.children.as_mut() else {
+            let Some(position) = self.entries.iter().position(|(e, _)| *e == entity) else {
+                return false;
+            };
+            self.entries.swap_remove(position);
+            return true;
+        };
+
+        // Quarters share their edges, and a grown root keeps entries on the
+        // max edge of the old root, so those can sit in a neighbouring quarter.
+        let quadrant = self.bounds.quadrant(point);
+        let removed = children[quadrant].remove(entity, point)
+            || children
+                .iter_mut()
+                .enumerate()
+                .filter(|(q, child)| *q != quadrant && child.bounds.contains(point))
+                .any(|(_, child)| child.remove(entity, point));
+        if !removed {
+            return false;
+        }
+        // Fold the children back in once they fit into one leaf again.
+        let all_leaves = children.iter().all(|c| c.children.is_none());
+        if all_leaves && children.iter().map(|c| c.entries.len()).sum::<usize>() <= NODE_CAPACITY {
+            let children = *self.children.take().unwrap();
+            self.entries = children.into_iter().flat_map(|c| c.entries).collect();
+        }
+        true
+    }
+
+    fn for_each_in(&self, aabb: &Aabb, f: &mut dyn FnMut(Entity, Point)) {
+        if !self.bounds.intersects(aabb) {
+            return;
+        }
+        self.entries
+            .iter()
+            .filter(|(_, p)| aabb.contains(*p))
+            .for_each(|(e, p)| f(*e, *p));
+        if let Some(children) = self.children.as_ref() {
+            children.iter().for_each(|child| child.for_each_in(aabb, f));
+        }
+    }
+
+    fn nearest(&self, point: Point, nearest: &mut Nearest) {
+        for (entity, p) in self.entries.iter() {
+            nearest.offer(distance_squared(point, *p), *entity);
+        }
+        let Some(children) = self.children.as_ref() else {
+            return;
+        };
+
+        let mut order: Vec<(f32, &Node)> = children
+            .iter()
+            .map(|child| (child.bounds.distance_squared(point), child))
+            .collect();
+        order.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (distance, child) in order {
+            // Equally distant entities can still win on id.
+            if distance > nearest.bound() {
+                break;
+            }
+            child.nearest(point, nearest);
+        }
+    }
+}
+
+/// Splits space into quarters where entities crowd, so it copes with
+/// clustered entities better than a grid. The root grows to take in entities
+/// outside of the initial bounds.
+pub struct QuadTree {
+    root: Node,
+}
+
+impl QuadTree {
+    pub fn new(bounds: Aabb) -> Self {
+        assert!(
+            bounds.max[0] > bounds.min[0] && bounds.max[1] > bounds.min[1],
+            "Quadtree bounds have to have an area"
+        );
+        Self { root: Node::leaf(bounds) }
+    }
+
+    /// Doubles the root towards `point`, the old root becoming one quarter.
+    fn grow(&mut self, point: Point) {
+        let Aabb { min, max } = self.root.bounds;
+        let [width, height] = [max[0] - min[0], max[1] - min[1]];
+        let (x0, x1, right) = if point[0] < min[0] {
+            (min[0] - width, max[0], 1)
+        } else {
+            (min[0], max[0] + width, 0)
+        };
+        let (y0, y1, top) = if point[1] < min[1] {
+            (min[1] - height, max[1], 2)
+        } else {
+            (min[1], max[1] + height, 0)
+        };
+
+        let bounds = Aabb::new([x0, y0], [x1, y1]);
+        let old = std::mem::replace(&mut self.root, Node::leaf(bounds));
+        let mut children = Box::new(bounds.quarters().map(Node::leaf));
+        children[right + top] = old;
+        self.root.children = Some(children);
+    }
+}
+
+impl SpatialStructure for QuadTree {
+    fn insert(&mut self, entity: Entity, point: Point) {
+        while !self.root.bounds.contains(point) {
+            self.grow(point);
+        }
+        self.root.insert(entity, point, 0);
+    }
+
+    fn remove(&mut self, entity: Entity, point: Point) {
+        self.root.remove(entity, point);
+    }
+
+    fn for_each_in(&self, aabb: &Aabb, f: &mut dyn FnMut(Entity, Point)) {
+        self.root.for_each_in(aabb, f);
+    }
+
+    fn nearest(&self, point: Point, k: usize) -> Vec<(f32, Entity)> {
+        let mut nearest = Nearest::new(k);
+        if k > 0 {
+            self.root.nearest(point, &mut nearest);
+        }
+        nearest.into_sorted()
+    }
+}
+
+/// Positions of every entity having `P`, held in `S` for proximity queries.
+/// Added as a resource by `World::add_spatial_index` and kept up to date by
+/// the `SpatialIndexing<P, S>` system.
+pub struct SpatialIndex<P: SpatialPosition, S: SpatialStructure> {
+    structure: S,
+    /// Indexed point of every entity, by id.
+    points: Vec<Option<Point>>,
+    position: PhantomData<P>,
+}
+
+impl<P: SpatialPosition, S: SpatialStructure> SpatialIndex<P, S> {
+    pub fn new(structure: S) -> Self {
+        Self {
+            structure,
+            points: vec![],
+            position: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.iter().all(|p| p.is_none())
+    }
+
+    /// Where `entity` was as of the last update.
+    pub fn position(&self, entity: &Entity) -> Option<Point> {
+        self.points.get(entity.0).copied().flatten()
+    }
+
+    /// Entities inside `aabb`, in id order.
+    pub fn within_aabb(&self, aabb: &Aabb) -> Vec<Entity> {
+        let mut found = vec![];
+        self.structure.for_each_in(aabb, &mut |entity, _| found.push(entity));
+        found.sort();
+        found
+    }
+
+    /// Entities at most `radius` away from `center`, in id order.
+    pub fn within_radius(&self, center: Point, radius: f32) -> Vec<Entity> {
+        let mut found = vec![];
+        self.structure.for_each_in(&Aabb::around(center, radius), &mut |entity, point| {
+            if distance_squared(center, point) <= radius * radius {
+                found.push(entity);
+            }
+        });
+        found.sort();
+        found
+    }
+
+    /// Up to `k` entities closest to `point`, closest first.
+    pub fn nearest(&self, point: Point, k: usize) -> Vec<Entity> {
+        self.structure.nearest(point, k).into_iter().map(|(_, e)| e).collect()
+    }
+
+    /// Moves every entity whose `P` was written to or removed, or that was
+    /// disabled or enabled, at or after `since`. Disabled entities are left
+    /// out like in every other query.
+    pub fn update(&mut self, query: &Query, since: u64) {
+        let positions = query.components().get::<P>();
+        if self.points.len() < positions.data.len() {
+            self.points.resize(positions.data.len(), None);
+        }
+
+        let disabled = query.entity_manager.disabled_mask();
+        let signatures = &query.entity_manager.entity_component_signatures;
+        let toggled: Vec<usize> = query
+            .components()
+            .try_get::<Disabled>()
+            .map(|pool| pool.iter_changed(since).collect())
+            .unwrap_or_default();
+        let ids: BTreeSet<usize> = positions.iter_changed(since).chain(toggled).collect();
+
+        for id in ids.range(..self.points.len()).copied() {
+            let enabled = signatures.get(id).is_some_and(|signature| signature & disabled == 0);
+            let point = positions.data[id].as_ref().filter(|_| enabled).map(|p| p.position());
+            let point = point.filter(|p| {
+                let finite = p.iter().all(|c| c.is_finite());
+                if !finite {
+                    let label = query.entity_manager.label(&Entity(id));
+                    warn!("{label} has a non finite position, left out of the spatial index");
+                }
+                finite
+            });
+            self.set(Entity(id), point);
+        }
+    }
+
+    fn set(&mut self, entity: Entity, point: Option<Point>) {
+        let old = self.points[entity.0];
+        if old == point {
+            return;
+        }
+        if let Some(old) = old {
+            self.structure.remove(entity, old);
+        }
+        if let Some(point) = point {
+            self.structure.insert(entity, point);
+        }
+        self.points[entity.0] = point;
+    }
+}
+
+/// Feeds the changes of `P` since its previous run into `SpatialIndex<P, S>`.
+pub struct SpatialIndexing<P: SpatialPosition, S: SpatialStructure> {
+    last_run: u64,
+    index: PhantomData<SpatialIndex<P, S>>,
+}
+
+impl<P: SpatialPosition, S: SpatialStructure> Default for SpatialIndexing<P, S> {
+    fn default() -> Self {
+        Self {
+            last_run: 0,
+            index: PhantomData,
+        }
+    }
+}
+
+impl<P: SpatialPosition, S: SpatialStructure> System for SpatialIndexing<P, S> {
+    fn action(
+        &mut self,
+        query: Query,
+        _entities: &[Entity],
+        _command_buffer: &mut CommandBuffer,
+        _emitter: EventEmitter,
+    ) {
+        let since = self.last_run;
+        self.last_run = query.tick();
+
+        let mut resource = query.resource_mut::<SpatialIndex<P, S>>();
+        resource.get_mut::<SpatialIndex<P, S>>().update(&query, since);
+        info!("Spatial index updated with positions changed since tick {since}");
+    }
+}
+
+impl<'a> World<'a> {
+    /// Indexes the entities having `P` in `structure`, added as the
+    /// `SpatialIndex<P, S>` resource. The `SpatialIndexing<P, S>` system
+    /// brings it up to date and is run through
+    /// `update_system::<SpatialIndexing<P, S>>()`.
+    pub fn add_spatial_index<P: SpatialPosition, S: SpatialStructure>(&mut self, structure: S) {
+        self.register_component::<P>();
+        self.add_resource(SpatialIndex::<P, S>::new(structure));
+
+        let system = SystemBuilder::new(self.get_component_signatures())
+            .with_action(SpatialIndexing::<P, S>::default())
+            .with_component::<P>()
+            .build();
+
+        self.add_system::<SpatialIndexing<P, S>>(system, true);
+    }
+}
@@ -3,6 +3,7 @@ use std::{
     any::{type_name, TypeId}, collections::HashMap
 };
 
+use crate::components::dynamic::ComponentId;
 use crate::events::EventEmitter;
 
 use crate::{command_buffer::CommandBuffer, components::Component, entities::Entity, query::Query, world::World};
@@ -42,6 +43,11 @@ impl<T: System> SystemBuilder<T> {
         self
     }
 
+    pub fn with_component_id(mut self, id: ComponentId) -> Self {
+        self.signature |= id.mask();
+        self
+    }
+
     pub fn build(self) -> impl InternalSystem {
         GameSystem {
             signature: self.signature,
@@ -57,8 +63,11 @@ pub trait InternalSystem {
     fn call(&mut self, world: &World) -> CommandBuffer;
     fn signature(&self) -> u32;
     fn name(&self) -> &str;
+    /// Entities currently matching the signature, in the order they joined.
+    fn entities(&self) -> &[Entity];
     fn add_entity(&mut self, entity: Entity);
     fn remove_entity(&mut self, entity: &Entity);
+    fn clear_entities(&mut self);
 }
 pub struct GameSystem<T: System> {
     pub name: String,
@@ -84,6 +93,10 @@ impl <T: System> InternalSystem for GameSystem<T>{
         &self.name
     }
 
+    fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
     fn add_entity(&mut self, entity: Entity) {
      self.entities.push(entity);   
     }
@@ -92,4 +105,8 @@ impl <T: System> InternalSystem for GameSystem<T>{
         self.entities.retain(|e| e.0 != entity.0);
     }
 
+    fn clear_entities(&mut self) {
+        self.entities.clear();
+    }
+
 }
\ No newline at end of file
@@ -1,6 +1,21 @@
 #[cfg(test)]
 use crate::world::World;
 
+/// Xorshift generator for the randomized tests, so every run replays the
+/// same sequence for a seed.
+#[cfg(test)]
+struct Rng(u64);
+
+#[cfg(test)]
+impl Rng {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
+
 #[test]
 fn create_entity() {
     let mut world = World::new();
@@ -11,8 +26,7 @@ fn create_entity() {
 #[cfg(test)]
 mod resources {
     use ecs_macro::Component;
-    use crate::components;
-    
+
     use crate::world::World;
     #[test]
     fn query_for_entities() {        
@@ -31,8 +45,31 @@ mod resources {
         world.update();
         world.add_component(&entity3, Size(99));
 
-        world.query().entities().with_component()
+        let entities = world.query().entities().with_component::<Location>().get();
+        assert_eq!(entities, vec![entity, entity2]);
 
+        let entities = world
+            .query()
+            .entities()
+            .with_component::<Location>()
+            .with_component::<Size>()
+            .get();
+        assert_eq!(entities, vec![entity]);
+
+        let query = world.query();
+        let location = query.components().get::<Location>();
+        let size = query.components().get::<Size>();
+        assert_eq!(location.get(entity2.0).unwrap().0, 11);
+        assert_eq!(location.get(entity.0).unwrap().1, 1);
+        assert_eq!(size.get(entity3.0).unwrap().0, 99);
+    }
+
+    #[test]
+    fn query_for_resource() {
+        let mut world = World::new();
+        world.add_resource(Fps(60));
+
+        assert_eq!(world.query().resource::<Fps>().get::<Fps>().0, 60);
     }
 
     #[test]
@@ -60,3 +97,3748 @@ mod resources {
 
     struct Fps(pub u32);
 }
+
+#[cfg(test)]
+mod hierarchy {
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::events::EventEmitter;
+    use crate::hierarchy::{Children, Parent};
+    use crate::query::Query;
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[test]
+    fn traverse_hierarchy() {
+        let mut world = World::new();
+
+        let tank = world.create_entity().finish_entity();
+        let turret = world.create_entity().finish_entity();
+        let barrel = world.create_entity().finish_entity();
+        let track = world.create_entity().finish_entity();
+        world.update();
+
+        world.set_parent(&turret, &tank).unwrap();
+        world.set_parent(&barrel, &turret).unwrap();
+        world.set_parent(&track, &tank).unwrap();
+
+        let query = world.query();
+        assert_eq!(query.parent(&barrel), Some(turret));
+        assert_eq!(query.children(&tank), vec![turret, track]);
+        assert_eq!(query.ancestors(&barrel), vec![turret, tank]);
+        assert_eq!(query.descendants(&tank), vec![turret, barrel, track]);
+    }
+
+    #[test]
+    fn reparenting_updates_children() {
+        let mut world = World::new();
+
+        let first = world.create_entity().finish_entity();
+        let second = world.create_entity().finish_entity();
+        let child = world.create_entity().finish_entity();
+        world.update();
+
+        world.set_parent(&child, &first).unwrap();
+        world.set_parent(&child, &second).unwrap();
+
+        assert!(!world.has_component::<Children>(&first));
+        assert_eq!(world.query().children(&second), vec![child]);
+
+        world.remove_parent(&child).unwrap();
+        assert!(!world.has_component::<Parent>(&child));
+        assert!(!world.has_component::<Children>(&second));
+    }
+
+    #[test]
+    fn removing_hierarchy_components_detaches_both_ends() {
+        let mut world = World::new();
+        let parent = world.create_entity().finish_entity();
+        let first = world.create_entity().finish_entity();
+        let second = world.create_entity().finish_entity();
+        world.update();
+        world.set_parent(&first, &parent).unwrap();
+        world.set_parent(&second, &parent).unwrap();
+
+        world.remove_component::<Parent>(&first);
+        assert_eq!(world.query().children(&parent), vec![second]);
+
+        world.remove_component::<Children>(&parent);
+        assert!(!world.has_component::<Children>(&parent));
+        assert_eq!(world.query().parent(&second), None);
+    }
+
+    struct Unlink(Entity, Entity);
+
+    impl System for Unlink {
+        fn action(&mut self, _: Query, _: &[Entity], commands: &mut CommandBuffer, _: EventEmitter) {
+            commands.remove_component::<Parent>(&self.0);
+            commands.remove_component::<Children>(&self.1);
+        }
+    }
+
+    #[test]
+    fn commands_and_bundles_detach_both_ends() {
+        let mut world = World::new();
+        let [root, middle, leaf, other] = [(); 4].map(|_| world.create_entity().finish_entity());
+        world.update();
+        world.set_parent(&middle, &root).unwrap();
+        world.set_parent(&leaf, &middle).unwrap();
+        world.set_parent(&other, &root).unwrap();
+
+        let system = SystemBuilder::new(world.get_component_signatures()).with_action(Unlink(other, middle)).build();
+        world.add_system::<Unlink>(system, false);
+        world.update_system::<Unlink>();
+        let query = world.query();
+        assert_eq!(query.children(&root), vec![middle]);
+        assert_eq!(query.parent(&other), None);
+        assert_eq!(query.parent(&leaf), None);
+
+        world.set_parent(&leaf, &middle).unwrap();
+        world.remove_bundle::<(Parent, Children)>(&middle);
+        let query = world.query();
+        assert!(query.children(&root).is_empty());
+        assert_eq!(query.parent(&leaf), None);
+        assert!(!world.has_component::<Children>(&root));
+    }
+
+    #[test]
+    fn hierarchy_components_are_added_through_set_parent() {
+        let mut world = World::new();
+        let [parent, first, second] = [(); 3].map(|_| world.create_entity().finish_entity());
+        world.update();
+
+        world.add_component(&first, Parent(parent));
+        assert_eq!(world.query().children(&parent), vec![first]);
+        world.add_component(&parent, Children(vec![second]));
+        assert_eq!(world.query().parent(&first), None);
+        assert_eq!(world.query().parent(&second), Some(parent));
+
+        let err = world.entity_manager_mut().insert_bundle(&first, Parent(second)).unwrap_err();
+        assert!(matches!(err, EcsErrors::HierarchyComponent(_)));
+        assert!(world.spawn().with(Children(vec![first])).try_commit().is_err());
+        assert_eq!(world.query().parent(&first), None);
+    }
+
+    #[test]
+    fn remove_parent_without_children() {
+        let mut world = World::new();
+        let parent = world.create_entity().finish_entity();
+        let child = world.create_entity().finish_entity();
+        world.update();
+        world.entity_manager_mut().add_component(&child, Parent(parent)).unwrap();
+
+        world.remove_parent(&child).unwrap();
+        assert!(!world.has_component::<Parent>(&child));
+    }
+
+    #[test]
+    fn reject_cycles() {
+        let mut world = World::new();
+
+        let parent = world.create_entity().finish_entity();
+        let child = world.create_entity().finish_entity();
+        world.update();
+
+        world.set_parent(&child, &parent).unwrap();
+
+        assert!(matches!(
+            world.set_parent(&parent, &child),
+            Err(EcsErrors::HierarchyCycle(_, _))
+        ));
+        assert!(world.set_parent(&parent, &parent).is_err());
+    }
+
+    #[test]
+    fn removing_parent_orphans_children() {
+        let mut world = World::new();
+
+        let grandparent = world.create_entity().finish_entity();
+        let parent = world.create_entity().finish_entity();
+        let child = world.create_entity().finish_entity();
+        world.update();
+
+        world.set_parent(&parent, &grandparent).unwrap();
+        world.set_parent(&child, &parent).unwrap();
+
+        world.remove_entity(&parent);
+        world.update();
+
+        let query = world.query();
+        assert_eq!(query.parent(&child), None);
+        assert!(query.children(&grandparent).is_empty());
+    }
+
+    #[test]
+    fn despawn_recursive_removes_subtree() {
+        let mut world = World::new();
+
+        let root = world.create_entity().finish_entity();
+        let child = world.create_entity().finish_entity();
+        let grandchild = world.create_entity().finish_entity();
+        world.update();
+
+        world.set_parent(&child, &root).unwrap();
+        world.set_parent(&grandchild, &child).unwrap();
+
+        world.despawn_recursive(&root);
+        world.update();
+
+        let mut reused: Vec<_> = (0..3)
+            .map(|_| world.create_entity().finish_entity().0)
+            .collect();
+        reused.sort();
+        assert_eq!(reused, vec![root.0, child.0, grandchild.0]);
+    }
+}
+
+#[cfg(test)]
+mod relations {
+    use ecs_macro::Relation;
+
+    use crate::errors::EcsErrors;
+    use crate::prefab::Prefab;
+    use crate::relations::{CleanupPolicy, Relation, Relations};
+    use crate::world::World;
+
+    #[derive(Relation, Clone)]
+    struct Likes(u32);
+
+    struct DockedAt;
+
+    impl Relation for DockedAt {
+        const ON_TARGET_REMOVED: CleanupPolicy = CleanupPolicy::RemoveSource;
+    }
+
+    #[test]
+    fn many_to_many_relations() {
+        let mut world = World::new();
+
+        let alice = world.create_entity().finish_entity();
+        let bob = world.create_entity().finish_entity();
+        let carol = world.create_entity().finish_entity();
+        world.update();
+
+        world.add_relation(&alice, Likes(1), &bob).unwrap();
+        world.add_relation(&alice, Likes(2), &carol).unwrap();
+        world.add_relation(&bob, Likes(3), &carol).unwrap();
+        world.add_relation(&alice, Likes(5), &bob).unwrap();
+
+        let query = world.query();
+        let likes = query.relations::<Likes>();
+        assert_eq!(likes.targets_of(&alice), vec![bob, carol]);
+        assert_eq!(likes.sources_of(&carol), vec![alice, bob]);
+        assert_eq!(likes.pairs(), vec![(alice, bob), (alice, carol), (bob, carol)]);
+        assert_eq!(likes.get(&alice).unwrap().get(&bob).unwrap().0, 5);
+        assert_eq!(query.entities().with_relation::<Likes>().get(), vec![alice, bob]);
+    }
+
+    #[test]
+    fn removing_last_pair_removes_component() {
+        let mut world = World::new();
+
+        let alice = world.create_entity().finish_entity();
+        let bob = world.create_entity().finish_entity();
+        world.update();
+
+        world.add_relation(&alice, Likes(1), &bob).unwrap();
+        assert!(world.has_relation::<Likes>(&alice, &bob));
+
+        world.remove_relation::<Likes>(&alice, &bob).unwrap();
+        assert!(!world.has_relation::<Likes>(&alice, &bob));
+        assert!(world.query().entities().with_relation::<Likes>().get().is_empty());
+    }
+
+    #[test]
+    fn relating_to_despawned_entities_fails() {
+        let mut world = World::new();
+
+        let alice = world.create_entity().finish_entity();
+        let bob = world.create_entity().finish_entity();
+        world.update();
+        world.remove_entity(&bob);
+        world.update();
+
+        assert!(matches!(
+            world.add_relation(&alice, Likes(1), &bob),
+            Err(EcsErrors::EntityDoesNotExist(id)) if id == bob.0
+        ));
+        assert!(!world.has_relation::<Likes>(&alice, &bob));
+    }
+
+    #[test]
+    fn clone_relations() {
+        let mut world = World::new();
+        world.register_clone::<Relations<Likes>>();
+
+        let alice = world.create_entity().finish_entity();
+        let bob = world.create_entity().finish_entity();
+        world.update();
+        world.add_relation(&alice, Likes(4), &bob).unwrap();
+
+        let copy = world.clone_entity(&alice);
+        let query = world.query();
+        assert_eq!(query.relations::<Likes>().get(&copy).unwrap().get(&bob).unwrap().0, 4);
+    }
+
+    #[test]
+    fn restore_relations_from_snapshot() {
+        let mut world = World::new();
+        let alice = world.create_entity().finish_entity();
+        let bob = world.create_entity().finish_entity();
+        world.update();
+        world.add_relation(&alice, Likes(1), &bob).unwrap();
+        let snapshot = world.snapshot();
+
+        world.remove_relation::<Likes>(&alice, &bob).unwrap();
+        world.restore(&snapshot);
+        assert!(world.has_relation::<Likes>(&alice, &bob));
+    }
+
+    #[test]
+    fn cleanup_when_target_is_removed() {
+        let mut world = World::new();
+
+        let station = world.create_entity().finish_entity();
+        let ship = world.create_entity().finish_entity();
+        let fan = world.create_entity().finish_entity();
+        world.update();
+
+        world.add_relation(&ship, DockedAt, &station).unwrap();
+        world.add_relation(&fan, Likes(1), &station).unwrap();
+        world.add_relation(&fan, Likes(2), &ship).unwrap();
+
+        world.remove_entity(&station);
+        world.update();
+
+        let query = world.query();
+        assert!(query.relations::<DockedAt>().pairs().is_empty());
+        assert!(query.relations::<Likes>().pairs().is_empty());
+
+        let mut reused = vec![
+            world.create_entity().finish_entity().0,
+            world.create_entity().finish_entity().0,
+        ];
+        reused.sort();
+        assert_eq!(reused, vec![station.0, ship.0]);
+    }
+
+    #[test]
+    fn cleanup_relations_restored_from_snapshot() {
+        let mut world = World::new();
+        world.register_snapshot_component::<Relations<Likes>>();
+        let alice = world.create_entity().finish_entity();
+        let bob = world.create_entity().finish_entity();
+        world.update();
+        world.entity_manager_mut().add_relation(&alice, Likes(1), &bob).unwrap();
+        let snapshot = world.snapshot();
+
+        let mut restored = World::new();
+        restored.register_component::<Relations<Likes>>();
+        restored.register_snapshot_component::<Relations<Likes>>();
+        restored.restore(&snapshot);
+        restored.remove_entity(&bob);
+        restored.update();
+        assert!(restored.query().relations::<Likes>().pairs().is_empty());
+    }
+
+    #[test]
+    fn remap_relations_in_prefabs() {
+        let mut world = World::new();
+        let outside = world.create_entity().finish_entity();
+        let tower = world.create_entity().finish_entity();
+        let gate = world.create_entity().finish_entity();
+        world.update();
+        world.set_parent(&gate, &tower).unwrap();
+        world.register_clone::<Relations<Likes>>();
+        world.add_relation(&tower, Likes(1), &gate).unwrap();
+        world.add_relation(&tower, Likes(2), &outside).unwrap();
+
+        let prefab = Prefab::from_entity(&world, &tower).unwrap();
+        let copy = world.instantiate(&prefab);
+
+        let query = world.query();
+        let copied_gate = query.children(&copy)[0];
+        assert_eq!(query.relations::<Likes>().targets_of(&copy), vec![copied_gate, outside]);
+    }
+}
+
+#[cfg(all(test, feature = "transform"))]
+mod transform {
+    use crate::entities::Entity;
+    use crate::reflect::Reflect;
+    use crate::transform::{
+        GlobalTransform, LocalTransform, Transform, Transform2d, Transform3d, TransformPropagation,
+    };
+    use crate::world::World;
+
+    #[test]
+    fn reflect_transforms() {
+        let mut transform = Transform2d::from_translation(1.0, 2.0);
+        let reflected = &mut transform as &mut dyn Reflect;
+        assert_eq!(reflected.get::<f32>("translation.1"), Some(&2.0));
+        reflected.set_path_value("scale.0", "3").unwrap();
+        assert_eq!(transform.scale, [3.0, 1.0]);
+    }
+
+    fn spawn_3d(world: &mut World, x: f32) -> Entity {
+        world
+            .create_entity()
+            .with_component(LocalTransform(Transform3d::from_translation(x, 0.0, 0.0)))
+            .with_component(GlobalTransform::<Transform3d>::default())
+            .finish_entity()
+    }
+
+    fn global_3d(world: &World, entity: &Entity) -> Transform3d {
+        world
+            .query()
+            .components()
+            .get::<GlobalTransform<Transform3d>>()
+            .get(entity.0)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn propagate_deep_hierarchy() {
+        let mut world = World::new();
+        world.add_transform_propagation::<Transform3d>();
+
+        let root = spawn_3d(&mut world, 1.0);
+        let mut parent = root;
+        let mut leaf = root;
+        for _ in 0..99 {
+            leaf = spawn_3d(&mut world, 1.0);
+            world.set_parent(&leaf, &parent).unwrap();
+            parent = leaf;
+        }
+        world.update();
+        world.update_system::<TransformPropagation<Transform3d>>();
+
+        assert_eq!(global_3d(&world, &leaf).translation, [100.0, 0.0, 0.0]);
+
+        world.update();
+        world.add_component(&root, LocalTransform(Transform3d::from_translation(11.0, 0.0, 0.0)));
+        world.update_system::<TransformPropagation<Transform3d>>();
+
+        assert_eq!(global_3d(&world, &leaf).translation, [110.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn propagate_through_entities_without_transforms() {
+        let mut world = World::new();
+        world.add_transform_propagation::<Transform3d>();
+
+        let root = spawn_3d(&mut world, 1.0);
+        let group = world.create_entity().finish_entity();
+        let leaf = spawn_3d(&mut world, 2.0);
+        world.set_parent(&group, &root).unwrap();
+        world.set_parent(&leaf, &group).unwrap();
+        world.update();
+        world.update_system::<TransformPropagation<Transform3d>>();
+
+        assert_eq!(global_3d(&world, &leaf).translation, [3.0, 0.0, 0.0]);
+
+        let other = spawn_3d(&mut world, 10.0);
+        world.update();
+        world.set_parent(&group, &other).unwrap();
+        world.update_system::<TransformPropagation<Transform3d>>();
+
+        assert_eq!(global_3d(&world, &leaf).translation, [12.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn fill_in_global_transforms_added_later() {
+        let mut world = World::new();
+        world.add_transform_propagation::<Transform3d>();
+
+        let root = world
+            .create_entity()
+            .with_component(LocalTransform(Transform3d::from_translation(4.0, 0.0, 0.0)))
+            .finish_entity();
+        let leaf = spawn_3d(&mut world, 1.0);
+        world.set_parent(&leaf, &root).unwrap();
+        world.update();
+        world.update_system::<TransformPropagation<Transform3d>>();
+        assert_eq!(global_3d(&world, &leaf).translation, [1.0, 0.0, 0.0]);
+
+        world.update();
+        world.add_component(&root, GlobalTransform::<Transform3d>::default());
+        world.update_system::<TransformPropagation<Transform3d>>();
+
+        assert_eq!(global_3d(&world, &root).translation, [4.0, 0.0, 0.0]);
+        assert_eq!(global_3d(&world, &leaf).translation, [5.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn propagate_rotation_and_scale() {
+        let mut world = World::new();
+        world.add_transform_propagation::<Transform2d>();
+
+        let parent = world
+            .create_entity()
+            .with_component(LocalTransform(Transform2d {
+                translation: [1.0, 1.0],
+                rotation: std::f32::consts::FRAC_PI_2,
+                scale: [2.0, 2.0],
+            }))
+            .with_component(GlobalTransform::<Transform2d>::default())
+            .finish_entity();
+        let child = world
+            .create_entity()
+            .with_component(LocalTransform(Transform2d::from_translation(1.0, 0.0)))
+            .with_component(GlobalTransform::<Transform2d>::default())
+            .finish_entity();
+        world.set_parent(&child, &parent).unwrap();
+        world.update();
+        world.update_system::<TransformPropagation<Transform2d>>();
+
+        let query = world.query();
+        let globals = query.components().get::<GlobalTransform<Transform2d>>();
+        let global = globals.get(child.0).unwrap().0;
+        assert!((global.translation[0] - 1.0).abs() < 1e-5);
+        assert!((global.translation[1] - 3.0).abs() < 1e-5);
+        assert_eq!(global.scale, [2.0, 2.0]);
+    }
+
+    #[test]
+    fn reparenting_moves_subtree() {
+        let mut world = World::new();
+        world.add_transform_propagation::<Transform3d>();
+
+        let left = spawn_3d(&mut world, -10.0);
+        let right = spawn_3d(&mut world, 10.0);
+        let child = spawn_3d(&mut world, 1.0);
+        let grandchild = spawn_3d(&mut world, 1.0);
+        world.set_parent(&child, &left).unwrap();
+        world.set_parent(&grandchild, &child).unwrap();
+        world.update();
+        world.update_system::<TransformPropagation<Transform3d>>();
+
+        assert_eq!(global_3d(&world, &grandchild).translation, [-8.0, 0.0, 0.0]);
+
+        world.update();
+        world.set_parent(&child, &right).unwrap();
+        world.update_system::<TransformPropagation<Transform3d>>();
+
+        assert_eq!(global_3d(&world, &grandchild).translation, [12.0, 0.0, 0.0]);
+
+        world.update();
+        world.remove_parent(&child).unwrap();
+        world.update_system::<TransformPropagation<Transform3d>>();
+
+        assert_eq!(global_3d(&world, &grandchild).translation, [2.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn only_changed_subtrees_are_walked() {
+        let mut world = World::new();
+        world.add_transform_propagation::<Transform3d>();
+
+        let changed = spawn_3d(&mut world, 1.0);
+        let untouched = spawn_3d(&mut world, 2.0);
+        world.update();
+        world.update_system::<TransformPropagation<Transform3d>>();
+
+        // Written behind the pool's back, so only a recompute would reset it.
+        world
+            .query()
+            .components()
+            .get_mut::<GlobalTransform<Transform3d>>()
+            .data[untouched.0] = Some(GlobalTransform(Transform3d::IDENTITY));
+
+        world.update();
+        world.add_component(&changed, LocalTransform(Transform3d::from_translation(5.0, 0.0, 0.0)));
+        world.update_system::<TransformPropagation<Transform3d>>();
+
+        assert_eq!(global_3d(&world, &changed).translation, [5.0, 0.0, 0.0]);
+        assert_eq!(global_3d(&world, &untouched), Transform3d::IDENTITY);
+    }
+}
+
+#[cfg(test)]
+mod snapshot {
+    use std::alloc::Layout;
+
+    use ecs_macro::Component;
+
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::events::EventEmitter;
+    use crate::query::Query;
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Health(u32);
+
+    #[derive(Component)]
+    struct Sprite;
+
+    #[derive(Clone)]
+    struct Score(u32);
+
+    struct Seed;
+
+    #[derive(Default, Clone)]
+    struct Seen(Vec<Entity>);
+
+    struct Collect;
+
+    impl System for Collect {
+        fn action(&mut self, query: Query, entities: &[Entity], _: &mut CommandBuffer, _: EventEmitter) {
+            query.resource_mut::<Seen>().get_mut::<Seen>().0 = entities.to_vec();
+        }
+    }
+
+    fn health(world: &World, entity: &Entity) -> Option<u32> {
+        let query = world.query();
+        let pool = query.components().get::<Health>();
+        pool.data.get(entity.0).and_then(|h| h.as_ref()).map(|h| h.0)
+    }
+
+    #[test]
+    fn restore_entities_components_and_resources() {
+        let mut world = World::new();
+        world.register_snapshot_component::<Health>();
+        world.register_snapshot_resource::<Score>();
+        world.add_resource(Score(10));
+
+        let first = world.create_entity().with_component(Health(100)).finish_entity();
+        let second = world.create_entity().with_component(Health(50)).finish_entity();
+        world.update();
+
+        let snapshot = world.snapshot();
+        assert!(snapshot.is_complete());
+
+        world.add_component(&first, Health(1));
+        world.remove_entity(&second);
+        world.update();
+        let third = world.create_entity().with_component(Health(7)).finish_entity();
+        world.update();
+        world.add_resource(Score(99));
+
+        world.restore(&snapshot);
+
+        assert_eq!(health(&world, &first), Some(100));
+        assert_eq!(health(&world, &second), Some(50));
+        assert_eq!(third, second);
+        assert_eq!(world.query().resource::<Score>().get::<Score>().0, 10);
+
+        let next = world.create_entity().finish_entity();
+        assert_eq!(next, Entity(2));
+    }
+
+    #[test]
+    fn report_types_without_snapshot_support() {
+        let mut world = World::new();
+        world.register_snapshot_component::<Health>();
+        world.add_resource(Seed);
+
+        world.create_entity().with_component(Health(1)).with_component(Sprite).finish_entity();
+        world.update();
+
+        let snapshot = world.snapshot();
+        assert!(!snapshot.is_complete());
+        assert_eq!(snapshot.skipped_components().len(), 1);
+        assert!(snapshot.skipped_components()[0].ends_with("Sprite"));
+        assert_eq!(snapshot.skipped_resources().len(), 1);
+        assert!(snapshot.skipped_resources()[0].ends_with("Seed"));
+    }
+
+    #[test]
+    fn restore_clears_skipped_components_of_restored_entities() {
+        let mut world = World::new();
+        world.register_snapshot_component::<Health>();
+        let kept = world.create_entity().with_component(Health(1)).with_component(Sprite).finish_entity();
+        world.update();
+        let snapshot = world.snapshot();
+
+        let ghost = world.create_entity().with_component(Health(2)).finish_entity();
+        world.update();
+        world.set_name(&ghost, "Ghost");
+        world.add_component(&ghost, Sprite);
+        world.update();
+
+        world.restore(&snapshot);
+        assert_eq!(world.find_by_name("Ghost"), None);
+        let query = world.query();
+        let sprites = query.components().get::<Sprite>();
+        assert!(sprites.data[kept.0].is_some());
+        assert!(sprites.data.get(ghost.0).is_none_or(|s| s.is_none()));
+    }
+
+    #[test]
+    fn restore_drops_bits_of_skipped_components_removed_since() {
+        let mut world = World::new();
+        let entity = world.create_entity().with_component(Sprite).finish_entity();
+        world.update();
+        let snapshot = world.snapshot();
+
+        world.remove_component::<Sprite>(&entity);
+        world.restore(&snapshot);
+        assert!(!world.has_component::<Sprite>(&entity));
+        assert!(world.query().entities().with_component::<Sprite>().get().is_empty());
+    }
+
+    #[test]
+    fn restore_disabled_child() {
+        let mut world = World::new();
+        let parent = world.create_entity().finish_entity();
+        let child = world.create_entity().finish_entity();
+        world.update();
+        world.set_parent(&child, &parent).unwrap();
+        world.disable_entity(&child);
+        let snapshot = world.snapshot();
+        assert!(snapshot.is_complete());
+
+        world.remove_parent(&child).unwrap();
+        world.enable_entity(&child);
+        world.update();
+        world.restore(&snapshot);
+
+        assert!(world.is_disabled(&child));
+        assert_eq!(world.query().parent(&child), Some(parent));
+        assert_eq!(world.query().children(&parent), vec![child]);
+    }
+
+    #[test]
+    fn restore_leaves_untouched_slots_unchanged() {
+        let mut world = World::new();
+        world.register_snapshot_component::<Health>();
+        let kept = world.create_entity().with_component(Health(1)).finish_entity();
+        world.create_entity().finish_entity();
+        world.update();
+        let snapshot = world.snapshot();
+
+        let extra = world.create_entity().with_component(Health(2)).finish_entity();
+        world.update();
+        let since = world.tick() + 1;
+        world.update();
+        world.restore(&snapshot);
+
+        let query = world.query();
+        let pool = query.components().get::<Health>();
+        assert_eq!(pool.iter_changed(since).collect::<Vec<_>>(), vec![kept.0, extra.0]);
+        assert!(!pool.added_since(kept.0, since));
+    }
+
+    #[test]
+    fn restore_dynamic_components() {
+        let mut world = World::new();
+        let mana = world.register_dynamic_component("mod::Mana", Layout::new::<u32>()).unwrap();
+        let entity = world.create_entity().finish_entity();
+        let other = world.create_entity().finish_entity();
+        world.add_dynamic_component(&entity, mana, &7u32.to_ne_bytes()).unwrap();
+        world.update();
+        let snapshot = world.snapshot();
+        assert!(snapshot.is_complete());
+
+        world.remove_component_by_id(&entity, mana);
+        world.update();
+        let since = world.tick();
+        world.restore(&snapshot);
+        assert!(world.has_component_id(&entity, mana));
+        let mut pool = world.entity_manager().component_manager.get_dynamic_mut(mana).unwrap();
+        assert_eq!(pool.get(entity.0).unwrap(), 7u32.to_ne_bytes());
+        assert!(pool.changed_since(entity.0, since) && pool.added_since(entity.0, since));
+        assert!(!pool.changed_since(other.0, since) && !pool.added_since(other.0, since));
+
+        assert!(matches!(pool.replace(&[None, Some(vec![1])]), Err(EcsErrors::DynamicComponentLayout(_))));
+        assert_eq!(pool.get(entity.0).unwrap(), 7u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn restore_system_membership() {
+        let mut world = World::new();
+        world.register_snapshot_component::<Health>();
+        world.register_component::<Health>();
+        world.add_resource(Seen::default());
+
+        let system = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Collect)
+            .with_component::<Health>()
+            .build();
+        world.add_system::<Collect>(system, true);
+
+        let kept = world.create_entity().with_component(Health(1)).finish_entity();
+        world.update();
+        let snapshot = world.snapshot();
+
+        world.remove_entity(&kept);
+        world.update();
+        world.update_system::<Collect>();
+        assert!(world.query().resource::<Seen>().get::<Seen>().0.is_empty());
+
+        world.restore(&snapshot);
+        world.update_system::<Collect>();
+        assert_eq!(world.query().resource::<Seen>().get::<Seen>().0, vec![kept]);
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod scene {
+    use ecs_macro::{Component, Relation};
+    use serde::{Deserialize, Serialize};
+
+    use crate::entities::{Entity, MapEntities};
+    use crate::errors::EcsErrors;
+    use crate::name::{Name, NameMode};
+    use crate::prefab::Prefab;
+    use crate::relations::Relations;
+    use crate::scene::SceneFormat;
+    use crate::world::World;
+
+    #[derive(Component, Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Component, Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+    struct Target(Entity);
+
+    impl MapEntities for Target {
+        fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+            self.0 = map(self.0);
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Level(u32);
+
+    fn register(world: &mut World) {
+        world.register_scene_component::<Position>("game::Position");
+        world.register_scene_mapped_component::<Target>("game::Target");
+        world.register_scene_resource::<Level>("game::Level");
+    }
+
+    fn round_trip(format: SceneFormat) {
+        let mut world = World::new();
+        register(&mut world);
+        world.add_resource(Level(3));
+
+        let hunter = world
+            .create_entity()
+            .with_component(Position { x: 1.0, y: 2.0 })
+            .finish_entity();
+        let prey = world
+            .create_entity()
+            .with_component(Position { x: 5.0, y: 6.0 })
+            .finish_entity();
+        world.update();
+        world.add_component(&hunter, Target(prey));
+        world.set_parent(&prey, &hunter).unwrap();
+
+        let data = world.save_scene(&[hunter, prey], format).unwrap();
+
+        let mut loaded = World::new();
+        register(&mut loaded);
+        loaded.create_entity().finish_entity();
+        loaded.update();
+
+        let spawned = loaded.load_scene(&data, format).unwrap();
+        assert_eq!(spawned, vec![Entity(1), Entity(2)]);
+
+        let query = loaded.query();
+        let positions = query.components().get::<Position>();
+        assert_eq!(*positions.get(2).unwrap(), Position { x: 5.0, y: 6.0 });
+        assert_eq!(query.components().get::<Target>().get(1).unwrap().0, Entity(2));
+        assert_eq!(query.parent(&Entity(2)), Some(Entity(1)));
+        assert_eq!(query.children(&Entity(1)), vec![Entity(2)]);
+        assert_eq!(*query.resource::<Level>().get::<Level>(), Level(3));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        round_trip(SceneFormat::Json);
+    }
+
+    #[test]
+    fn ron_round_trip() {
+        round_trip(SceneFormat::Ron);
+    }
+
+    #[test]
+    fn json_uses_registered_names() {
+        let mut world = World::new();
+        register(&mut world);
+        let entity = world
+            .create_entity()
+            .with_component(Position { x: 1.0, y: 2.0 })
+            .finish_entity();
+
+        let data = world.save_scene(&[entity], SceneFormat::Json).unwrap();
+        assert!(data.contains("\"game::Position\""));
+    }
+
+    #[test]
+    fn reject_unregistered_types() {
+        let mut world = World::new();
+        register(&mut world);
+        let entity = world
+            .create_entity()
+            .with_component(Position { x: 1.0, y: 2.0 })
+            .finish_entity();
+        let data = world.save_scene(&[entity], SceneFormat::Json).unwrap();
+
+        let mut loaded = World::new();
+        assert!(matches!(
+            loaded.load_scene(&data, SceneFormat::Json),
+            Err(EcsErrors::UnknownSceneType(name)) if name == "game::Position"
+        ));
+    }
+
+    #[test]
+    fn drop_parents_outside_the_scene() {
+        let mut world = World::new();
+        register(&mut world);
+        let hunter = world.create_entity().finish_entity();
+        let prey = world.create_entity().with_component(Position { x: 5.0, y: 6.0 }).finish_entity();
+        world.update();
+        world.set_parent(&prey, &hunter).unwrap();
+        let data = world.save_scene(&[prey], SceneFormat::Json).unwrap();
+
+        let mut loaded = World::new();
+        register(&mut loaded);
+        let spawned = loaded.load_scene(&data, SceneFormat::Json).unwrap();
+        assert_eq!(spawned, vec![hunter]);
+        assert_eq!(loaded.query().parent(&spawned[0]), None);
+    }
+
+    #[test]
+    fn failed_load_removes_spawned_entities() {
+        let mut world = World::new();
+        register(&mut world);
+        let hunter = world.create_entity().finish_entity();
+        let prey = world.create_entity().with_component(Position { x: 5.0, y: 6.0 }).finish_entity();
+        world.update();
+        world.set_parent(&prey, &hunter).unwrap();
+        let data = world.save_scene(&[hunter, prey], SceneFormat::Json).unwrap();
+        let data = data.replace("5.0", "\"five\"");
+
+        let mut loaded = World::new();
+        register(&mut loaded);
+        assert!(matches!(loaded.load_scene(&data, SceneFormat::Json), Err(EcsErrors::SceneFormat(_))));
+        assert!(!loaded.entity_manager().is_alive(&Entity(0)));
+        assert!(!loaded.entity_manager().is_alive(&Entity(1)));
+    }
+
+    #[test]
+    fn failed_load_keeps_resources() {
+        let mut world = World::new();
+        register(&mut world);
+        world.add_resource(Level(3));
+        world.create_entity().with_component(Position { x: 5.0, y: 6.0 }).finish_entity();
+        world.update();
+        let data = world.save_scene(&[Entity(0)], SceneFormat::Json).unwrap();
+        let data = data.replace("5.0", "\"five\"");
+
+        let mut loaded = World::new();
+        register(&mut loaded);
+        loaded.add_resource(Level(1));
+        assert!(loaded.load_scene(&data, SceneFormat::Json).is_err());
+        assert_eq!(*loaded.query().resource::<Level>().get::<Level>(), Level(1));
+    }
+
+    #[test]
+    fn reject_duplicate_scene_entities() {
+        let mut world = World::new();
+        register(&mut world);
+        let hunter = world.create_entity().with_component(Position { x: 5.0, y: 6.0 }).finish_entity();
+        world.update();
+        let data = world.save_scene(&[hunter, hunter], SceneFormat::Json).unwrap();
+
+        let mut loaded = World::new();
+        register(&mut loaded);
+        assert!(matches!(loaded.load_scene(&data, SceneFormat::Json), Err(EcsErrors::SceneFormat(_))));
+        assert!(loaded.entity_manager().alive_entities().is_empty());
+    }
+
+    #[derive(Relation, Serialize, Deserialize, Clone)]
+    struct Follows;
+
+    #[test]
+    fn remap_relations_on_load() {
+        let mut world = World::new();
+        world.register_scene_mapped_component::<Relations<Follows>>("game::Follows");
+        let leader = world.spawn().commit();
+        let follower = world.spawn().commit();
+        world.add_relation(&follower, Follows, &leader).unwrap();
+        let data = world.save_scene(&[leader, follower], SceneFormat::Json).unwrap();
+
+        let loaded = world.load_scene(&data, SceneFormat::Json).unwrap();
+        assert_eq!(world.query().relations::<Follows>().targets_of(&loaded[1]), vec![loaded[0]]);
+    }
+
+    #[test]
+    fn duplicate_name_rolls_back_load() {
+        let mut world = World::new();
+        world.register_scene_component::<Name>("secs::Name");
+        let named = world.spawn().with(Name("Player".to_owned())).commit();
+        let data = world.save_scene(&[named], SceneFormat::Json).unwrap();
+
+        world.set_name_mode(NameMode::Unique).unwrap();
+        assert!(matches!(world.load_scene(&data, SceneFormat::Json), Err(EcsErrors::DuplicateName(_))));
+        world.update();
+        assert_eq!(world.entity_manager().alive_entities(), vec![named]);
+        assert_eq!(world.find_all_by_name("Player"), vec![named]);
+    }
+
+    #[test]
+    fn prefab_from_scene_fragment() {
+        let mut world = World::new();
+        register(&mut world);
+        let hunter = world
+            .create_entity()
+            .with_component(Position { x: 1.0, y: 2.0 })
+            .finish_entity();
+        let prey = world.create_entity().finish_entity();
+        world.update();
+        world.add_component(&hunter, Target(prey));
+        world.set_parent(&prey, &hunter).unwrap();
+        let data = world.save_scene(&[hunter, prey], SceneFormat::Json).unwrap();
+
+        let prefab = Prefab::from_scene(&data, SceneFormat::Json).unwrap();
+        let first = world.instantiate(&prefab);
+        let second = world.instantiate(&prefab);
+
+        let query = world.query();
+        for root in [first, second] {
+            let child = query.children(&root)[0];
+            assert_eq!(query.components().get::<Target>().get(root.0).unwrap().0, child);
+            assert_eq!(*query.components().get::<Position>().get(root.0).unwrap(), Position { x: 1.0, y: 2.0 });
+        }
+    }
+}
+
+#[cfg(test)]
+mod binary {
+    use ecs_macro::Component;
+
+    use super::Rng;
+    use crate::binary::{BinaryComponent, BinaryReader, BinaryWriter};
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::hierarchy::{Children, Parent};
+    use crate::name::NameMode;
+    use crate::world::World;
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Position(f32, f32);
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Name(String);
+
+    impl BinaryComponent for Position {
+        fn encode(&self, writer: &mut BinaryWriter) {
+            writer.write_f32(self.0);
+            writer.write_f32(self.1);
+        }
+
+        fn decode(reader: &mut BinaryReader) -> Result<Self, EcsErrors> {
+            Ok(Position(reader.read_f32()?, reader.read_f32()?))
+        }
+    }
+
+    impl BinaryComponent for Name {
+        fn encode(&self, writer: &mut BinaryWriter) {
+            writer.write_str(&self.0);
+        }
+
+        fn decode(reader: &mut BinaryReader) -> Result<Self, EcsErrors> {
+            Ok(Name(reader.read_str()?.to_owned()))
+        }
+    }
+
+    #[derive(Component)]
+    struct PackedPosition(Position);
+
+    impl BinaryComponent for PackedPosition {
+        const VERSION: u32 = 1;
+
+        fn encode(&self, writer: &mut BinaryWriter) {
+            self.0.encode(writer);
+        }
+
+        fn decode(reader: &mut BinaryReader) -> Result<Self, EcsErrors> {
+            Ok(PackedPosition(Position::decode(reader)?))
+        }
+    }
+
+    fn registered_world<'a>() -> World<'a> {
+        let mut world = World::new();
+        world.register_binary_component::<Position>("game::Position");
+        world.register_binary_component::<Name>("game::Name");
+        world
+    }
+
+    fn server_world<'a>() -> World<'a> {
+        let mut world = registered_world();
+        let player = world
+            .create_entity()
+            .with_component(Position(1.0, 2.0))
+            .with_component(Name("player".to_owned()))
+            .finish_entity();
+        let removed = world.create_entity().finish_entity();
+        let sword = world.create_entity().with_component(Position(3.0, 4.0)).finish_entity();
+        world.update();
+        world.set_parent(&sword, &player).unwrap();
+        world.remove_entity(&removed);
+        world.update();
+        world
+    }
+
+    #[test]
+    fn round_trip() {
+        let server = server_world();
+        let bytes = server.encode_binary().unwrap();
+
+        let mut client = registered_world();
+        client.create_entity().with_component(Position(9.0, 9.0)).finish_entity();
+        client.update();
+        client.decode_binary(&bytes).unwrap();
+
+        let query = client.query();
+        assert_eq!(*query.components().get::<Position>().get(0).unwrap(), Position(1.0, 2.0));
+        assert_eq!(query.components().get::<Name>().get(0).unwrap().0, "player");
+        assert_eq!(*query.components().get::<Position>().get(2).unwrap(), Position(3.0, 4.0));
+        assert_eq!(query.parent(&Entity(2)), Some(Entity(0)));
+        assert_eq!(client.encode_binary().unwrap(), bytes);
+
+        let reused = client.create_entity().finish_entity();
+        assert_eq!(reused, Entity(1));
+    }
+
+    #[test]
+    fn reject_schema_mismatch() {
+        let bytes = server_world().encode_binary().unwrap();
+
+        let mut client = World::new();
+        client.register_binary_component::<Position>("game::Position");
+
+        assert!(matches!(
+            client.decode_binary(&bytes),
+            Err(EcsErrors::SchemaMismatch { .. })
+        ));
+
+        let mut client = World::new();
+        client.register_binary_component::<PackedPosition>("game::Position");
+        client.register_binary_component::<Name>("game::Name");
+        assert!(matches!(
+            client.decode_binary(&bytes),
+            Err(EcsErrors::SchemaMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn reject_other_format_version() {
+        let mut bytes = server_world().encode_binary().unwrap();
+        bytes[4] = 99;
+
+        assert!(matches!(
+            registered_world().decode_binary(&bytes),
+            Err(EcsErrors::UnsupportedFormatVersion(99))
+        ));
+    }
+
+    #[test]
+    fn fuzz_truncated_input() {
+        let bytes = server_world().encode_binary().unwrap();
+
+        let mut client = registered_world();
+        let kept = client.create_entity().with_component(Position(7.0, 7.0)).finish_entity();
+        client.update();
+
+        for len in 0..bytes.len() {
+            assert!(client.decode_binary(&bytes[..len]).is_err(), "prefix of {len} bytes decoded");
+        }
+        assert_eq!(*client.query().components().get::<Position>().get(kept.0).unwrap(), Position(7.0, 7.0));
+    }
+
+    #[test]
+    fn fuzz_corrupted_input() {
+        let bytes = server_world().encode_binary().unwrap();
+        let mut rng = Rng(0x2545f4914f6cdd1d);
+
+        for _ in 0..2000 {
+            let mut corrupted = bytes.clone();
+            for _ in 0..3 {
+                let index = rng.next(corrupted.len());
+                corrupted[index] = rng.next(256) as u8;
+            }
+
+            let mut client = registered_world();
+            client.create_entity().with_component(Position(7.0, 7.0)).finish_entity();
+            client.update();
+            let before = client.encode_binary().unwrap();
+
+            match client.decode_binary(&corrupted) {
+                Err(_) => assert_eq!(client.encode_binary().unwrap(), before),
+                Ok(()) => {
+                    let entity_manager = client.entity_manager();
+                    for entity in entity_manager.alive_entities() {
+                        assert!(entity_manager.ancestors_of(&entity).len() < entity_manager.alive_entities().len());
+                        assert!(!entity_manager.descendants_of(&entity).contains(&entity));
+                    }
+                    let encoded = client.encode_binary().unwrap();
+                    registered_world().decode_binary(&encoded).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn encode_every_component_of_the_signature() {
+        let mut server = registered_world();
+        let hidden = server.create_entity().with_component(Position(1.0, 1.0)).finish_entity();
+        server.update();
+        server.disable_entity(&hidden);
+
+        let mut client = registered_world();
+        client.decode_binary(&server.encode_binary().unwrap()).unwrap();
+        assert!(client.is_disabled(&hidden));
+
+        server.add_component(&hidden, PackedPosition(Position(2.0, 2.0)));
+        let err = server.encode_binary().unwrap_err();
+        assert!(matches!(err, EcsErrors::NotBinaryComponent(name) if name.ends_with("PackedPosition")));
+    }
+
+    #[test]
+    fn reject_broken_hierarchy() {
+        let mut server = registered_world();
+        let a = server.create_entity().finish_entity();
+        let b = server.create_entity().finish_entity();
+        server.update();
+        server.entity_manager_mut().add_component(&a, Parent(a)).unwrap();
+        let self_parent = server.encode_binary().unwrap();
+
+        server.entity_manager_mut().add_component(&a, Parent(b)).unwrap();
+        server.entity_manager_mut().add_component(&b, Parent(a)).unwrap();
+        let cycle = server.encode_binary().unwrap();
+
+        server.entity_manager_mut().remove_component::<Parent>(&a).unwrap();
+        server.entity_manager_mut().add_component(&a, Children(vec![b, b])).unwrap();
+        let listed_twice = server.encode_binary().unwrap();
+
+        server.entity_manager_mut().remove_component::<Children>(&a).unwrap();
+        let unlisted = server.encode_binary().unwrap();
+
+        let mut client = registered_world();
+        let kept = client.create_entity().with_component(Position(7.0, 7.0)).finish_entity();
+        client.update();
+        for bytes in [self_parent, cycle, listed_twice, unlisted] {
+            assert!(matches!(client.decode_binary(&bytes), Err(EcsErrors::BinaryFormat(_))));
+        }
+        assert_eq!(*client.query().components().get::<Position>().get(kept.0).unwrap(), Position(7.0, 7.0));
+    }
+
+    #[test]
+    fn reject_duplicate_names_before_replacing() {
+        let mut server = World::new();
+        server.register_binary_component::<crate::name::Name>("secs::Name");
+        server.create_entity().with_component(crate::name::Name("twin".to_owned())).finish_entity();
+        server.create_entity().with_component(crate::name::Name("twin".to_owned())).finish_entity();
+        server.update();
+        let bytes = server.encode_binary().unwrap();
+
+        let mut client = World::new();
+        client.register_binary_component::<crate::name::Name>("secs::Name");
+        client.set_name_mode(NameMode::Unique).unwrap();
+        let kept = client.create_entity().finish_entity();
+        client.update();
+        client.set_name(&kept, "kept");
+
+        assert!(matches!(client.decode_binary(&bytes), Err(EcsErrors::DuplicateName(_))));
+        assert_eq!(client.find_by_name("kept"), Some(kept));
+    }
+}
+
+#[cfg(test)]
+mod delta {
+    use ecs_macro::Component;
+
+    use super::Rng;
+    use crate::binary::{BinaryComponent, BinaryReader, BinaryWriter};
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::hierarchy::{Children, Parent};
+    use crate::world::World;
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Health(i64);
+
+    impl BinaryComponent for Health {
+        fn encode(&self, writer: &mut BinaryWriter) {
+            writer.write_i64(self.0);
+        }
+
+        fn decode(reader: &mut BinaryReader) -> Result<Self, EcsErrors> {
+            Ok(Health(reader.read_i64()?))
+        }
+    }
+
+    fn registered_world<'a>() -> World<'a> {
+        let mut world = World::new();
+        world.register_component::<Health>();
+        world.register_binary_component::<Health>("game::Health");
+        world
+    }
+
+    fn random_ops(world: &mut World, rng: &mut Rng) {
+        for _ in 0..20 {
+            let alive: Vec<Entity> = (0..world.entity_manager().id_generator().state().0)
+                .map(Entity)
+                .filter(|e| world.entity_manager().is_alive(e))
+                .collect();
+            let target = (!alive.is_empty()).then(|| alive[rng.next(alive.len())]);
+
+            match (rng.next(6), target) {
+                (0, _) | (_, None) => {
+                    world.create_entity().with_component(Health(rng.next(100) as i64)).finish_entity();
+                }
+                (1, Some(entity)) => world.remove_entity(&entity),
+                (2, Some(entity)) => world.add_component(&entity, Health(rng.next(100) as i64)),
+                (3, Some(entity)) => world.remove_component::<Health>(&entity),
+                (4, Some(entity)) if world.has_component::<Health>(&entity) => {
+                    let query = world.query();
+                    query.components().get_mut::<Health>().get_mut(entity.0).unwrap().0 += 1;
+                }
+                (4, Some(_)) => {}
+                (_, Some(entity)) => {
+                    let parent = alive[rng.next(alive.len())];
+                    let _ = world.set_parent(&entity, &parent);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn applying_deltas_reproduces_server_state() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+
+        for _ in 0..20 {
+            let mut server = registered_world();
+            let mut client = registered_world();
+            random_ops(&mut server, &mut rng);
+            server.update();
+            client.decode_binary(&server.encode_binary().unwrap()).unwrap();
+
+            for _ in 0..10 {
+                let since = server.tick();
+                random_ops(&mut server, &mut rng);
+                server.update();
+
+                let delta = server.diff(since);
+                let bytes = server.encode_delta(&delta);
+                let delta = client.decode_delta(&bytes).unwrap();
+                client.apply_delta(&delta).unwrap();
+
+                assert_eq!(client.encode_binary().unwrap(), server.encode_binary().unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn empty_delta_without_changes() {
+        let mut world = registered_world();
+        world.create_entity().with_component(Health(3)).finish_entity();
+        world.update();
+        world.update();
+
+        assert!(world.diff(world.tick()).is_empty());
+        assert_eq!(world.diff(0).spawned, vec![Entity(0)]);
+    }
+
+    #[test]
+    fn iter_mut_only_reports_occupied_slots() {
+        let mut world = registered_world();
+        let healthy = world.create_entity().with_component(Health(3)).finish_entity();
+        (0..4).for_each(|_| {
+            world.create_entity().finish_entity();
+        });
+        world.update();
+        world.update();
+
+        let since = world.tick();
+        world
+            .query()
+            .components()
+            .get_mut::<Health>()
+            .iter_mut()
+            .flatten()
+            .for_each(|health| health.0 += 1);
+
+        let delta = world.diff(since);
+        assert_eq!(delta.changed.len(), 1);
+        assert_eq!(delta.changed[0].entity, healthy);
+        assert!(delta.removed.is_empty());
+        assert!(delta.added.is_empty());
+    }
+
+    /// Delta bytes with the given id state and spawned entities, and nothing
+    /// else in them.
+    fn delta_bytes(world: &World, next_id: u64, freed: &[usize], spawned: &[usize]) -> Vec<u8> {
+        let mut writer = BinaryWriter::new();
+        writer.write_raw(b"SECD");
+        writer.write_raw(&crate::binary::FORMAT_VERSION.to_le_bytes());
+        writer.write_raw(&world.binary.schema_hash().to_le_bytes());
+        writer.write_varint(0);
+        writer.write_varint(0);
+        writer.write_varint(next_id);
+        for ids in [freed, spawned, &[]] {
+            writer.write_usize(ids.len());
+            ids.iter().for_each(|id| writer.write_usize(*id));
+        }
+        (0..3).for_each(|_| writer.write_usize(0));
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn reject_unbounded_next_id() {
+        let mut world = registered_world();
+        let delta = world.decode_delta(&delta_bytes(&world, u64::MAX >> 1, &[], &[])).unwrap();
+
+        assert!(matches!(world.apply_delta(&delta), Err(EcsErrors::BinaryFormat(_))));
+        assert_eq!(world.entity_manager().id_generator().state().0, 0);
+    }
+
+    #[test]
+    fn reject_reused_freed_ids() {
+        let mut world = registered_world();
+        let alive = world.create_entity().finish_entity();
+        world.update();
+
+        for bytes in [
+            delta_bytes(&world, 3, &[1, 1], &[2]),
+            delta_bytes(&world, 3, &[1, 2], &[2]),
+        ] {
+            assert!(matches!(world.decode_delta(&bytes), Err(EcsErrors::BinaryFormat(_))));
+        }
+
+        let delta = world.decode_delta(&delta_bytes(&world, 2, &[0], &[1])).unwrap();
+        assert!(matches!(world.apply_delta(&delta), Err(EcsErrors::BinaryFormat(_))));
+        assert!(world.entity_manager().is_alive(&alive));
+        assert_eq!(world.entity_manager().id_generator().state().0, 1);
+    }
+
+    #[test]
+    fn failed_apply_leaves_world_untouched() {
+        let mut server = registered_world();
+        server.register_binary_component::<crate::name::Name>("secs::Name");
+        let first = server.create_entity().with_component(Health(1)).finish_entity();
+        let second = server.create_entity().finish_entity();
+        server.update();
+
+        let mut client = registered_world();
+        client.register_binary_component::<crate::name::Name>("secs::Name");
+        client.decode_binary(&server.encode_binary().unwrap()).unwrap();
+        client.set_name_mode(crate::name::NameMode::Unique).unwrap();
+
+        let since = server.tick();
+        server.remove_entity(&first);
+        server.set_name(&second, "twin");
+        server.create_entity().with_component(crate::name::Name("twin".to_owned())).finish_entity();
+        server.update();
+
+        let delta = client.decode_delta(&server.encode_delta(&server.diff(since))).unwrap();
+        let before = client.encode_binary().unwrap();
+        assert!(matches!(client.apply_delta(&delta), Err(EcsErrors::DuplicateName(_))));
+        assert_eq!(client.encode_binary().unwrap(), before);
+    }
+
+    #[test]
+    fn apply_swapped_unique_names() {
+        let mut server = registered_world();
+        server.register_binary_component::<crate::name::Name>("secs::Name");
+        let first = server.create_entity().with_component(crate::name::Name("a".to_owned())).finish_entity();
+        let second = server.create_entity().with_component(crate::name::Name("b".to_owned())).finish_entity();
+        server.update();
+
+        let mut client = registered_world();
+        client.register_binary_component::<crate::name::Name>("secs::Name");
+        client.decode_binary(&server.encode_binary().unwrap()).unwrap();
+        client.set_name_mode(crate::name::NameMode::Unique).unwrap();
+
+        let since = server.tick();
+        server.set_name(&first, "b");
+        server.set_name(&second, "a");
+        server.update();
+
+        client.apply_delta(&server.diff(since)).unwrap();
+        assert_eq!(client.find_by_name("a"), Some(second));
+        assert_eq!(client.find_by_name("b"), Some(first));
+    }
+
+    #[test]
+    fn reject_hierarchy_cycles_in_deltas() {
+        let mut server = registered_world();
+        let a = server.create_entity().finish_entity();
+        let b = server.create_entity().finish_entity();
+        server.update();
+        server.set_parent(&b, &a).unwrap();
+
+        let mut client = registered_world();
+        client.decode_binary(&server.encode_binary().unwrap()).unwrap();
+
+        // Written around `set_parent`, which would refuse the cycle.
+        let since = server.tick();
+        server.entity_manager_mut().add_component(&a, Parent(b)).unwrap();
+        server.entity_manager_mut().add_component(&b, Children(vec![a])).unwrap();
+        let cycle = server.encode_delta(&server.diff(since));
+        server.entity_manager_mut().remove_component::<Parent>(&a).unwrap();
+        server.entity_manager_mut().remove_component::<Children>(&a).unwrap();
+        let orphaned = server.encode_delta(&server.diff(since));
+
+        let before = client.encode_binary().unwrap();
+        for bytes in [cycle, orphaned] {
+            let delta = client.decode_delta(&bytes).unwrap();
+            assert!(matches!(client.apply_delta(&delta), Err(EcsErrors::BinaryFormat(_))));
+            assert_eq!(client.encode_binary().unwrap(), before);
+        }
+        assert_eq!(client.query().ancestors(&b), vec![a]);
+    }
+}
+
+#[cfg(test)]
+mod rollback {
+    use std::hash::Hasher;
+
+    use ecs_macro::Component;
+
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::events::EventEmitter;
+    use crate::query::Query;
+    use crate::rollback::{Rollback, StateHasher};
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component, Clone, Hash, Debug, PartialEq)]
+    struct Position(i64);
+
+    #[derive(Component, Clone, Hash)]
+    struct Velocity(i64);
+
+    #[derive(Clone, Hash, Debug, PartialEq)]
+    struct Input(i64);
+
+    struct Movement;
+
+    impl System for Movement {
+        fn action(&mut self, query: Query, entities: &[Entity], _: &mut CommandBuffer, _: EventEmitter) {
+            let input = query.resource::<Input>().get::<Input>().0;
+            let velocities = query.components().get::<Velocity>();
+            let mut positions = query.components().get_mut::<Position>();
+            for entity in entities {
+                let velocity = velocities.data[entity.0].as_ref().unwrap().0;
+                positions.get_mut(entity.0).unwrap().0 += velocity * input;
+            }
+        }
+    }
+
+    fn schedule(world: &mut World) {
+        world.run_systems();
+        world.update();
+    }
+
+    fn simulation<'a>() -> World<'a> {
+        let mut world = World::new();
+        world.register_rollback_component::<Position>();
+        world.register_rollback_component::<Velocity>();
+        world.register_rollback_resource::<Input>();
+
+        world.create_entity().with_component(Position(0)).with_component(Velocity(1)).finish_entity();
+        world.create_entity().with_component(Position(10)).with_component(Velocity(-2)).finish_entity();
+        world.update();
+
+        let system = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Movement)
+            .with_component::<Position>()
+            .with_component::<Velocity>()
+            .build();
+        world.add_system::<Movement>(system, true);
+        world
+    }
+
+    fn run(inputs: &[i64]) -> (World<'static>, Vec<u64>) {
+        let mut world = simulation();
+        let mut rollback = Rollback::new(16, schedule);
+        let hashes = inputs.iter().map(|i| rollback.advance(&mut world, Input(*i))).collect();
+        (world, hashes)
+    }
+
+    #[test]
+    fn hash_integers_as_little_endian_bytes() {
+        let hash = |write: &dyn Fn(&mut StateHasher)| {
+            let mut hasher = StateHasher::default();
+            write(&mut hasher);
+            hasher.finish()
+        };
+        let bytes = |bytes: &[u8]| hash(&|hasher| hasher.write(bytes));
+
+        assert_eq!(hash(&|hasher| hasher.write_u16(0x0102)), bytes(&[2, 1]));
+        assert_eq!(hash(&|hasher| hasher.write_u32(0x01020304)), bytes(&[4, 3, 2, 1]));
+        assert_eq!(hash(&|hasher| hasher.write_i64(-2)), bytes(&(-2i64).to_le_bytes()));
+        assert_eq!(hash(&|hasher| hasher.write_u128(1)), bytes(&1u128.to_le_bytes()));
+        assert_eq!(hash(&|hasher| hasher.write_usize(7)), hash(&|hasher| hasher.write_u64(7)));
+        assert_eq!(hash(&|hasher| hasher.write_isize(-7)), hash(&|hasher| hasher.write_i64(-7)));
+    }
+
+    #[test]
+    fn same_inputs_same_hashes() {
+        let (_, first) = run(&[1, 2, 3, 1]);
+        let (_, second) = run(&[1, 2, 3, 1]);
+        let (_, other) = run(&[1, 2, 0, 1]);
+
+        assert_eq!(first, second);
+        assert_eq!(first[..2], other[..2]);
+        assert_ne!(first[2], other[2]);
+    }
+
+    #[test]
+    fn late_input_resimulates_to_corrected_state() {
+        let (expected, expected_hashes) = run(&[1, 1, 5, 1, 1]);
+
+        let mut world = simulation();
+        let mut rollback = Rollback::new(16, schedule);
+        for _ in 0..5 {
+            rollback.advance(&mut world, Input(1));
+        }
+
+        let hash = rollback.correct_input(&mut world, 2, Input(5)).unwrap();
+
+        assert_eq!(rollback.frame(), 5);
+        assert_eq!(hash, expected_hashes[4]);
+        assert_eq!(world.state_hash(), expected.state_hash());
+        assert_eq!(rollback.input(2), Some(&Input(5)));
+        assert_eq!(*world.query().components().get::<Position>().get(0).unwrap(), Position(9));
+        assert!(rollback.verify(4, expected_hashes[4]).is_ok());
+    }
+
+    #[test]
+    fn detect_desync_and_expired_frames() {
+        let mut world = simulation();
+        let mut rollback = Rollback::new(2, schedule);
+        for input in 0..4 {
+            rollback.advance(&mut world, Input(input));
+        }
+
+        assert_eq!(rollback.oldest_frame(), Some(2));
+        assert!(matches!(
+            rollback.restore(&mut world, 1),
+            Err(EcsErrors::FrameNotBuffered(1))
+        ));
+
+        let hash = rollback.hash(3).unwrap();
+        assert!(matches!(
+            rollback.verify(3, hash ^ 1),
+            Err(EcsErrors::Desync { frame: 3, .. })
+        ));
+
+        rollback.restore(&mut world, 2).unwrap();
+        assert_eq!(rollback.frame(), 2);
+        assert_eq!(rollback.hash(2), None);
+    }
+}
+
+#[cfg(test)]
+mod determinism {
+    use ecs_macro::{Component, GameEvent};
+
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::Entity;
+    use crate::events::{EventEmitter, WorldEventSubscriber};
+    use crate::query::Query;
+    use crate::relations::{CleanupPolicy, Relation};
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component)]
+    struct Health(u32);
+
+    #[derive(Component)]
+    struct Armor;
+
+    struct Follows;
+
+    impl Relation for Follows {
+        const ON_TARGET_REMOVED: CleanupPolicy = CleanupPolicy::RemoveSource;
+    }
+
+    #[derive(GameEvent)]
+    struct Hit(usize);
+
+    #[derive(Default)]
+    struct Trace(Vec<String>);
+
+    struct Other;
+
+    fn record(query: &Query, line: String) {
+        query.resource_mut::<Trace>().get_mut::<Trace>().0.push(line);
+    }
+
+    struct Damage;
+
+    impl System for Damage {
+        fn action(&mut self, query: Query, entities: &[Entity], commands: &mut CommandBuffer, _: EventEmitter) {
+            record(&query, format!("damage {entities:?}"));
+            let mut health = query.components().get_mut::<Health>();
+            for entity in entities {
+                let health = health.get_mut(entity.0).unwrap();
+                health.0 = health.0.saturating_sub(40);
+                if health.0 == 0 {
+                    commands.remove_entity(entity);
+                }
+            }
+        }
+    }
+
+    struct Shield;
+
+    impl System for Shield {
+        fn action(&mut self, query: Query, entities: &[Entity], _: &mut CommandBuffer, _: EventEmitter) {
+            record(&query, format!("shield {entities:?}"));
+        }
+    }
+
+    fn first_handler(event: &Hit, query: &Query, _: &mut CommandBuffer) {
+        record(query, format!("first hit {}", event.0));
+    }
+
+    fn second_handler(event: &Hit, query: &Query, _: &mut CommandBuffer) {
+        record(query, format!("second hit {}", event.0));
+    }
+
+    fn simulate() -> Vec<String> {
+        let mut world = World::new();
+        world.add_resource(Trace::default());
+        world.add_resource(Other);
+        world.register_component::<Health>();
+        world.register_component::<Armor>();
+        world.events().subscribe(first_handler);
+        world.events().subscribe(second_handler);
+
+        let damage = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Damage)
+            .with_component::<Health>()
+            .build();
+        let shield = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Shield)
+            .with_component::<Armor>()
+            .build();
+        world.add_system::<Damage>(damage, false);
+        world.add_system::<Shield>(shield, false);
+
+        let mut entities = vec![];
+        for i in 0..12 {
+            world.create_entity().with_component(Health(40 * (i % 4) + 10));
+            if i % 3 == 0 {
+                world.with_component(Armor);
+            }
+            entities.push(world.finish_entity());
+        }
+        for pair in entities.windows(2) {
+            world.add_relation(&pair[1], Follows, &pair[0]).unwrap();
+        }
+        world.update();
+
+        for frame in 0..5 {
+            world.emit_event(Hit(frame));
+            world.run_systems();
+            world.update();
+        }
+
+        let query = world.query();
+        let mut trace = query.resource::<Trace>().get::<Trace>().0.clone();
+        let resources: Vec<_> = query.resources.iter().map(|(_, r)| r.borrow().name()).collect();
+        trace.push(format!("resources {resources:?}"));
+        trace
+    }
+
+    #[test]
+    fn same_script_same_trace() {
+        let first = simulate();
+
+        assert_eq!(first[0], "first hit 0");
+        assert_eq!(first[1], "second hit 0");
+        assert!(first[2].starts_with("damage"));
+        assert!(first[3].starts_with("shield"));
+        assert!(first.last().unwrap().ends_with("Trace\", \"secs::tests::determinism::Other\"]"));
+
+        for _ in 0..10 {
+            assert_eq!(simulate(), first);
+        }
+    }
+}
+
+#[cfg(test)]
+mod reflect {
+    use ecs_macro::{Component, Reflect};
+
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::reflect::{FieldInfo, Reflect};
+    use crate::world::World;
+
+    #[derive(Reflect, Debug, PartialEq)]
+    struct Offset(f32, f32);
+
+    #[derive(Component, Reflect, Debug, PartialEq)]
+    struct Weapon {
+        name: String,
+        damage: u32,
+        offset: Offset,
+        owner: Entity,
+    }
+
+    #[derive(Reflect)]
+    struct Difficulty {
+        level: u8,
+    }
+
+    struct Cache;
+
+    #[derive(Reflect)]
+    struct Inventory {
+        slots: [u8; 3],
+        items: Vec<String>,
+        equipped: Option<Offset>,
+        #[reflect(ignore)]
+        _cache: Cache,
+    }
+
+    fn world_with_weapon<'a>() -> (World<'a>, Entity) {
+        let mut world = World::new();
+        world.register_reflect_component::<Weapon>();
+        world.register_reflect_resource::<Difficulty>();
+        world.add_resource(Difficulty { level: 2 });
+
+        let weapon = world
+            .create_entity()
+            .with_component(Weapon {
+                name: "sword".to_owned(),
+                damage: 10,
+                offset: Offset(0.5, 1.0),
+                owner: Entity(7),
+            })
+            .finish_entity();
+        world.update();
+        (world, weapon)
+    }
+
+    #[test]
+    fn field_info_from_registry() {
+        let (world, weapon) = world_with_weapon();
+
+        let reflected = world.reflect_registry().component_by_name("Weapon").unwrap();
+        assert_eq!(
+            reflected.fields[1],
+            FieldInfo {
+                name: "damage",
+                type_name: "u32"
+            }
+        );
+        assert_eq!(Offset::type_fields()[0].name, "0");
+        assert_eq!(world.reflected_components(&weapon), vec![reflected.type_name]);
+        assert!(world.reflected_components(&Entity(3)).is_empty());
+    }
+
+    #[test]
+    fn reflect_collections_and_skip_ignored_fields() {
+        let mut inventory = Inventory {
+            slots: [1, 2, 3],
+            items: vec!["rope".to_owned()],
+            equipped: None,
+            _cache: Cache,
+        };
+        let names: Vec<&str> = Inventory::type_fields().iter().map(|f| f.name).collect();
+        assert_eq!(names, vec!["slots", "items", "equipped"]);
+
+        let reflected = &mut inventory as &mut dyn Reflect;
+        assert_eq!(reflected.get::<u8>("slots.2"), Some(&3));
+        assert!(reflected.path("slots.3").is_none());
+        assert!(reflected.path("equipped.0").is_none());
+        reflected.set_path_value("slots.0", "9").unwrap();
+        reflected.set_path_value("items.0", "torch").unwrap();
+        reflected.set("equipped", Some(Offset(1.0, 2.0))).unwrap();
+        reflected.set("equipped.0.1", 4.0f32).unwrap();
+
+        assert_eq!(
+            reflected.value(),
+            "Inventory { slots: [9, 2, 3], items: [\"torch\"], equipped: Some(Offset { 0: 1.0, 1: 4.0 }) }"
+        );
+    }
+
+    #[test]
+    fn get_and_set_by_path() {
+        let (world, weapon) = world_with_weapon();
+
+        {
+            let reflected = world.get_component_reflect(&weapon, "Weapon").unwrap();
+            assert_eq!(reflected.get::<u32>("damage"), Some(&10));
+            assert_eq!(reflected.get::<f32>("offset.1"), Some(&1.0));
+            assert_eq!(reflected.path("owner").unwrap().value(), "Entity(7)");
+            assert_eq!(reflected.path("offset").unwrap().value(), "Offset { 0: 0.5, 1: 1.0 }");
+            assert!(reflected.path("offset.2").is_none());
+        }
+
+        {
+            let mut reflected = world.get_component_reflect_mut(&weapon, "Weapon").unwrap();
+            reflected.set("offset.0", 2.5f32).unwrap();
+            reflected.set_path_value("damage", "25").unwrap();
+            reflected.set_path_value("name", "axe").unwrap();
+            assert!(matches!(reflected.set("damage", 1.0f32), Err(EcsErrors::ReflectValue(_))));
+            assert!(matches!(reflected.set_path_value("damage", "lots"), Err(EcsErrors::ReflectValue(_))));
+            assert!(matches!(reflected.set_path_value("speed", "1"), Err(EcsErrors::InvalidReflectPath(_))));
+        }
+
+        let query = world.query();
+        let weapons = query.components().get::<Weapon>();
+        let stored = weapons.get(weapon.0).unwrap();
+        assert_eq!(stored.damage, 25);
+        assert_eq!(stored.name, "axe");
+        assert_eq!(stored.offset, Offset(2.5, 1.0));
+        assert!(weapons.changed_since(weapon.0, world.tick()));
+    }
+
+    #[test]
+    fn reflect_resources() {
+        let (world, _) = world_with_weapon();
+
+        world
+            .get_resource_reflect_mut("Difficulty")
+            .unwrap()
+            .set_path_value("level", "3")
+            .unwrap();
+
+        let difficulty = world.get_resource_reflect(std::any::type_name::<Difficulty>()).unwrap();
+        assert_eq!(difficulty.get::<u8>("level"), Some(&3));
+        assert!(world.get_resource_reflect("Missing").is_none());
+    }
+}
+
+#[cfg(test)]
+mod dynamic {
+    use std::alloc::Layout;
+
+    use ecs_macro::Component;
+
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::events::EventEmitter;
+    use crate::query::Query;
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component)]
+    struct Position;
+
+    #[derive(Component, Default)]
+    struct Mass;
+
+    #[derive(Component, Default)]
+    #[require(Mass)]
+    struct Body;
+
+    #[derive(Default)]
+    struct Seen(Vec<Entity>);
+
+    struct Collect;
+
+    impl System for Collect {
+        fn action(&mut self, query: Query, entities: &[Entity], _: &mut CommandBuffer, _: EventEmitter) {
+            query.resource_mut::<Seen>().get_mut::<Seen>().0 = entities.to_vec();
+        }
+    }
+
+    #[test]
+    fn mix_dynamic_and_static_components() {
+        let mut world = World::new();
+        world.register_component::<Position>();
+        let mana = world.register_dynamic_component("mod::Mana", Layout::new::<u32>()).unwrap();
+        let tag = world.register_dynamic_component("mod::Tag", Layout::new::<()>()).unwrap();
+
+        assert_eq!(world.dynamic_component_id("mod::Mana"), Some(mana));
+        assert_ne!(world.component_id::<Position>(), Some(mana));
+
+        let caster = world.create_entity().with_component(Position).finish_entity();
+        world.add_dynamic_component(&caster, mana, &30u32.to_le_bytes()).unwrap();
+        let totem = world.create_entity().finish_entity();
+        world.add_dynamic_component(&totem, mana, &5u32.to_le_bytes()).unwrap();
+        world.add_dynamic_component(&totem, tag, &[]).unwrap();
+        world.create_entity().with_component(Position).finish_entity();
+
+        world.add_resource(Seen::default());
+        let system = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Collect)
+            .with_component::<Position>()
+            .with_component_id(mana)
+            .build();
+        world.add_system::<Collect>(system, false);
+        world.update();
+        world.update_system::<Collect>();
+        assert_eq!(world.query().resource::<Seen>().get::<Seen>().0, vec![caster]);
+
+        let position = world.component_id::<Position>().unwrap();
+        let query = world.query();
+        assert_eq!(query.entities().with_component_id(mana).get(), vec![caster, totem]);
+        assert_eq!(
+            query.entities().with_component_id(mana).with_component_id(position).get(),
+            vec![caster]
+        );
+        {
+            let mut pool = query.components().get_dynamic_mut(mana);
+            pool.get_mut(totem.0).unwrap().copy_from_slice(&6u32.to_le_bytes());
+        }
+        let pool = query.components().get_dynamic(mana);
+        assert_eq!(pool.get(totem.0), Some(&6u32.to_le_bytes()[..]));
+        assert!(pool.changed_since(totem.0, world.tick()));
+        drop(pool);
+
+        world.remove_component_by_id(&totem, mana);
+        assert!(!world.has_component_id(&totem, mana));
+        assert!(world.has_component_id(&totem, tag));
+        world.remove_entity(&caster);
+        world.update();
+        assert_eq!(world.query().components().get_dynamic(mana).iter().count(), 0);
+    }
+
+    #[test]
+    fn reject_mismatched_layouts() {
+        let mut world = World::new();
+        let mana = world.register_dynamic_component("mod::Mana", Layout::new::<u32>()).unwrap();
+
+        assert_eq!(world.register_dynamic_component("mod::Mana", Layout::new::<u32>()).unwrap(), mana);
+        assert!(matches!(
+            world.register_dynamic_component("mod::Mana", Layout::new::<u64>()),
+            Err(EcsErrors::DynamicComponentLayout(_))
+        ));
+
+        let entity = world.create_entity().finish_entity();
+        assert!(matches!(
+            world.add_dynamic_component(&entity, mana, &[1, 2]),
+            Err(EcsErrors::DynamicComponentLayout(_))
+        ));
+        assert!(!world.has_component_id(&entity, mana));
+    }
+
+    #[test]
+    fn reject_components_past_signature_bits() {
+        let mut world = World::new();
+        let mut ids = vec![];
+        while let Ok(id) = world.register_dynamic_component(&format!("mod::C{}", ids.len()), Layout::new::<u8>()) {
+            ids.push(id);
+        }
+        assert_eq!(ids.last().unwrap().0, u32::BITS - 1);
+        assert!(matches!(
+            world.register_dynamic_component("mod::Extra", Layout::new::<u8>()),
+            Err(EcsErrors::TooManyComponents(_))
+        ));
+    }
+
+    #[test]
+    fn reject_static_components_past_signature_bits() {
+        let mut world = World::new();
+        for i in 0..u32::BITS - 2 {
+            world.register_dynamic_component(&format!("mod::C{i}"), Layout::new::<u8>()).unwrap();
+        }
+        world.register_component::<Position>();
+
+        // Body and the Mass it requires need two bits, only one is left.
+        assert!(matches!(world.try_register_component::<Body>(), Err(EcsErrors::TooManyComponents(_))));
+        assert_eq!(world.component_id::<Mass>(), None);
+        let entity = world.create_entity().finish_entity();
+        assert!(world.entity_manager_mut().add_component(&entity, Body).is_err());
+
+        world.register_component::<Mass>();
+        assert!(matches!(world.try_register_component::<Body>(), Err(EcsErrors::TooManyComponents(_))));
+    }
+}
+
+#[cfg(test)]
+mod bundle {
+    use ecs_macro::{Bundle, Component};
+
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::Entity;
+    use crate::events::EventEmitter;
+    use crate::query::Query;
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Health(u32);
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Weapon(&'static str);
+
+    #[derive(Component)]
+    struct Marching;
+
+    #[derive(Bundle)]
+    struct Soldier {
+        health: Health,
+        weapon: Weapon,
+    }
+
+    #[derive(Bundle)]
+    struct Squad(Soldier, Marching);
+
+    struct Recruit;
+
+    impl System for Recruit {
+        fn action(&mut self, _: Query, entities: &[Entity], commands: &mut CommandBuffer, _: EventEmitter) {
+            for entity in entities {
+                commands.remove_bundle::<Soldier>(entity);
+                commands.spawn((Health(5), Weapon("spear")));
+            }
+        }
+    }
+
+    fn soldier() -> Soldier {
+        Soldier {
+            health: Health(100),
+            weapon: Weapon("sword"),
+        }
+    }
+
+    #[test]
+    fn spawn_insert_and_remove_bundles() {
+        let mut world = World::new();
+
+        let single = world.spawn_bundle((Health(1),));
+        let pair = world.spawn_bundle((Health(2), Marching));
+        let nested = world.spawn_bundle(Squad(soldier(), Marching));
+
+        assert!(world.has_component::<Health>(&single));
+        assert!(!world.has_component::<Marching>(&single));
+        assert!(world.has_component::<Marching>(&pair));
+        assert!(world.has_component::<Weapon>(&nested));
+        assert!(world.has_component::<Marching>(&nested));
+
+        world.insert_bundle(&single, soldier());
+        assert_eq!(*world.query().components().get::<Health>().get(single.0).unwrap(), Health(100));
+        assert_eq!(*world.query().components().get::<Weapon>().get(single.0).unwrap(), Weapon("sword"));
+
+        world.remove_bundle::<Soldier>(&nested);
+        assert!(!world.has_component::<Health>(&nested));
+        assert!(!world.has_component::<Weapon>(&nested));
+        assert!(world.has_component::<Marching>(&nested));
+        assert!(world.query().components().get::<Health>().data[nested.0].is_none());
+    }
+
+    #[test]
+    fn bundles_through_command_buffer() {
+        let mut world = World::new();
+        world.register_component::<Health>();
+        world.register_component::<Weapon>();
+
+        let system = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Recruit)
+            .with_component::<Health>()
+            .with_component::<Weapon>()
+            .build();
+        world.add_system::<Recruit>(system, false);
+        let veteran = world.spawn_bundle(soldier());
+        world.update_system::<Recruit>();
+
+        assert!(!world.has_component::<Health>(&veteran));
+        let recruit = Entity(1);
+        assert_eq!(*world.query().components().get::<Weapon>().get(recruit.0).unwrap(), Weapon("spear"));
+        assert!(world.has_component::<Health>(&recruit));
+    }
+
+    struct Drill;
+
+    impl System for Drill {
+        fn action(&mut self, _: Query, entities: &[Entity], commands: &mut CommandBuffer, _: EventEmitter) {
+            for entity in entities {
+                commands.insert_bundle(entity, Marching);
+                commands.remove_bundle::<Marching>(entity);
+                commands.remove_bundle::<Soldier>(entity);
+                commands.insert_bundle(entity, (Health(7), Weapon("pike")));
+            }
+        }
+    }
+
+    #[test]
+    fn commands_apply_in_issue_order() {
+        let mut world = World::new();
+        world.register_component::<Health>();
+
+        let system = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Drill)
+            .with_component::<Health>()
+            .build();
+        world.add_system::<Drill>(system, false);
+        let soldier = world.spawn_bundle(soldier());
+        world.update_system::<Drill>();
+
+        assert!(!world.has_component::<Marching>(&soldier));
+        assert_eq!(*world.query().components().get::<Weapon>().get(soldier.0).unwrap(), Weapon("pike"));
+    }
+}
+
+#[cfg(test)]
+mod require {
+    use ecs_macro::Component;
+
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::events::EventEmitter;
+    use crate::query::Query;
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component, Default, Debug, PartialEq)]
+    struct Transform(i32);
+
+    #[derive(Component, Default, Debug, PartialEq)]
+    #[require(Transform)]
+    struct Visibility(bool);
+
+    #[derive(Component, Default)]
+    #[require(Transform, Visibility)]
+    struct Sprite;
+
+    #[derive(Component, Default)]
+    #[require(Egg)]
+    struct Chicken;
+
+    #[derive(Component, Default)]
+    #[require(Chicken)]
+    struct Egg;
+
+    #[derive(Component, Default)]
+    #[require(Chicken)]
+    struct Farm;
+
+    #[derive(Default)]
+    struct Seen(Vec<Entity>);
+
+    struct Render;
+
+    impl System for Render {
+        fn action(&mut self, query: Query, entities: &[Entity], _: &mut CommandBuffer, _: EventEmitter) {
+            query.resource_mut::<Seen>().get_mut::<Seen>().0 = entities.to_vec();
+        }
+    }
+
+    #[test]
+    fn insert_missing_required_components() {
+        let mut world = World::new();
+        world.register_component::<Sprite>();
+        world.add_resource(Seen::default());
+
+        let system = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Render)
+            .with_component::<Sprite>()
+            .with_component::<Transform>()
+            .with_component::<Visibility>()
+            .build();
+        world.add_system::<Render>(system, false);
+
+        let sprite = world.create_entity().with_component(Sprite).finish_entity();
+        let placed = world
+            .create_entity()
+            .with_component(Transform(7))
+            .with_component(Sprite)
+            .finish_entity();
+        let bundled = world.spawn_bundle((Sprite, Visibility(true)));
+        world.update();
+        world.update_system::<Render>();
+
+        // Spawned bundles join right away, `create_entity` waits for `update`.
+        assert_eq!(world.query().resource::<Seen>().get::<Seen>().0, vec![bundled, sprite, placed]);
+        let query = world.query();
+        let transforms = query.components().get::<Transform>();
+        assert_eq!(*transforms.get(sprite.0).unwrap(), Transform(0));
+        assert_eq!(*transforms.get(placed.0).unwrap(), Transform(7));
+        assert_eq!(*query.components().get::<Visibility>().get(bundled.0).unwrap(), Visibility(true));
+    }
+
+    #[test]
+    fn reject_requirement_cycles_at_registration() {
+        let mut world = World::new();
+
+        let err = world.try_register_component::<Farm>().unwrap_err();
+        assert!(matches!(&err, EcsErrors::RequirementCycle(cycle) if cycle.contains("Chicken -> ") && cycle.ends_with("Chicken")));
+        assert!(world.try_register_component::<Sprite>().is_ok());
+    }
+}
+
+#[cfg(test)]
+mod prefab {
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    use ecs_macro::Component;
+
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::{Entity, MapEntities};
+    use crate::errors::EcsErrors;
+    use crate::events::EventEmitter;
+    use crate::name::NameMode;
+    use crate::prefab::{Prefab, PrefabComponent};
+    use crate::query::Query;
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Health(u32);
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Name(&'static str);
+
+    #[derive(Component, Clone, Copy, Debug, PartialEq)]
+    struct Target(Entity);
+
+    impl MapEntities for Target {
+        fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+            self.0 = map(self.0);
+        }
+    }
+
+    struct Spawner(Prefab);
+
+    struct Failing;
+
+    impl PrefabComponent for Failing {
+        fn instantiate(&self, _: &mut World, entity: &Entity, _: &HashMap<Entity, Entity>) -> Result<(), EcsErrors> {
+            Err(EcsErrors::EntityDoesNotExist(entity.0))
+        }
+    }
+
+    impl System for Spawner {
+        fn action(&mut self, _: Query, entities: &[Entity], commands: &mut CommandBuffer, _: EventEmitter) {
+            for _ in entities {
+                commands.instantiate_with(&self.0, Health(3));
+            }
+        }
+    }
+
+    fn goblin() -> Prefab {
+        Prefab::new().with(Health(10)).with(Name("goblin"))
+    }
+
+    #[test]
+    fn instantiate_with_overrides() {
+        let mut world = World::new();
+
+        let first = world.instantiate(&goblin());
+        let boss = world.instantiate_with(&goblin(), (Health(50), Target(first)));
+
+        let query = world.query();
+        let health = query.components().get::<Health>();
+        assert_eq!(*health.get(first.0).unwrap(), Health(10));
+        assert_eq!(*health.get(boss.0).unwrap(), Health(50));
+        assert_eq!(*query.components().get::<Name>().get(boss.0).unwrap(), Name("goblin"));
+        assert_eq!(query.components().get::<Target>().get(boss.0).unwrap().0, first);
+    }
+
+    #[test]
+    fn nested_prefabs_remap_references() {
+        // Local 0 is the root of the prefab the component was added to.
+        let barrel = Prefab::new().with(Health(1)).with_mapped(Target(Prefab::local(0)));
+        let tank = Prefab::new()
+            .with(Health(20))
+            .with_mapped(Target(Prefab::local(1)))
+            .with_child(&barrel)
+            .with_child(&barrel);
+        assert_eq!(tank.len(), 3);
+
+        let mut world = World::new();
+        world.create_entity().finish_entity();
+        let first = world.instantiate(&tank);
+        let second = world.instantiate(&tank);
+
+        let query = world.query();
+        for tank in [first, second] {
+            let barrels = query.children(&tank);
+            assert_eq!(barrels.len(), 2);
+            let targets = query.components().get::<Target>();
+            assert_eq!(targets.get(tank.0).unwrap().0, barrels[0]);
+            for barrel in barrels {
+                assert_eq!(query.parent(&barrel), Some(tank));
+                assert_eq!(targets.get(barrel.0).unwrap().0, barrel);
+            }
+        }
+        assert_ne!(query.children(&first), query.children(&second));
+    }
+
+    #[test]
+    fn keep_references_to_world_entities() {
+        let mut world = World::new();
+        let outside = world.create_entity().finish_entity();
+        world.create_entity().finish_entity();
+        let prefab = Prefab::new().with_mapped(Target(outside)).with_child(&goblin());
+
+        let root = world.instantiate(&prefab);
+        assert_eq!(world.query().components().get::<Target>().get(root.0).unwrap().0, outside);
+    }
+
+    #[test]
+    fn prefab_from_entity() {
+        let mut world = World::new();
+        let base = world.create_entity().with_component(Health(5)).finish_entity();
+        let child = world.create_entity().with_component(Name("turret")).finish_entity();
+        world.set_parent(&child, &base).unwrap();
+
+        let prefab = Prefab::from_entity(&world, &base).unwrap();
+        assert_eq!(prefab.len(), 2);
+        let copy = world.instantiate(&prefab);
+
+        let query = world.query();
+        assert_eq!(*query.components().get::<Health>().get(copy.0).unwrap(), Health(5));
+        let children = query.children(&copy);
+        assert_eq!(children.len(), 1);
+        assert_ne!(children[0], child);
+        assert_eq!(*query.components().get::<Name>().get(children[0].0).unwrap(), Name("turret"));
+    }
+
+    #[test]
+    fn prefab_from_entity_remaps_references() {
+        let mut world = World::new();
+        let outside = world.create_entity().finish_entity();
+        let base = world.create_entity().finish_entity();
+        let child = world.create_entity().with_component(Target(base)).finish_entity();
+        world.update();
+        world.add_component(&base, Target(child));
+        world.set_parent(&child, &base).unwrap();
+        let turret = world.create_entity().with_component(Target(outside)).finish_entity();
+        world.update();
+        world.set_parent(&turret, &child).unwrap();
+
+        let prefab = Prefab::from_entity(&world, &base).unwrap();
+        let copy = world.instantiate(&prefab);
+
+        let query = world.query();
+        let targets = query.components().get::<Target>();
+        let copied_child = query.children(&copy)[0];
+        let copied_turret = query.children(&copied_child)[0];
+        assert_eq!(targets.get(copy.0).unwrap().0, copied_child);
+        assert_eq!(targets.get(copied_child.0).unwrap().0, copy);
+        assert_eq!(targets.get(copied_turret.0).unwrap().0, outside);
+        assert_eq!(targets.get(base.0).unwrap().0, child);
+    }
+
+    #[test]
+    fn prefab_from_entity_outlives_source() {
+        let mut world = World::new();
+        let base = world.create_entity().with_component(Health(5)).finish_entity();
+        world.update();
+
+        let prefab = Prefab::from_entity(&world, &base).unwrap();
+        world.remove_entity(&base);
+        world.create_entity().with_component(Health(9)).finish_entity();
+        world.update();
+
+        let copy = world.instantiate(&prefab);
+        assert_eq!(*world.query().components().get::<Health>().get(copy.0).unwrap(), Health(5));
+    }
+
+    #[test]
+    fn reject_missing_local_entity() {
+        let mut world = World::new();
+        let prefab = goblin().with_mapped(Target(Prefab::local(3)));
+
+        assert!(matches!(world.try_instantiate(&prefab), Err(EcsErrors::InvalidPrefabEntity(3))));
+        assert!(!world.entity_manager().is_alive(&Entity(0)));
+    }
+
+    #[test]
+    fn failed_override_removes_spawned_entities() {
+        let mut world = World::new();
+        world.set_name_mode(NameMode::Unique).unwrap();
+        let boss = world.create_entity().finish_entity();
+        world.update();
+        world.set_name(&boss, "boss");
+
+        let result = world.try_instantiate_with(&goblin(), crate::name::Name("boss".to_owned()));
+        assert!(matches!(result, Err(EcsErrors::DuplicateName(_))));
+        world.update();
+        assert_eq!(world.entity_manager().alive_entities(), vec![boss]);
+    }
+
+    #[test]
+    fn failed_instantiate_removes_spawned_entities() {
+        let mut world = World::new();
+        let prefab = Prefab::new().with(Health(1)).with_component(Rc::new(Failing)).with_child(&goblin());
+
+        assert!(world.try_instantiate(&prefab).is_err());
+        assert!(!world.entity_manager().is_alive(&Entity(0)));
+        assert!(!world.entity_manager().is_alive(&Entity(1)));
+        world.update();
+        assert!(world.query().components().get::<Health>().data.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn instantiate_through_command_buffer() {
+        let mut world = World::new();
+        world.register_component::<Name>();
+        let spawner = world.create_entity().with_component(Name("portal")).finish_entity();
+
+        let system = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Spawner(goblin()))
+            .with_component::<Name>()
+            .build();
+        world.add_system::<Spawner>(system, false);
+        world.update();
+        world.update_system::<Spawner>();
+
+        let spawned = Entity(spawner.0 + 1);
+        let query = world.query();
+        assert_eq!(*query.components().get::<Name>().get(spawned.0).unwrap(), Name("goblin"));
+        assert_eq!(*query.components().get::<Health>().get(spawned.0).unwrap(), Health(3));
+    }
+}
+
+#[cfg(test)]
+mod clone {
+    use std::alloc::Layout;
+
+    use ecs_macro::Component;
+
+    use crate::command_buffer::CommandBuffer;
+    use crate::components::{
+        clone::{clone_component, ClonePolicy},
+        Component,
+    };
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::hierarchy::Parent;
+    use crate::events::EventEmitter;
+    use crate::query::Query;
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component, Clone, Debug, PartialEq)]
+    struct Health(u32);
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Handle(u32);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Tag(&'static str);
+
+    impl Component for Tag {}
+
+    struct Duplicate;
+
+    impl System for Duplicate {
+        fn action(&mut self, _: Query, entities: &[Entity], commands: &mut CommandBuffer, _: EventEmitter) {
+            for entity in entities {
+                commands.clone_entity_with(entity, ClonePolicy::Error);
+                commands.clone_entity(entity);
+            }
+        }
+    }
+
+    #[test]
+    fn clone_registered_components() {
+        let mut world = World::new();
+        world.register_clone::<Tag>();
+        let scale = world.register_dynamic_component("scale", Layout::new::<f32>()).unwrap();
+
+        let parent = world.create_entity().finish_entity();
+        let original = world
+            .create_entity()
+            .with_component(Health(7))
+            .with_component(Tag("orc"))
+            .with_component(Handle(1))
+            .finish_entity();
+        world.add_dynamic_component(&original, scale, &2.5f32.to_ne_bytes()).unwrap();
+        world.set_parent(&original, &parent).unwrap();
+
+        let copy = world.clone_entity(&original);
+        assert_ne!(copy, original);
+        assert!(!world.has_component::<Handle>(&copy));
+        assert!(world.has_component_id(&copy, scale));
+
+        let query = world.query();
+        assert_eq!(*query.components().get::<Health>().get(copy.0).unwrap(), Health(7));
+        assert_eq!(*query.components().get::<Tag>().get(copy.0).unwrap(), Tag("orc"));
+        assert_eq!(query.parent(&copy), Some(parent));
+        assert_eq!(query.children(&parent), vec![original, copy]);
+        let bytes = query.components().get_dynamic(scale).get(copy.0).unwrap().to_vec();
+        assert_eq!(bytes, 2.5f32.to_ne_bytes());
+
+        let err = world.clone_entity_with(&original, ClonePolicy::Error).unwrap_err();
+        assert!(matches!(err, EcsErrors::NotCloneable(name) if name.ends_with("Handle")));
+    }
+
+    #[test]
+    fn clone_disabled_entities() {
+        let mut world = World::new();
+        let original = world.create_entity().with_component(Health(2)).finish_entity();
+        world.update();
+        world.disable_entity(&original);
+
+        let copy = world.clone_entity_with(&original, ClonePolicy::Error).unwrap();
+        assert!(world.is_disabled(&copy));
+        assert_eq!(*world.query().components().get::<Health>().get(copy.0).unwrap(), Health(2));
+    }
+
+    #[test]
+    fn failed_clone_removes_the_copy() {
+        let mut world = World::new();
+        let original = world
+            .create_entity()
+            .with_component(Health(2))
+            .with_component(Tag("orc"))
+            .finish_entity();
+        world.update();
+        // Tag is left out of the source, so copying it fails.
+        world.entity_manager_mut().component_manager.set_clone_fn::<Tag>(clone_component::<Tag>);
+        world.entity_manager_mut().component_manager.get_components_mut::<Tag>().unwrap().remove(original.0).unwrap();
+
+        assert!(world.clone_entity_with(&original, ClonePolicy::Skip).is_err());
+        world.update();
+        assert_eq!(world.entity_manager().alive_entities(), vec![original]);
+    }
+
+    #[test]
+    fn clone_under_a_missing_parent_removes_the_copy() {
+        let mut world = World::new();
+        let original = world.spawn().with(Health(2)).commit();
+        let gone = world.spawn().commit();
+        world.remove_entity(&gone);
+        world.update();
+        // Left behind on purpose, `remove_entity` would have cleared it.
+        world.entity_manager_mut().add_component(&original, Parent(gone)).unwrap();
+
+        assert!(world.clone_entity_with(&original, ClonePolicy::Skip).is_err());
+        world.update();
+        assert_eq!(world.entity_manager().alive_entities(), vec![original]);
+    }
+
+    #[test]
+    fn clone_missing_component_fails() {
+        let mut world = World::new();
+        world.create_entity().with_component(Health(2)).finish_entity();
+        let empty = world.create_entity().finish_entity();
+
+        let component_manager = &world.entity_manager().component_manager;
+        let id = component_manager.component_id::<Health>().unwrap();
+        assert!(matches!(
+            clone_component::<Health>(component_manager, id, &empty),
+            Err(EcsErrors::ComponentDoesNotExist(_))
+        ));
+    }
+
+    #[test]
+    fn clone_through_command_buffer() {
+        let mut world = World::new();
+        world.register_component::<Health>();
+        let original = world.create_entity().with_component(Health(3)).finish_entity();
+        let locked = world
+            .create_entity()
+            .with_component(Health(4))
+            .with_component(Handle(2))
+            .finish_entity();
+
+        let system = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Duplicate)
+            .with_component::<Health>()
+            .build();
+        world.add_system::<Duplicate>(system, false);
+        world.update();
+        world.update_system::<Duplicate>();
+
+        // Commands apply in the order they were issued: both copies of
+        // `original`, but only the skipping one of `locked`.
+        let query = world.query();
+        let health = query.components().get::<Health>();
+        let copies: Vec<u32> = (locked.0 + 1..locked.0 + 4).map(|id| health.get(id).unwrap().0).collect();
+        assert_eq!(copies, vec![3, 3, 4]);
+        assert!(world.has_component::<Health>(&original));
+    }
+}
+
+#[cfg(test)]
+mod builder {
+    use ecs_macro::Component;
+
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::Entity;
+    use crate::events::EventEmitter;
+    use crate::query::Query;
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component, Clone)]
+    struct Position;
+
+    #[derive(Component, Clone)]
+    struct Velocity;
+
+    #[derive(Component, Default)]
+    #[require(Yolk)]
+    struct Shell;
+
+    #[derive(Component, Default)]
+    #[require(Shell)]
+    struct Yolk;
+
+    #[derive(Default)]
+    struct Moved(Vec<Entity>);
+
+    struct Movement;
+
+    impl System for Movement {
+        fn action(&mut self, query: Query, entities: &[Entity], _: &mut CommandBuffer, _: EventEmitter) {
+            query.resource_mut::<Moved>().get_mut::<Moved>().0 = entities.to_vec();
+        }
+    }
+
+    fn world_with_movement<'a>() -> World<'a> {
+        let mut world = World::new();
+        world.register_component::<Position>();
+        world.register_component::<Velocity>();
+        world.add_resource(Moved::default());
+
+        let system = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Movement)
+            .with_component::<Position>()
+            .with_component::<Velocity>()
+            .build();
+        world.add_system::<Movement>(system, false);
+        world
+    }
+
+    fn moved(world: &mut World) -> Vec<Entity> {
+        world.update_system::<Movement>();
+        world.query().resource::<Moved>().get::<Moved>().0.clone()
+    }
+
+    #[test]
+    fn spawned_entities_join_systems_immediately() {
+        let mut world = world_with_movement();
+
+        let runner = world.spawn().with(Position).with(Velocity).commit();
+        let statue = world.spawn().with(Position).commit();
+        assert_eq!(moved(&mut world), vec![runner]);
+
+        world.add_component(&statue, Velocity);
+        world.remove_component::<Velocity>(&runner);
+        assert_eq!(moved(&mut world), vec![statue]);
+    }
+
+    #[test]
+    fn systems_added_before_update_list_entities_once() {
+        let mut world = World::new();
+        world.add_resource(Moved::default());
+        let pending = world
+            .create_entity()
+            .with_component(Position)
+            .with_component(Velocity)
+            .finish_entity();
+        let spawned = world.spawn().with((Position, Velocity)).commit();
+
+        let system = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Movement)
+            .with_component::<Position>()
+            .with_component::<Velocity>()
+            .build();
+        world.add_system::<Movement>(system, true);
+        world.update();
+        assert_eq!(moved(&mut world), vec![spawned, pending]);
+    }
+
+    #[test]
+    fn clones_join_systems_immediately() {
+        let mut world = world_with_movement();
+
+        let runner = world.spawn().with(Position).with(Velocity).commit();
+        let copy = world.clone_entity(&runner);
+        assert_eq!(moved(&mut world), vec![runner, copy]);
+    }
+
+    #[test]
+    fn failed_commit_leaves_no_entity() {
+        let mut world = world_with_movement();
+
+        // Dropping a builder never allocates an id.
+        drop(world.spawn().with(Position));
+        assert!(world.spawn().with(Position).with(Shell).try_commit().is_err());
+        assert!(world.query().entities().with_component::<Position>().get().is_empty());
+
+        let entity = world.spawn().with((Position, Velocity)).commit();
+        assert_eq!(entity, Entity(0));
+        assert_eq!(moved(&mut world), vec![entity]);
+    }
+}
+
+#[cfg(test)]
+mod membership {
+    use ecs_macro::Component;
+
+    use super::Rng;
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::Entity;
+    use crate::events::EventEmitter;
+    use crate::query::Query;
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component)]
+    struct Position(i32);
+
+    #[derive(Component)]
+    struct Velocity(i32);
+
+    #[derive(Component)]
+    struct Mass;
+
+    struct Movement;
+
+    impl System for Movement {
+        fn action(&mut self, query: Query, entities: &[Entity], _: &mut CommandBuffer, _: EventEmitter) {
+            let velocities = query.components().get::<Velocity>();
+            let mut positions = query.components().get_mut::<Position>();
+            for entity in entities {
+                positions.get_mut(entity.0).unwrap().0 += velocities.get(entity.0).unwrap().0;
+            }
+        }
+    }
+
+    struct Physics;
+
+    impl System for Physics {
+        fn action(&mut self, _: Query, entities: &[Entity], commands: &mut CommandBuffer, _: EventEmitter) {
+            for entity in entities {
+                commands.remove_component::<Velocity>(entity);
+            }
+        }
+    }
+
+    struct Render;
+
+    impl System for Render {
+        fn action(&mut self, _: Query, _: &[Entity], _: &mut CommandBuffer, _: EventEmitter) {}
+    }
+
+    fn world_with_systems<'a>() -> World<'a> {
+        let mut world = World::new();
+        world.register_component::<Position>();
+        world.register_component::<Velocity>();
+        world.register_component::<Mass>();
+        let signatures = world.get_component_signatures();
+
+        let movement = SystemBuilder::new(signatures.clone())
+            .with_action(Movement)
+            .with_component::<Position>()
+            .with_component::<Velocity>()
+            .build();
+        let physics = SystemBuilder::new(signatures.clone())
+            .with_action(Physics)
+            .with_component::<Position>()
+            .with_component::<Velocity>()
+            .with_component::<Mass>()
+            .build();
+        let render = SystemBuilder::new(signatures)
+            .with_action(Render)
+            .with_component::<Position>()
+            .build();
+        world.add_system::<Movement>(movement, false);
+        world.add_system::<Physics>(physics, false);
+        world.add_system::<Render>(render, false);
+        world
+    }
+
+    fn members<T: 'static>(world: &World) -> Vec<Entity> {
+        let mut entities = world.get_system::<T>().entities().to_vec();
+        entities.sort();
+        entities
+    }
+
+    fn assert_in_sync(world: &World, alive: &[Entity]) {
+        let matching = |has: &dyn Fn(&Entity) -> bool| -> Vec<Entity> {
+            let mut entities: Vec<Entity> = alive.iter().copied().filter(|e| has(e)).collect();
+            entities.sort();
+            entities
+        };
+        let moving = |e: &Entity| world.has_component::<Position>(e) && world.has_component::<Velocity>(e);
+
+        assert_eq!(members::<Movement>(world), matching(&moving));
+        assert_eq!(members::<Physics>(world), matching(&|e| moving(e) && world.has_component::<Mass>(e)));
+        assert_eq!(members::<Render>(world), matching(&|e| world.has_component::<Position>(e)));
+    }
+
+    #[test]
+    fn removed_components_leave_systems() {
+        let mut world = world_with_systems();
+        let entity = world.spawn().with((Position(0), Velocity(2))).commit();
+        world.update_system::<Movement>();
+
+        world.remove_component::<Velocity>(&entity);
+        assert!(members::<Movement>(&world).is_empty());
+        assert_eq!(members::<Render>(&world), vec![entity]);
+        world.update_system::<Movement>();
+
+        world.add_component(&entity, Velocity(3));
+        world.update_system::<Movement>();
+        assert_eq!(world.query().components().get::<Position>().get(entity.0).unwrap().0, 5);
+    }
+
+    #[test]
+    fn commands_and_kills_update_every_system() {
+        let mut world = world_with_systems();
+        let heavy = world.spawn().with((Position(0), Velocity(1), Mass)).commit();
+        let light = world.spawn().with((Position(0), Velocity(1))).commit();
+        assert_eq!(members::<Physics>(&world), vec![heavy]);
+
+        // Physics strips the velocity of heavy entities before movement runs.
+        world.update_system::<Physics>();
+        world.run_systems();
+        assert_eq!(members::<Movement>(&world), vec![light]);
+        assert!(members::<Physics>(&world).is_empty());
+        assert_eq!(world.query().components().get::<Position>().get(heavy.0).unwrap().0, 0);
+
+        world.remove_entity(&light);
+        world.update();
+        assert!(members::<Movement>(&world).is_empty());
+        assert_eq!(members::<Render>(&world), vec![heavy]);
+    }
+
+    #[test]
+    fn random_sequences_keep_systems_in_sync() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut world = world_with_systems();
+        let mut alive: Vec<Entity> = vec![];
+
+        for _ in 0..500 {
+            let target = (!alive.is_empty()).then(|| alive[rng.next(alive.len())]);
+            match (rng.next(7), target) {
+                (0, _) | (_, None) => alive.push(world.spawn().with(Position(0)).commit()),
+                (1, Some(e)) => world.add_component(&e, Velocity(1)),
+                (2, Some(e)) => world.add_component(&e, Mass),
+                (3, Some(e)) if world.has_component::<Velocity>(&e) => world.remove_component::<Velocity>(&e),
+                (4, Some(e)) if world.has_component::<Position>(&e) => world.remove_component::<Position>(&e),
+                (5, Some(e)) => world.insert_bundle(&e, (Position(0), Velocity(0))),
+                (6, Some(e)) => {
+                    world.remove_entity(&e);
+                    world.update();
+                    alive.retain(|a| *a != e);
+                }
+                _ => world.remove_bundle::<(Velocity, Mass)>(&target.unwrap()),
+            }
+            assert_in_sync(&world, &alive);
+        }
+    }
+}
+
+#[cfg(test)]
+mod cached_query {
+    use ecs_macro::Component;
+
+    use crate::cached_query::QueryHandle;
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::Entity;
+    use crate::events::EventEmitter;
+    use crate::query::Query;
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component, Clone)]
+    struct Position;
+
+    #[derive(Component, Clone)]
+    struct Velocity;
+
+    fn sorted(entities: &[Entity]) -> Vec<Entity> {
+        let mut entities = entities.to_vec();
+        entities.sort();
+        entities
+    }
+
+    struct Count(usize);
+
+    struct Counter(QueryHandle);
+
+    impl System for Counter {
+        fn action(&mut self, query: Query, _: &[Entity], _: &mut CommandBuffer, _: EventEmitter) {
+            query.resource_mut::<Count>().get_mut::<Count>().0 = query.cached(&self.0).len();
+        }
+    }
+
+    #[test]
+    fn pending_entities_match_before_update() {
+        let mut world = World::new();
+        let moving = world.register_query::<Position>();
+        let pending = world.create_entity().with_component(Position).finish_entity();
+
+        assert_eq!(world.query_entities(&moving), &[pending]);
+        assert_eq!(world.query().entities().with_component::<Position>().get(), vec![pending]);
+    }
+
+    #[test]
+    fn match_sets_follow_signature_changes() {
+        let mut world = World::new();
+        let early = world.spawn().with((Position, Velocity)).commit();
+        let moving = world.register_query::<(Position, Velocity)>();
+        assert_eq!(world.register_query::<(Position, Velocity)>(), moving);
+        let placed = world.register_query::<Position>();
+        assert_eq!(world.query_entities(&moving), &[early]);
+
+        let legacy = world.create_entity().with_component(Position).finish_entity();
+        let spawned = world.spawn().with(Position).commit();
+        world.add_component(&spawned, Velocity);
+        assert_eq!(sorted(world.query_entities(&moving)), vec![early, spawned]);
+        assert_eq!(sorted(world.query_entities(&placed)), vec![early, legacy, spawned]);
+
+        world.remove_component::<Velocity>(&early);
+        world.remove_entity(&legacy);
+        world.update();
+        assert_eq!(world.query_entities(&moving), &[spawned]);
+        assert_eq!(sorted(world.query_entities(&placed)), vec![early, spawned]);
+
+        let snapshot = {
+            world.register_snapshot_component::<Position>();
+            world.register_snapshot_component::<Velocity>();
+            world.snapshot()
+        };
+        world.remove_component::<Velocity>(&spawned);
+        assert!(world.query_entities(&moving).is_empty());
+        world.restore(&snapshot);
+        assert_eq!(world.query_entities(&moving), &[spawned]);
+    }
+
+    #[test]
+    fn systems_read_cached_queries() {
+        let mut world = World::new();
+        let handle = world.register_query::<(Position, Velocity)>();
+        world.add_resource(Count(0));
+        for _ in 0..3 {
+            world.spawn().with((Position, Velocity)).commit();
+        }
+
+        let system = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Counter(handle))
+            .with_component::<Position>()
+            .build();
+        world.add_system::<Counter>(system, true);
+        world.run_systems();
+
+        assert_eq!(world.query().resource::<Count>().get::<Count>().0, 3);
+    }
+}
+
+#[cfg(test)]
+mod typed_query {
+    use ecs_macro::Component;
+
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::world::World;
+
+    #[derive(Component, Debug, PartialEq)]
+    struct Position(i64);
+
+    #[derive(Component)]
+    struct Velocity(i64);
+
+    fn boids<'a>() -> World<'a> {
+        let mut world = World::new();
+        for i in 0..1000 {
+            world.spawn().with((Position(0), Velocity(i))).commit();
+        }
+        world.spawn().with(Position(-1)).commit();
+        world
+    }
+
+    #[test]
+    fn par_for_each_matches_sequential() {
+        let mut sequential = boids();
+        let mut parallel = boids();
+        let tick = parallel.tick();
+        sequential.update();
+        parallel.update();
+
+        sequential
+            .query()
+            .typed::<(&mut Position, &Velocity)>()
+            .for_each(|(position, velocity)| position.0 += velocity.0);
+        parallel
+            .query()
+            .typed::<(&mut Position, &Velocity)>()
+            .batch_size(7)
+            .par_for_each(|(position, velocity)| position.0 += velocity.0);
+
+        let query = parallel.query();
+        let positions = query.components().get::<Position>();
+        assert_eq!(positions.get(999).unwrap().0, 999);
+        assert_eq!(positions.get(1000).unwrap().0, -1);
+        assert!(positions.changed_since(5, tick + 1));
+        assert!(!positions.changed_since(1000, tick + 1));
+        assert_eq!(positions.data, sequential.query().components().get::<Position>().data);
+    }
+
+    #[test]
+    fn iterate_given_entities() {
+        let mut world = boids();
+        let handle = world.register_query::<(Position, Velocity)>();
+
+        let query = world.query();
+        let mut visited = vec![];
+        query
+            .typed::<(Entity, &Position)>()
+            .with_entities(query.cached(&handle))
+            .for_each(|(entity, _)| visited.push(entity));
+        assert_eq!(visited.len(), 1000);
+
+        let twice = [Entity(3), Entity(3)];
+        let result = query.typed::<&mut Position>().with_entities(&twice).try_for_each(|_| {});
+        assert!(matches!(result, Err(EcsErrors::InvalidQueryEntity(3))));
+        let unmatched = [Entity(1000)];
+        let result = query.typed::<&Velocity>().with_entities(&unmatched).try_par_for_each(|_| {});
+        assert!(matches!(result, Err(EcsErrors::InvalidQueryEntity(1000))));
+    }
+}
+
+#[cfg(test)]
+mod index {
+    use std::borrow::Borrow;
+
+    use ecs_macro::Component;
+
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::world::World;
+
+    #[derive(Component, Clone, PartialEq, Eq, Hash)]
+    struct PlayerId(u32);
+
+    impl Borrow<u32> for PlayerId {
+        fn borrow(&self) -> &u32 {
+            &self.0
+        }
+    }
+
+    #[derive(Component, Clone, PartialEq, Eq, Hash, Debug)]
+    enum Team {
+        Red,
+        Blue,
+    }
+
+    #[derive(Component, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct Score(u32);
+
+    #[test]
+    fn lookup_follows_add_set_remove_and_mutate() {
+        let mut world = World::new();
+        let first = world.spawn().with((PlayerId(7), Team::Red)).commit();
+        world.register_index::<PlayerId>();
+        world.register_index::<Team>();
+        let second = world.spawn().with((PlayerId(8), Team::Red)).commit();
+        let third = world.spawn().with(Team::Blue).commit();
+
+        assert_eq!(world.query().index::<PlayerId>().get(&7), vec![first]);
+        assert_eq!(world.query().index::<Team>().get(&Team::Red), vec![first, second]);
+
+        world.add_component(&third, PlayerId(7));
+        world.remove_component::<Team>(&first);
+        *world.query().components().get_mut::<PlayerId>().get_mut(second.0).unwrap() = PlayerId(9);
+
+        let query = world.query();
+        assert_eq!(query.index::<PlayerId>().get(&7), vec![first, third]);
+        assert_eq!(query.index::<PlayerId>().first(&9), Some(second));
+        assert!(query.index::<PlayerId>().get(&8).is_empty());
+        assert_eq!(query.index::<Team>().get(&Team::Red), vec![second]);
+
+        world.remove_entity(&third);
+        world.update();
+        assert_eq!(world.query().index::<PlayerId>().get(&7), vec![first]);
+        world.register_component::<Score>();
+        assert!(matches!(world.query().try_index::<Score>(), Err(EcsErrors::ComponentNotIndexed(_))));
+    }
+
+    #[test]
+    fn lookups_share_the_pool_with_readers() {
+        let mut world = World::new();
+        world.register_index::<PlayerId>();
+        let first = world.spawn().with(PlayerId(7)).commit();
+        let second = world.spawn().with(PlayerId(8)).commit();
+        *world.query().components().get_mut::<PlayerId>().get_mut(second.0).unwrap() = PlayerId(7);
+
+        let query = world.query();
+        let ids = query.components().get::<PlayerId>();
+        let by_seven = query.index::<PlayerId>();
+        let by_eight = query.index::<PlayerId>();
+        assert_eq!(by_seven.get(&7), vec![first, second]);
+        assert!(by_eight.get(&8).is_empty());
+        assert_eq!(ids.get(first.0).unwrap().0, 7);
+    }
+
+    #[test]
+    #[should_panic(expected = "has no hash index")]
+    fn first_needs_hash_index() {
+        let mut world = World::new();
+        world.register_ordered_index::<Score>();
+        world.spawn().with(Score(3)).commit();
+        world.query().index::<Score>().first(&Score(3));
+    }
+
+    #[test]
+    fn range_matches_brute_force() {
+        let mut world = World::new();
+        world.register_ordered_index::<Score>();
+        for i in 0..200u32 {
+            world.spawn().with(Score(i * 7919 % 101)).commit();
+        }
+        world
+            .query()
+            .typed::<&mut Score>()
+            .for_each(|score| score.0 = (score.0 * 3) % 101);
+
+        let query = world.query();
+        let scores = query.components().get::<Score>();
+        let mut expected: Vec<(u32, Entity)> = scores
+            .iter()
+            .enumerate()
+            .filter_map(|(id, s)| s.as_ref().map(|s| (s.0, Entity(id))))
+            .filter(|(s, _)| (20..=40).contains(s))
+            .collect();
+        expected.sort();
+        drop(scores);
+
+        let found = query.index::<Score>().range(Score(20)..=Score(40));
+        assert_eq!(found, expected.into_iter().map(|(_, e)| e).collect::<Vec<_>>());
+    }
+}
+
+#[cfg(all(test, feature = "spatial"))]
+mod spatial {
+    use ecs_macro::Component;
+
+    use super::Rng;
+    use crate::entities::Entity;
+    use crate::spatial::{
+        Aabb, Point, QuadTree, SpatialIndex, SpatialIndexing, SpatialPosition, SpatialStructure, UniformGrid,
+    };
+    use crate::world::World;
+
+    #[derive(Component)]
+    struct Position(Point);
+
+    impl SpatialPosition for Position {
+        fn position(&self) -> Point {
+            self.0
+        }
+    }
+
+    fn random_point(rng: &mut Rng) -> Point {
+        [rng.next(2000) as f32 / 10.0 - 100.0, rng.next(2000) as f32 / 10.0 - 100.0]
+    }
+
+    fn brute_force(world: &World, f: impl Fn(Point) -> bool) -> Vec<Entity> {
+        let query = world.query();
+        let positions = query.components().get::<Position>();
+        positions
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.as_ref().is_some_and(|p| f(p.0)))
+            .map(|(id, _)| Entity(id))
+            .collect()
+    }
+
+    fn brute_force_nearest(world: &World, point: Point, k: usize) -> Vec<Entity> {
+        let query = world.query();
+        let positions = query.components().get::<Position>();
+        let mut all: Vec<(f32, Entity)> = positions
+            .iter()
+            .enumerate()
+            .filter_map(|(id, p)| {
+                let p = p.as_ref()?.0;
+                Some(((p[0] - point[0]).powi(2) + (p[1] - point[1]).powi(2), Entity(id)))
+            })
+            .collect();
+        all.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        all.into_iter().take(k).map(|(_, e)| e).collect()
+    }
+
+    fn matches_brute_force<S: SpatialStructure>(structure: S) {
+        let mut world = World::new();
+        world.add_spatial_index::<Position, S>(structure);
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+        let mut entities = vec![];
+
+        for round in 0..5 {
+            for _ in 0..200 {
+                entities.push(world.spawn().with(Position(random_point(&mut rng))).commit());
+            }
+            for _ in 0..100 {
+                let entity = entities[rng.next(entities.len())];
+                if let Ok(mut positions) = world.query().components().try_get_mut::<Position>() {
+                    if let Ok(position) = positions.get_mut(entity.0) {
+                        position.0 = random_point(&mut rng);
+                    }
+                }
+            }
+            for _ in 0..20 {
+                let entity = entities.swap_remove(rng.next(entities.len()));
+                world.remove_entity(&entity);
+            }
+            world.update();
+            world.update_system::<SpatialIndexing<Position, S>>();
+
+            let query = world.query();
+            let resource = query.resource::<SpatialIndex<Position, S>>();
+            let index = resource.get::<SpatialIndex<Position, S>>();
+            assert_eq!(index.len(), entities.len(), "round {round}");
+            for _ in 0..20 {
+                let center = random_point(&mut rng);
+                let radius = rng.next(300) as f32 / 10.0;
+                let within = |p: Point| (p[0] - center[0]).powi(2) + (p[1] - center[1]).powi(2) <= radius * radius;
+                assert_eq!(index.within_radius(center, radius), brute_force(&world, within));
+
+                let aabb = Aabb::around(center, radius);
+                assert_eq!(index.within_aabb(&aabb), brute_force(&world, |p| aabb.contains(p)));
+
+                let k = rng.next(12);
+                assert_eq!(index.nearest(center, k), brute_force_nearest(&world, center, k));
+            }
+        }
+    }
+
+    #[test]
+    fn grid_matches_brute_force() {
+        matches_brute_force(UniformGrid::new(8.0));
+    }
+
+    #[test]
+    fn quadtree_matches_brute_force() {
+        // Starts smaller than the spawn area, so the root has to grow.
+        matches_brute_force(QuadTree::new(Aabb::new([0.0, 0.0], [10.0, 10.0])));
+    }
+
+    #[test]
+    fn index_leaves_out_disabled_entities() {
+        let mut world = World::new();
+        world.add_spatial_index::<Position, UniformGrid>(UniformGrid::new(8.0));
+        let near = world.spawn().with(Position([1.0, 1.0])).commit();
+        let far = world.spawn().with(Position([5.0, 5.0])).commit();
+
+        world.update();
+        world.disable_entity(&near);
+        world.update_system::<SpatialIndexing<Position, UniformGrid>>();
+        {
+            let query = world.query();
+            let resource = query.resource::<SpatialIndex<Position, UniformGrid>>();
+            let index = resource.get::<SpatialIndex<Position, UniformGrid>>();
+            assert_eq!(index.nearest([0.0, 0.0], 2), vec![far]);
+        }
+
+        world.update();
+        world.enable_entity(&near);
+        world.update_system::<SpatialIndexing<Position, UniformGrid>>();
+        let query = world.query();
+        let resource = query.resource::<SpatialIndex<Position, UniformGrid>>();
+        let index = resource.get::<SpatialIndex<Position, UniformGrid>>();
+        assert_eq!(index.nearest([0.0, 0.0], 2), vec![near, far]);
+    }
+
+    #[test]
+    fn quadtree_removes_points_on_edges() {
+        let mut tree = QuadTree::new(Aabb::new([0.0, 0.0], [10.0, 10.0]));
+        tree.insert(Entity(0), [10.0, 5.0]);
+        tree.insert(Entity(1), [15.0, 5.0]);
+        tree.remove(Entity(0), [10.0, 5.0]);
+        assert_eq!(tree.nearest([10.0, 5.0], 2), vec![(25.0, Entity(1))]);
+
+        // Corners, edges and midlines of the initial bounds, enough of them to
+        // split the quarters a few times over.
+        let points: Vec<Point> = (0..=4)
+            .flat_map(|x| (0..=4).map(move |y| [x as f32 * 2.5, y as f32 * 2.5]))
+            .chain([[20.0, 20.0], [-10.0, 10.0]])
+            .collect();
+        for (id, point) in points.iter().enumerate() {
+            tree.insert(Entity(id + 2), *point);
+        }
+        for (id, point) in points.iter().enumerate() {
+            tree.remove(Entity(id + 2), *point);
+        }
+
+        let mut left = vec![];
+        tree.for_each_in(&Aabb::new([-100.0, -100.0], [100.0, 100.0]), &mut |e, _| left.push(e));
+        assert_eq!(left, vec![Entity(1)]);
+    }
+}
+
+#[cfg(test)]
+mod sorted_view {
+    use std::borrow::Borrow;
+
+    use ecs_macro::Component;
+
+    use crate::entities::Entity;
+    use crate::sorted_view::SortedView;
+    use crate::world::World;
+
+    #[derive(Component)]
+    struct ZIndex(i32);
+
+    impl Borrow<i32> for ZIndex {
+        fn borrow(&self) -> &i32 {
+            &self.0
+        }
+    }
+
+    #[derive(Component, PartialEq, Eq, PartialOrd, Ord, Clone)]
+    struct Initiative(u8);
+
+    #[derive(Component)]
+    struct Visible;
+
+    #[test]
+    fn sorted_by_key_orders_matching_entities() {
+        let mut world = World::new();
+        let back = world.spawn().with((ZIndex(-3), Visible)).commit();
+        let hidden = world.spawn().with(ZIndex(-5)).commit();
+        let front = world.spawn().with((ZIndex(4), Visible)).commit();
+        let middle = world.spawn().with((ZIndex(0), Visible)).commit();
+        let tied = world.spawn().with((ZIndex(0), Visible)).commit();
+
+        let query = world.query();
+        let visible = query.entities().with_component::<Visible>().sorted_by_key::<ZIndex, i32>();
+        assert_eq!(visible, vec![back, middle, tied, front]);
+        assert_eq!(query.entities().sorted_by_key::<ZIndex, i32>()[0], hidden);
+    }
+
+    #[test]
+    fn view_follows_changes() {
+        let mut world = World::new();
+        let mut view = SortedView::<Initiative, Initiative>::new();
+        let entities: Vec<Entity> = (0..50u8)
+            .map(|i| world.spawn().with(Initiative(i.wrapping_mul(37) % 20)).commit())
+            .collect();
+        view.refresh(&world.query());
+        assert_eq!(view.entities(), world.query().entities().sorted_by_key::<Initiative, Initiative>());
+
+        for round in 0..5u8 {
+            world.update();
+            for (i, entity) in entities.iter().enumerate().skip(round as usize).step_by(7) {
+                let query = world.query();
+                let mut initiatives = query.components().get_mut::<Initiative>();
+                if initiatives.data[entity.0].is_some() {
+                    initiatives.get_mut(entity.0).unwrap().0 = (i as u8 + round * 11) % 20;
+                }
+            }
+            world.remove_component::<Initiative>(&entities[round as usize * 3]);
+            world.spawn().with(Initiative(round)).commit();
+
+            view.refresh(&world.query());
+            let expected = world.query().entities().sorted_by_key::<Initiative, Initiative>();
+            assert_eq!(view.entities(), expected, "round {round}");
+        }
+        assert_eq!(view.len(), 50);
+    }
+
+    #[test]
+    fn view_drops_entities_past_a_shrunk_pool() {
+        let mut world = World::new();
+        world.register_snapshot_component::<Initiative>();
+        world.spawn().with(Initiative(1)).commit();
+        let snapshot = world.snapshot();
+        let length = world.query().components().get::<Initiative>().data.len();
+
+        let mut view = SortedView::<Initiative, Initiative>::new();
+        for _ in 0..length {
+            world.spawn().with(Initiative(2)).commit();
+        }
+        view.refresh(&world.query());
+        assert_eq!(view.len(), length + 1);
+
+        world.update();
+        world.restore(&snapshot);
+        view.refresh(&world.query());
+        assert_eq!(view.entities(), vec![Entity(0)]);
+    }
+
+    #[test]
+    fn view_leaves_out_disabled_entities() {
+        let mut world = World::new();
+        let mut view = SortedView::<Initiative, Initiative>::new();
+        let first = world.spawn().with(Initiative(1)).commit();
+        let second = world.spawn().with(Initiative(2)).commit();
+        view.refresh(&world.query());
+
+        world.update();
+        world.disable_entity(&first);
+        view.refresh(&world.query());
+        assert_eq!(view.entities(), vec![second]);
+
+        world.update();
+        world.enable_entity(&first);
+        view.refresh(&world.query());
+        assert_eq!(view.entities(), vec![first, second]);
+    }
+}
+
+#[cfg(test)]
+mod related {
+    use ecs_macro::Component;
+
+    use crate::entities::{Entity, EntityRef};
+    use crate::typed_query::Related;
+    use crate::world::World;
+
+    #[derive(Component)]
+    struct Position(i64);
+
+    #[derive(Component)]
+    struct Target(Entity);
+
+    impl EntityRef for Target {
+        fn entity(&self) -> Entity {
+            self.0
+        }
+    }
+
+    #[derive(Component)]
+    struct Owner(Entity);
+
+    impl EntityRef for Owner {
+        fn entity(&self) -> Entity {
+            self.0
+        }
+    }
+
+    #[test]
+    fn join_skips_dangling_targets() {
+        let mut world = World::new();
+        let goal = world.spawn().with(Position(10)).commit();
+        let doomed = world.spawn().with(Position(20)).commit();
+        let bare = world.spawn().commit();
+        let hunter = world.spawn().with((Position(1), Target(goal))).commit();
+        world.spawn().with((Position(2), Target(doomed))).commit();
+        world.spawn().with((Position(3), Target(bare))).commit();
+        let follower = world.spawn().with((Position(4), Target(hunter))).commit();
+        world.remove_entity(&doomed);
+        world.update();
+
+        let mut pairs = vec![];
+        world
+            .query()
+            .typed::<(Entity, &Position, Related<Target, (Entity, &Position)>)>()
+            .for_each(|(entity, position, (target, target_position))| {
+                pairs.push((entity, position.0, target, target_position.0))
+            });
+        assert_eq!(pairs, vec![(hunter, 1, goal, 10), (follower, 4, hunter, 1)]);
+
+        let query = world.query();
+        let sources = [follower, hunter];
+        let mut seen = vec![];
+        query
+            .typed::<(Entity, Related<Target, Related<Target, &Position>>)>()
+            .with_entities(&sources)
+            .for_each(|(entity, position)| seen.push((entity, position.0)));
+        assert_eq!(seen, vec![(follower, 10)]);
+    }
+
+    #[test]
+    fn shared_targets_in_parallel() {
+        let mut world = World::new();
+        world.spawn().commit();
+        let base = world.spawn().with(Position(5)).commit();
+        for i in 0..100 {
+            world.spawn().with((Position(i), Owner(base))).commit();
+        }
+
+        world
+            .query()
+            .typed::<(&mut Position, Related<Owner, Entity>)>()
+            .batch_size(9)
+            .par_for_each(|(position, owner)| position.0 += owner.0 as i64);
+
+        let query = world.query();
+        let positions = query.components().get::<Position>();
+        assert_eq!(positions.get(base.0).unwrap().0, 5);
+        assert!((2..102).all(|id| positions.get(id).unwrap().0 == id as i64 - 1));
+    }
+}
+
+#[cfg(test)]
+mod disabled {
+    use ecs_macro::Component;
+
+    use crate::command_buffer::CommandBuffer;
+    use crate::entities::{Disabled, Entity};
+    use crate::events::EventEmitter;
+    use crate::query::Query;
+    use crate::system::{System, SystemBuilder};
+    use crate::world::World;
+
+    #[derive(Component)]
+    struct Position(i32);
+
+    struct Movement;
+
+    impl System for Movement {
+        fn action(&mut self, query: Query, entities: &[Entity], _: &mut CommandBuffer, _: EventEmitter) {
+            let mut positions = query.components().get_mut::<Position>();
+            for entity in entities {
+                positions.get_mut(entity.0).unwrap().0 += 1;
+            }
+        }
+    }
+
+    struct Sleepers;
+
+    impl System for Sleepers {
+        fn action(&mut self, _: Query, _: &[Entity], _: &mut CommandBuffer, _: EventEmitter) {}
+    }
+
+    #[test]
+    fn disabled_entities_leave_systems_and_queries() {
+        let mut world = World::new();
+        world.register_component::<Position>();
+        let movement = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Movement)
+            .with_component::<Position>()
+            .build();
+        world.add_system::<Movement>(movement, false);
+        let handle = world.register_query::<Position>();
+
+        let npc = world.spawn().with(Position(0)).commit();
+        let player = world.spawn().with(Position(0)).commit();
+        world.disable_entity(&npc);
+        assert!(world.is_disabled(&npc));
+        world.run_systems();
+
+        assert_eq!(world.get_system::<Movement>().entities(), [player]);
+        assert_eq!(world.query_entities(&handle), [player]);
+        let query = world.query();
+        assert_eq!(query.entities().with_component::<Position>().get(), vec![player]);
+        assert_eq!(
+            query.entities().with_component::<Position>().include_disabled().get(),
+            vec![npc, player]
+        );
+        let mut visited = 0;
+        query.typed::<&Position>().for_each(|_| visited += 1);
+        query.typed::<&Position>().include_disabled().for_each(|_| visited += 10);
+        assert_eq!(visited, 21);
+
+        world.enable_entity(&npc);
+        world.run_systems();
+        let query = world.query();
+        let positions = query.components().get::<Position>();
+        assert_eq!((positions.get(npc.0).unwrap().0, positions.get(player.0).unwrap().0), (1, 2));
+        assert!(!world.is_disabled(&npc));
+    }
+
+    #[test]
+    fn systems_requiring_disabled_see_only_disabled() {
+        let mut world = World::new();
+        world.register_component::<Position>();
+        world.register_component::<Disabled>();
+        let sleepers = SystemBuilder::new(world.get_component_signatures())
+            .with_action(Sleepers)
+            .with_component::<Position>()
+            .with_component::<Disabled>()
+            .build();
+        world.add_system::<Sleepers>(sleepers, false);
+
+        let npc = world.create_entity().with_component(Position(0)).finish_entity();
+        world.spawn().with(Position(0)).commit();
+        world.disable_entity(&npc);
+        world.update();
+        assert_eq!(world.get_system::<Sleepers>().entities(), [npc]);
+
+        world.enable_entity(&npc);
+        assert!(world.get_system::<Sleepers>().entities().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod name {
+    use crate::entities::Entity;
+    use crate::errors::EcsErrors;
+    use crate::name::{Name, NameMode};
+    use crate::world::World;
+
+    #[test]
+    fn find_entities_by_name() {
+        let mut world = World::new();
+        assert_eq!(world.find_by_name("Player"), None);
+        let nameless = world.spawn().commit();
+        let player = world.spawn().commit();
+        world.set_name(&player, "Player");
+        let goblins: Vec<Entity> = (0..3).map(|_| world.spawn().with(Name("Goblin".to_owned())).commit()).collect();
+
+        assert_eq!(world.find_by_name("Player"), Some(player));
+        assert_eq!(world.find_all_by_name("Goblin"), goblins);
+
+        world.set_name(&goblins[1], "Chief");
+        world.remove_entity(&goblins[0]);
+        world.update();
+        assert_eq!(world.find_all_by_name("Goblin"), vec![goblins[2]]);
+        assert_eq!(world.find_by_name("Chief"), Some(goblins[1]));
+
+        assert_eq!(world.entity_manager().label(&player).to_string(), "Entity 1 \"Player\"");
+        assert_eq!(world.entity_manager().label(&nameless).to_string(), "Entity 0");
+    }
+
+    #[test]
+    fn find_while_names_are_read() {
+        let mut world = World::new();
+        let player = world.spawn().with(Name("Player".to_owned())).commit();
+        world.query().components().get_mut::<Name>().get_mut(player.0).unwrap().0 = "Hero".to_owned();
+
+        let query = world.query();
+        let names = query.components().get::<Name>();
+        assert_eq!(world.find_by_name("Hero"), Some(player));
+        assert_eq!(world.find_by_name("Player"), None);
+        assert_eq!(names.get(player.0).unwrap().0, "Hero");
+    }
+
+    #[test]
+    fn unique_names() {
+        let mut world = World::new();
+        world.set_name_mode(NameMode::Unique).unwrap();
+        let player = world.spawn().commit();
+        let other = world.spawn().commit();
+        world.set_name(&player, "Player");
+        world.set_name(&player, "Player");
+
+        let result = world.try_set_name(&other, "Player");
+        assert!(matches!(result, Err(EcsErrors::DuplicateName(name)) if name == "Player"));
+        world.set_name(&player, "Hero");
+        world.set_name(&other, "Player");
+        assert_eq!(world.find_by_name("Player"), Some(other));
+    }
+
+    #[test]
+    fn unique_mode_rejects_existing_duplicates() {
+        let mut world = World::new();
+        let first = world.spawn().with(Name("Twin".to_owned())).commit();
+        world.spawn().with(Name("Twin".to_owned())).commit();
+
+        let result = world.set_name_mode(NameMode::Unique);
+        assert!(matches!(result, Err(EcsErrors::DuplicateName(name)) if name == "Twin"));
+        world.set_name(&first, "Solo");
+        world.set_name_mode(NameMode::Unique).unwrap();
+    }
+
+    #[test]
+    fn unique_names_however_added() {
+        let mut world = World::new();
+        world.set_name_mode(NameMode::Unique).unwrap();
+        let player = world.spawn().with(Name("Player".to_owned())).commit();
+
+        let spawned = world.spawn().with((Name("Player".to_owned()),)).try_commit();
+        assert!(matches!(spawned, Err(EcsErrors::DuplicateName(_))));
+        let other = world.spawn().commit();
+        let added = world.entity_manager_mut().add_component(&other, Name("Player".to_owned()));
+        assert!(matches!(added, Err(EcsErrors::DuplicateName(_))));
+
+        let copy = world.clone_entity(&player);
+        assert!(!world.has_component::<Name>(&copy));
+        assert_eq!(world.find_all_by_name("Player"), vec![player]);
+    }
+}
@@ -0,0 +1,272 @@
+use std::{collections::BTreeSet, marker::PhantomData};
+
+use ecs_macro::Reflect;
+use log::info;
+
+use crate::{
+    command_buffer::CommandBuffer,
+    components::{comp_pool::CompPool, Component},
+    entities::Entity,
+    events::EventEmitter,
+    hierarchy::Parent,
+    query::Query,
+    system::{System, SystemBuilder},
+    world::World,
+};
+
+/// Transform data that can be chained from a parent to its children.
+pub trait Transform: Copy + PartialEq + 'static {
+    const IDENTITY: Self;
+
+    /// Combines `self` as the parent transform with the transform of a child.
+    fn mul(&self, child: &Self) -> Self;
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Reflect)]
+pub struct Transform2d {
+    pub translation: [f32; 2],
+    /// Counter-clockwise rotation in radians.
+    pub rotation: f32,
+    pub scale: [f32; 2],
+}
+
+impl Transform2d {
+    pub fn from_translation(x: f32, y: f32) -> Self {
+        Self {
+            translation: [x, y],
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn transform_point(&self, point: [f32; 2]) -> [f32; 2] {
+        let (sin, cos) = self.rotation.sin_cos();
+        let [x, y] = [point[0] * self.scale[0], point[1] * self.scale[1]];
+        [
+            self.translation[0] + x * cos - y * sin,
+            self.translation[1] + x * sin + y * cos,
+        ]
+    }
+}
+
+impl Transform for Transform2d {
+    const IDENTITY: Self = Self {
+        translation: [0.0, 0.0],
+        rotation: 0.0,
+        scale: [1.0, 1.0],
+    };
+
+    fn mul(&self, child: &Self) -> Self {
+        Self {
+            translation: self.transform_point(child.translation),
+            rotation: self.rotation + child.rotation,
+            scale: [self.scale[0] * child.scale[0], self.scale[1] * child.scale[1]],
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Reflect)]
+pub struct Transform3d {
+    pub translation: [f32; 3],
+    /// Unit quaternion stored as `[x, y, z, w]`.
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl Transform3d {
+    pub fn from_translation(x: f32, y: f32, z: f32) -> Self {
+        Self {
+            translation: [x, y, z],
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_axis_angle(axis: [f32; 3], angle: f32) -> Self {
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Self {
+            rotation: [axis[0] * sin, axis[1] * sin, axis[2] * sin, cos],
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn transform_point(&self, point: [f32; 3]) -> [f32; 3] {
+        let scaled = [
+            point[0] * self.scale[0],
+            point[1] * self.scale[1],
+            point[2] * self.scale[2],
+        ];
+        let rotated = rotate(self.rotation, scaled);
+        [
+            self.translation[0] + rotated[0],
+            self.translation[1] + rotated[1],
+            self.translation[2] + rotated[2],
+        ]
+    }
+}
+
+impl Transform for Transform3d {
+    const IDENTITY: Self = Self {
+        translation: [0.0, 0.0, 0.0],
+        rotation: [0.0, 0.0, 0.0, 1.0],
+        scale: [1.0, 1.0, 1.0],
+    };
+
+    fn mul(&self, child: &Self) -> Self {
+        Self {
+            translation: self.transform_point(child.translation),
+            rotation: quat_mul(self.rotation, child.rotation),
+            scale: [
+                self.scale[0] * child.scale[0],
+                self.scale[1] * child.scale[1],
+                self.scale[2] * child.scale[2],
+            ],
+        }
+    }
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn rotate(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let axis = [q[0], q[1], q[2]];
+    let t = cross(axis, v).map(|c| c * 2.0);
+    let c = cross(axis, t);
+    [
+        v[0] + q[3] * t[0] + c[0],
+        v[1] + q[3] * t[1] + c[1],
+        v[2] + q[3] * t[2] + c[2],
+    ]
+}
+
+fn quat_mul(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    let [ax, ay, az, aw] = a;
+    let [bx, by, bz, bw] = b;
+    [
+        aw * bx + ax * bw + ay * bz - az * by,
+        aw * by - ax * bz + ay * bw + az * bx,
+        aw * bz + ax * by - ay * bx + az * bw,
+        aw * bw - ax * bx - ay * by - az * bz,
+    ]
+}
+
+/// Transform relative to the parent entity, or to the world for roots.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LocalTransform<T: Transform>(pub T);
+
+impl<T: Transform> Component for LocalTransform<T> {}
+
+/// Transform relative to the world, written by `TransformPropagation`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct GlobalTransform<T: Transform>(pub T);
+
+impl<T: Transform> Component for GlobalTransform<T> {}
+
+impl<T: Transform> Default for GlobalTransform<T> {
+    fn default() -> Self {
+        Self(T::IDENTITY)
+    }
+}
+
+/// Recomputes `GlobalTransform` for entities having both transform components.
+/// Entities in the hierarchy without them pass their parent's transform on
+/// unchanged.
+///
+/// Only subtrees whose root had its `LocalTransform` or `Parent` changed since
+/// the previous run are walked; everything else keeps its global transform.
+pub struct TransformPropagation<T: Transform> {
+    last_run: u64,
+    transform: PhantomData<T>,
+}
+
+impl<T: Transform> Default for TransformPropagation<T> {
+    fn default() -> Self {
+        Self {
+            last_run: 0,
+            transform: PhantomData,
+        }
+    }
+}
+
+impl<T: Transform> System for TransformPropagation<T> {
+    fn action(
+        &mut self,
+        query: Query,
+        _entities: &[Entity],
+        _command_buffer: &mut CommandBuffer,
+        _emitter: EventEmitter,
+    ) {
+        let since = self.last_run;
+        self.last_run = query.tick();
+
+        let locals = query.components().get::<LocalTransform<T>>();
+        let mut globals = query.components().get_mut::<GlobalTransform<T>>();
+        let has_transforms = |globals: &CompPool<GlobalTransform<T>>, id: usize| {
+            locals.data.get(id).is_some_and(|l| l.is_some())
+                && globals.data.get(id).is_some_and(|g| g.is_some())
+        };
+
+        // Entities without transforms count as identity, so changes below
+        // them still have to reach their descendants. A global transform
+        // added to an unchanged local one needs filling in as well.
+        let mut dirty: BTreeSet<usize> = locals.iter_changed(since).collect();
+        dirty.extend((0..globals.data.len()).filter(|id| globals.added_since(*id, since)));
+        if let Ok(parents) = query.components().try_get::<Parent>() {
+            dirty.extend(parents.iter_changed(since));
+        }
+
+        let roots: Vec<Entity> = dirty
+            .iter()
+            .map(|id| Entity(*id))
+            .filter(|entity| {
+                !query
+                    .ancestors(entity)
+                    .iter()
+                    .any(|ancestor| dirty.contains(&ancestor.0))
+            })
+            .collect();
+
+        for root in roots {
+            let parent_global = query
+                .ancestors(&root)
+                .iter()
+                .find(|ancestor| has_transforms(&globals, ancestor.0))
+                .map(|ancestor| globals.get(ancestor.0).unwrap().0)
+                .unwrap_or(T::IDENTITY);
+
+            let mut stack = vec![(root, parent_global)];
+            while let Some((entity, parent_global)) = stack.pop() {
+                let global = if has_transforms(&globals, entity.0) {
+                    let global = parent_global.mul(&locals.get(entity.0).unwrap().0);
+                    globals.get_mut(entity.0).unwrap().0 = global;
+                    global
+                } else {
+                    parent_global
+                };
+                stack.extend(query.children(&entity).into_iter().map(|child| (child, global)));
+            }
+        }
+        info!("Propagated transforms changed since tick {since}");
+    }
+}
+
+impl<'a> World<'a> {
+    /// Registers the transform components for `T` along with the
+    /// `TransformPropagation<T>` system, which is then run through
+    /// `update_system::<TransformPropagation<T>>()`.
+    pub fn add_transform_propagation<T: Transform>(&mut self) {
+        self.register_component::<LocalTransform<T>>();
+        self.register_component::<GlobalTransform<T>>();
+
+        let system = SystemBuilder::new(self.get_component_signatures())
+            .with_action(TransformPropagation::<T>::default())
+            .with_component::<LocalTransform<T>>()
+            .with_component::<GlobalTransform<T>>()
+            .build();
+
+        self.add_system::<TransformPropagation<T>>(system, true);
+    }
+}
@@ -0,0 +1,446 @@
+use std::{
+    cell::{Ref, RefMut},
+    marker::PhantomData,
+};
+
+use crate::{
+    components::{comp_pool::CompPool, component_manager::ComponentManager, Component},
+    entities::{
+        entity_manager::{signature_matches, EntityManager},
+        Entity, EntityRef,
+    },
+    errors::EcsErrors,
+    query::Query,
+};
+
+/// Batch size of `par_for_each` unless set with `TypedQuery::batch_size`.
+pub const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// What a typed query hands out per entity: `&T`, `&mut T`, `Entity`,
+/// `Related` or a tuple of those.
+///
+/// The pools stay borrowed for the whole iteration, so asking for the same
+/// component twice with `&mut` panics like any other conflicting borrow.
+pub trait QueryData {
+    type Item<'i>;
+    /// Borrows of the pools, held while iterating.
+    type Guard<'q>;
+    /// Unchecked access to the borrowed pools, copied to worker threads.
+    type Ptr: Copy;
+    /// Whether matching the mask is not enough and `retain` has to run.
+    const FILTERS: bool = false;
+
+    fn mask(component_manager: &ComponentManager) -> Result<u32, EcsErrors>;
+
+    /// Drops the entities that match the mask but still can't be fetched,
+    /// like those referring to an entity that is gone.
+    fn retain(
+        _component_manager: &ComponentManager,
+        _entity_manager: &EntityManager,
+        _entities: &mut Vec<Entity>,
+    ) -> Result<(), EcsErrors> {
+        Ok(())
+    }
+
+    fn borrow<'q>(component_manager: &'q ComponentManager) -> Result<Self::Guard<'q>, EcsErrors>;
+    fn ptr(guard: &mut Self::Guard<'_>) -> Self::Ptr;
+
+    /// # Safety
+    /// `ptr` has to come from a guard that is still alive, and no two calls
+    /// may be made for the same `entity` while their items are in use.
+    unsafe fn get<'i>(ptr: Self::Ptr, entity: &Entity) -> Self::Item<'i>;
+}
+
+/// Query data handing out shared references only, which makes it fine to
+/// fetch for several entities at the same time.
+///
+/// # Safety
+/// Implementors may not hand out mutable access or stamp change ticks.
+pub unsafe trait ReadOnlyQueryData: QueryData {}
+
+unsafe impl<T: Component + 'static> ReadOnlyQueryData for &T {}
+unsafe impl ReadOnlyQueryData for Entity {}
+
+pub struct ReadPtr<T> {
+    data: *const Option<T>,
+    len: usize,
+}
+
+impl<T> Clone for ReadPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ReadPtr<T> {}
+
+// Only shared references are handed out, which is fine across threads for
+// `Sync` components.
+unsafe impl<T: Sync> Send for ReadPtr<T> {}
+unsafe impl<T: Sync> Sync for ReadPtr<T> {}
+
+pub struct WritePtr<T> {
+    data: *mut Option<T>,
+    changed: *mut u64,
+    len: usize,
+    tick: u64,
+}
+
+impl<T> Clone for WritePtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for WritePtr<T> {}
+
+// Every entity is visited once, so each slot is written by one thread only.
+unsafe impl<T: Send> Send for WritePtr<T> {}
+unsafe impl<T: Send> Sync for WritePtr<T> {}
+
+impl<T: Component + 'static> QueryData for &T {
+    type Item<'i> = &'i T;
+    type Guard<'q> = Ref<'q, CompPool<T>>;
+    type Ptr = ReadPtr<T>;
+
+    fn mask(component_manager: &ComponentManager) -> Result<u32, EcsErrors> {
+        component_manager.get_mask::<T>().copied()
+    }
+
+    fn borrow<'q>(component_manager: &'q ComponentManager) -> Result<Self::Guard<'q>, EcsErrors> {
+        component_manager.get_components::<T>()
+    }
+
+    fn ptr(guard: &mut Self::Guard<'_>) -> Self::Ptr {
+        ReadPtr {
+            data: guard.data.as_ptr(),
+            len: guard.data.len(),
+        }
+    }
+
+    unsafe fn get<'i>(ptr: Self::Ptr, entity: &Entity) -> Self::Item<'i> {
+        assert!(entity.0 < ptr.len, "Entity Id = {} has no {}", entity.0, std::any::type_name::<T>());
+        (*ptr.data.add(entity.0)).as_ref().unwrap()
+    }
+}
+
+impl<T: Component + 'static> QueryData for &mut T {
+    type Item<'i> = &'i mut T;
+    type Guard<'q> = RefMut<'q, CompPool<T>>;
+    type Ptr = WritePtr<T>;
+
+    fn mask(component_manager: &ComponentManager) -> Result<u32, EcsErrors> {
+        component_manager.get_mask::<T>().copied()
+    }
+
+    fn borrow<'q>(component_manager: &'q ComponentManager) -> Result<Self::Guard<'q>, EcsErrors> {
+        component_manager.get_components_mut::<T>()
+    }
+
+    fn ptr(guard: &mut Self::Guard<'_>) -> Self::Ptr {
+        let (data, changed, tick) = guard.slots_mut();
+        WritePtr {
+            data: data.as_mut_ptr(),
+            changed: changed.as_mut_ptr(),
+            len: data.len(),
+            tick,
+        }
+    }
+
+    unsafe fn get<'i>(ptr: Self::Ptr, entity: &Entity) -> Self::Item<'i> {
+        assert!(entity.0 < ptr.len, "Entity Id = {} has no {}", entity.0, std::any::type_name::<T>());
+        *ptr.changed.add(entity.0) = ptr.tick;
+        (*ptr.data.add(entity.0)).as_mut().unwrap()
+    }
+}
+
+impl QueryData for Entity {
+    type Item<'i> = Entity;
+    type Guard<'q> = ();
+    type Ptr = ();
+
+    fn mask(_: &ComponentManager) -> Result<u32, EcsErrors> {
+        Ok(0)
+    }
+
+    fn borrow<'q>(_: &'q ComponentManager) -> Result<Self::Guard<'q>, EcsErrors> {
+        Ok(())
+    }
+
+    fn ptr(_: &mut Self::Guard<'_>) -> Self::Ptr {}
+
+    unsafe fn get<'i>(_: Self::Ptr, entity: &Entity) -> Self::Item<'i> {
+        *entity
+    }
+}
+
+macro_rules! tuple_query_data {
+    ($($name:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<$($name: QueryData),*> QueryData for ($($name,)*) {
+            type Item<'i> = ($($name::Item<'i>,)*);
+            type Guard<'q> = ($($name::Guard<'q>,)*);
+            type Ptr = ($($name::Ptr,)*);
+
+            const FILTERS: bool = false $(|| $name::FILTERS)*;
+
+            fn mask(component_manager: &ComponentManager) -> Result<u32, EcsErrors> {
+                Ok(0 $(| $name::mask(component_manager)?)*)
+            }
+
+            fn retain(
+                component_manager: &ComponentManager,
+                entity_manager: &EntityManager,
+                entities: &mut Vec<Entity>,
+            ) -> Result<(), EcsErrors> {
+                $(if $name::FILTERS {
+                    $name::retain(component_manager, entity_manager, entities)?;
+                })*
+                Ok(())
+            }
+
+            fn borrow<'q>(component_manager: &'q ComponentManager) -> Result<Self::Guard<'q>, EcsErrors> {
+                Ok(($($name::borrow(component_manager)?,)*))
+            }
+
+            fn ptr(guard: &mut Self::Guard<'_>) -> Self::Ptr {
+                let ($($name,)*) = guard;
+                ($($name::ptr($name),)*)
+            }
+
+            unsafe fn get<'i>(ptr: Self::Ptr, entity: &Entity) -> Self::Item<'i> {
+                let ($($name,)*) = ptr;
+                ($($name::get($name, entity),)*)
+            }
+        }
+
+        unsafe impl<$($name: ReadOnlyQueryData),*> ReadOnlyQueryData for ($($name,)*) {}
+    };
+}
+
+tuple_query_data!(A);
+tuple_query_data!(A, B);
+tuple_query_data!(A, B, C);
+tuple_query_data!(A, B, C, D);
+tuple_query_data!(A, B, C, D, E);
+tuple_query_data!(A, B, C, D, E, F);
+
+/// Fetches `D` from the entity the `R` component of the queried entity
+/// refers to, so `(&Position, Related<Target, &Position>)` yields the
+/// position of every entity with a `Target` along with the position of that
+/// target.
+///
+/// Entities whose target is gone or lacks part of `D` are skipped. `D` has
+/// to be read-only, as several entities may share a target.
+pub struct Related<R, D>(PhantomData<(R, D)>);
+
+impl<R: Component + EntityRef + 'static, D: ReadOnlyQueryData> QueryData for Related<R, D> {
+    type Item<'i> = D::Item<'i>;
+    type Guard<'q> = (Ref<'q, CompPool<R>>, D::Guard<'q>);
+    type Ptr = (ReadPtr<R>, D::Ptr);
+    const FILTERS: bool = true;
+
+    fn mask(component_manager: &ComponentManager) -> Result<u32, EcsErrors> {
+        component_manager.get_mask::<R>().copied()
+    }
+
+    fn retain(
+        component_manager: &ComponentManager,
+        entity_manager: &EntityManager,
+        entities: &mut Vec<Entity>,
+    ) -> Result<(), EcsErrors> {
+        let mask = D::mask(component_manager)?;
+        let pool = component_manager.get_components::<R>()?;
+        let target = |entity: &Entity| pool.data[entity.0].as_ref().unwrap().entity();
+
+        let mut targets: Vec<Entity> = entities
+            .iter()
+            .map(target)
+            .filter(|t| {
+                entity_manager.is_alive(t)
+                    && entity_manager
+                        .entity_component_signatures
+                        .get(t.0)
+                        .is_some_and(|s| s & mask == mask)
+            })
+            .collect();
+        targets.sort();
+        targets.dedup();
+        if D::FILTERS {
+            D::retain(component_manager, entity_manager, &mut targets)?;
+        }
+
+        entities.retain(|entity| targets.binary_search(&target(entity)).is_ok());
+        Ok(())
+    }
+
+    fn borrow<'q>(component_manager: &'q ComponentManager) -> Result<Self::Guard<'q>, EcsErrors> {
+        Ok((component_manager.get_components::<R>()?, D::borrow(component_manager)?))
+    }
+
+    fn ptr(guard: &mut Self::Guard<'_>) -> Self::Ptr {
+        (<&R as QueryData>::ptr(&mut guard.0), D::ptr(&mut guard.1))
+    }
+
+    unsafe fn get<'i>(ptr: Self::Ptr, entity: &Entity) -> Self::Item<'i> {
+        let target = <&R as QueryData>::get(ptr.0, entity).entity();
+        D::get(ptr.1, &target)
+    }
+}
+
+unsafe impl<R: Component + EntityRef + 'static, D: ReadOnlyQueryData> ReadOnlyQueryData for Related<R, D> {}
+
+/// Iterates the components of every entity matching `D`, sequentially or
+/// in batches on a thread pool.
+pub struct TypedQuery<'a, D: QueryData> {
+    component_manager: &'a ComponentManager<'a>,
+    entity_manager: &'a EntityManager<'a>,
+    entities: Option<&'a [Entity]>,
+    include_disabled: bool,
+    batch_size: usize,
+    data: PhantomData<D>,
+}
+
+impl<'a> Query<'a> {
+    pub fn typed<D: QueryData>(&self) -> TypedQuery<'a, D> {
+        TypedQuery {
+            component_manager: self.component_manager,
+            entity_manager: self.entity_manager,
+            entities: None,
+            include_disabled: false,
+            batch_size: DEFAULT_BATCH_SIZE,
+            data: PhantomData,
+        }
+    }
+}
+
+impl<'a, D: QueryData> TypedQuery<'a, D> {
+    /// Visits only `entities`, e.g. those of a system or a cached query,
+    /// instead of scanning every signature. Iterating fails if an entity is
+    /// listed twice or misses a component of `D`.
+    pub fn with_entities(mut self, entities: &'a [Entity]) -> Self {
+        self.entities = Some(entities);
+        self
+    }
+
+    /// Also visits entities disabled with `World::disable_entity`. Entities
+    /// given through `with_entities` are visited either way.
+    pub fn include_disabled(mut self) -> Self {
+        self.include_disabled = true;
+        self
+    }
+
+    /// Number of entities each `par_for_each` task handles.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    fn matching(&self) -> Result<Vec<Entity>, EcsErrors> {
+        let mask = D::mask(self.component_manager)?;
+        let entity_manager = self.entity_manager;
+        let disabled = if self.include_disabled {
+            0
+        } else {
+            entity_manager.disabled_mask()
+        };
+        Ok(entity_manager
+            .entity_component_signatures
+            .iter()
+            .enumerate()
+            .map(|(id, s)| (Entity(id), *s))
+            .filter(|(entity, s)| signature_matches(*s, mask, disabled) && entity_manager.is_alive(entity))
+            .map(|(entity, _)| entity)
+            .collect())
+    }
+
+    /// Handing out `&mut` twice for one slot would alias, so given entity
+    /// lists are checked before anything is borrowed.
+    fn check(&self, entities: &[Entity]) -> Result<(), EcsErrors> {
+        let mask = D::mask(self.component_manager)?;
+        let mut seen = vec![false; self.entity_manager.entity_component_signatures.len()];
+        for entity in entities {
+            let signature = self.entity_manager.get_signature(entity)?;
+            if signature & mask != mask || seen[entity.0] {
+                return Err(EcsErrors::InvalidQueryEntity(entity.0));
+            }
+            seen[entity.0] = true;
+        }
+        Ok(())
+    }
+
+    fn run(self, f: impl FnOnce(&[Entity], D::Ptr)) -> Result<(), EcsErrors> {
+        let mut scanned;
+        let entities = match self.entities {
+            Some(entities) if D::FILTERS => {
+                self.check(entities)?;
+                scanned = entities.to_vec();
+                D::retain(self.component_manager, self.entity_manager, &mut scanned)?;
+                &scanned
+            }
+            Some(entities) => {
+                self.check(entities)?;
+                entities
+            }
+            None => {
+                scanned = self.matching()?;
+                if D::FILTERS {
+                    D::retain(self.component_manager, self.entity_manager, &mut scanned)?;
+                }
+                &scanned
+            }
+        };
+
+        let mut guard = D::borrow(self.component_manager)?;
+        f(entities, D::ptr(&mut guard));
+        Ok(())
+    }
+
+    pub fn for_each(self, mut f: impl FnMut(D::Item<'_>)) {
+        self.try_for_each(|item| f(item)).unwrap()
+    }
+
+    pub fn try_for_each(self, mut f: impl FnMut(D::Item<'_>)) -> Result<(), EcsErrors> {
+        self.run(|entities, ptr| {
+            for entity in entities {
+                // SAFETY: the guard outlives the loop and every entity is
+                // visited once.
+                f(unsafe { D::get(ptr, entity) });
+            }
+        })
+    }
+
+    /// Like `for_each`, but batches of entities run on the rayon thread
+    /// pool. Without the `parallel` feature the batches run one after the
+    /// other on the calling thread.
+    pub fn par_for_each(self, f: impl Fn(D::Item<'_>) + Send + Sync)
+    where
+        D::Ptr: Send + Sync,
+    {
+        self.try_par_for_each(f).unwrap()
+    }
+
+    pub fn try_par_for_each(self, f: impl Fn(D::Item<'_>) + Send + Sync) -> Result<(), EcsErrors>
+    where
+        D::Ptr: Send + Sync,
+    {
+        let batch_size = self.batch_size;
+        self.run(|entities, ptr| {
+            let batch = |batch: &[Entity]| {
+                for entity in batch {
+                    // SAFETY: batches don't overlap and every entity is
+                    // visited once, so no slot is handed out twice.
+                    f(unsafe { D::get(ptr, entity) });
+                }
+            };
+
+            #[cfg(feature = "parallel")]
+            {
+                use rayon::prelude::*;
+                entities.par_chunks(batch_size).for_each(batch);
+            }
+            #[cfg(not(feature = "parallel"))]
+            entities.chunks(batch_size).for_each(batch);
+        })
+    }
+}
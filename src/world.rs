@@ -1,56 +1,106 @@
-use log::info;
+use log::{info, warn};
 
-use crate::{command_buffer::WorldCommand, system::InternalSystem};
+#[cfg(feature = "serde")]
+use crate::scene::SceneRegistry;
+use crate::{command_buffer::WorldCommand, errors::EcsErrors, system::InternalSystem};
 use std::{
+    alloc::Layout,
     any::{type_name, Any, TypeId}, 
-    collections::{HashMap, HashSet}
+    collections::{BTreeSet, HashMap}
 };
 
 use super::{
+    binary::BinaryRegistry,
+    cached_query::QueryCache,
     command_buffer::CommandBuffer,
-    components::Component,
-    entities::{entity_manager::EntityManager, Entity},
+    components::{dynamic::ComponentId, Component},
+    entities::{
+        builder::EntityBuilder,
+        entity_manager::{signature_matches, EntityManager},
+        Disabled, Entity,
+    },
     events::{EventEmitter, GameEvent, WorldEventEmmiter, WorldEventSubscriber, WorldEvents},
+    hierarchy::{Children, Parent},
     query::Query,
+    reflect::ReflectRegistry,
+    relations::{Relation, Relations},
     resources::Resources,
+    rollback::StateHashRegistry,
+    snapshot::{SnapshotRegistry, WorldSnapshot},
 };
 
 pub struct World<'a> {
     entity_manager: EntityManager<'a>,
-    systems: HashMap<TypeId, Box<dyn InternalSystem>>,
+    /// Systems in registration order, which is also the order they run in.
+    systems: Vec<(TypeId, Box<dyn InternalSystem>)>,
     resources: Resources,
 
-    entities_to_add: HashSet<Entity>,
-    entities_to_remove: HashSet<Entity>,
+    /// Pending entities are handled in id order.
+    entities_to_add: BTreeSet<Entity>,
+    entities_to_remove: BTreeSet<Entity>,
 
     current_entity: Option<Entity>,
     events: WorldEvents,
+    snapshots: SnapshotRegistry,
+    #[cfg(feature = "serde")]
+    pub(crate) scenes: SceneRegistry,
+    pub(crate) binary: BinaryRegistry,
+    pub(crate) state_hashers: StateHashRegistry,
+    pub(crate) reflection: ReflectRegistry,
+    pub(crate) queries: QueryCache,
+}
+
+impl<'a> Default for World<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a> World<'a> {
     pub fn new() -> Self {
-        env_logger::init();
+        let _ = env_logger::try_init();
         Self {
             entity_manager: EntityManager::new(),
-            systems: HashMap::new(),
+            systems: Vec::new(),
             resources: Resources::new(),
-            entities_to_add: HashSet::new(),
-            entities_to_remove: HashSet::new(),
+            entities_to_add: BTreeSet::new(),
+            entities_to_remove: BTreeSet::new(),
             current_entity: None,
             events: WorldEvents::new(),
+            snapshots: SnapshotRegistry::new(),
+            #[cfg(feature = "serde")]
+            scenes: SceneRegistry::new(),
+            binary: BinaryRegistry::new(),
+            state_hashers: StateHashRegistry::default(),
+            reflection: ReflectRegistry::default(),
+            queries: QueryCache::default(),
         }
     }
 
     pub fn update(&mut self) {
+        self.entity_manager.component_manager.advance_tick();
+        self.sync_systems();
+
         let entities_to_add = std::mem::take(&mut self.entities_to_add);
         entities_to_add.iter().for_each(|entity| {
             self.add_entity_to_systems(*entity);
         });
 
-        let entities_to_remove = std::mem::take(&mut self.entities_to_remove);
-        entities_to_remove
-            .iter()
-            .for_each(|entity| self.kill_entity(entity));
+        // Killing an entity can cascade into more removals through relation
+        // cleanup policies, so keep going until nothing is left.
+        while !self.entities_to_remove.is_empty() {
+            let entities_to_remove = std::mem::take(&mut self.entities_to_remove);
+            entities_to_remove
+                .iter()
+                .for_each(|entity| self.kill_entity(entity));
+        }
+    }
+
+    /// Starts a new entity whose components are added in one go by
+    /// `EntityBuilder::commit`. Unlike `create_entity`, it joins systems
+    /// immediately.
+    pub fn spawn(&mut self) -> EntityBuilder<'_, 'a> {
+        EntityBuilder::new(self)
     }
 
     pub fn create_entity(&mut self) -> &mut Self {
@@ -73,46 +123,218 @@ impl<'a> World<'a> {
     }
 
     fn add_entity_to_systems(&mut self, entity: Entity) {
-            info!("Adding entity id = {} to systems", entity.0);
+            info!("Adding {} to systems", self.entity_manager.label(&entity));
 
-        let key = self.entity_manager.get_signature(&entity).unwrap();
+        let key = *self.entity_manager.get_signature(&entity).unwrap();
+        let disabled = self.entity_manager.disabled_mask();
 
         self.systems
-            .values_mut()
+            .iter_mut()
+            .map(|(_, system)| system)
             .filter(|s| {
-                (*key & s.signature()) == s.signature()
+                signature_matches(key, s.signature(), disabled)
             })
             .for_each(|system| {
                 system.as_mut().add_entity(entity);
                 info!(
-                    "Adding entity id = {} to system {}",
-                    entity.0, system.name()
+                    "Adding {} to system {}",
+                    self.entity_manager.label(&entity), system.name()
                 );
             });
     }
 
+    /// Moves every entity whose signature changed since the last call into
+    /// or out of the systems and cached queries it now matches or no longer
+    /// matches. Entities created with `create_entity` wait for `update`
+    /// before joining systems.
+    pub(crate) fn sync_systems(&mut self) {
+        let disabled = self.entity_manager.disabled_mask();
+        for (entity, old) in self.entity_manager.take_signature_changes() {
+            let new = self.entity_manager.get_signature(&entity).copied().unwrap_or(0);
+            if new == old {
+                continue;
+            }
+            // Cached queries answer like uncached ones, only systems wait.
+            self.queries.update(entity, old, new, disabled);
+            if self.entities_to_add.contains(&entity) {
+                continue;
+            }
+
+            for (_, system) in self.systems.iter_mut() {
+                let signature = system.signature();
+                let matched = signature_matches(old, signature, disabled);
+                let matches = signature_matches(new, signature, disabled);
+                if matches && !matched {
+                    info!("Adding {} to system {}", self.entity_manager.label(&entity), system.name());
+                    system.add_entity(entity);
+                } else if matched && !matches {
+                    info!("Removing {} from system {}", self.entity_manager.label(&entity), system.name());
+                    system.remove_entity(&entity);
+                }
+            }
+        }
+    }
+
     pub fn remove_entity(&mut self, entity: &Entity) {
-            info!("Removing entity id = {}", entity.0);
+            info!("Removing {}", self.entity_manager.label(entity));
 
         self.entities_to_remove.insert(*entity);
     }
 
+    /// Removes entities spawned by an operation that failed halfway, right
+    /// away instead of on the next `update`.
+    pub(crate) fn discard_spawned(&mut self, entities: &[Entity]) {
+        self.sync_systems();
+        for entity in entities {
+            self.entities_to_add.remove(entity);
+            self.kill_entity(entity);
+        }
+    }
+
     fn kill_entity(&mut self, entity: &Entity) {
-            info!("Killing entity id = {}", entity.0);
+        if !self.entity_manager.is_alive(entity) {
+            return;
+        }
+            info!("Killing {}", self.entity_manager.label(entity));
 
-        let key = self.entity_manager.get_signature(entity).unwrap();
+        // Membership is dropped from every system, so an entity whose
+        // signature went out of sync can't stay behind.
         self.systems
-            .values_mut()
-            .filter(|s| (*key & s.signature()) == s.signature())
+            .iter_mut()
+            .map(|(_, system)| system)
             .for_each(|system| {
                 info!(
-                    "Removing id = {} from system {}",
-                    entity.0, system.name()
+                    "Removing {} from system {}",
+                    self.entity_manager.label(entity), system.name()
                 );
                 system.remove_entity(entity);
             });
 
+        self.entity_manager.detach_from_hierarchy(entity);
+        for cleanup in self.entity_manager.component_manager.release_fns() {
+            for source in cleanup(&mut self.entity_manager, entity) {
+                self.remove_entity(&source);
+            }
+        }
         self.entity_manager.remove_entity(entity);
+        self.sync_systems();
+    }
+
+    pub fn set_parent(&mut self, child: &Entity, parent: &Entity) -> Result<(), EcsErrors> {
+        let result = self.entity_manager.set_parent(child, parent);
+        self.sync_systems();
+        result
+    }
+
+    pub fn remove_parent(&mut self, child: &Entity) -> Result<(), EcsErrors> {
+        let result = self.entity_manager.remove_parent(child);
+        self.sync_systems();
+        result
+    }
+
+    pub fn despawn_recursive(&mut self, entity: &Entity) {
+        info!("Removing {} with its descendants", self.entity_manager.label(entity));
+
+        for descendant in self.entity_manager.descendants_of(entity) {
+            self.remove_entity(&descendant);
+        }
+        self.remove_entity(entity);
+    }
+
+    /// Fails like `set_parent` when `source` or `target` is not alive.
+    pub fn add_relation<R: Relation>(&mut self, source: &Entity, relation: R, target: &Entity) -> Result<(), EcsErrors> {
+        if let Some(register) = R::snapshot_fn() {
+            if !self.snapshots.is_component_registered::<Relations<R>>() {
+                register(&mut self.snapshots);
+            }
+        }
+        let result = self.entity_manager.add_relation(source, relation, target);
+        self.sync_systems();
+        result
+    }
+
+    pub fn remove_relation<R: Relation>(&mut self, source: &Entity, target: &Entity) -> Result<(), EcsErrors> {
+        let result = self.entity_manager.remove_relation::<R>(source, target);
+        self.sync_systems();
+        result
+    }
+
+    pub fn has_relation<R: Relation>(&self, source: &Entity, target: &Entity) -> bool {
+        self.query().relations::<R>().contains(source, target)
+    }
+
+    pub fn register_snapshot_component<T: Component + Clone + 'static>(&mut self) {
+        self.snapshots.register_component::<T>();
+    }
+
+    pub fn register_snapshot_resource<T: Clone + Any>(&mut self) {
+        self.snapshots.register_resource::<T>();
+    }
+
+    pub fn snapshot(&self) -> WorldSnapshot {
+        let mut snapshot = self.snapshots.snapshot(&self.entity_manager, &self.resources);
+        snapshot.entities_to_add = self.entities_to_add.clone();
+        snapshot.entities_to_remove = self.entities_to_remove.clone();
+
+        info!(
+            "Snapshot taken, skipped components {:?} and resources {:?}",
+            snapshot.skipped_components(),
+            snapshot.skipped_resources()
+        );
+        snapshot
+    }
+
+    /// Restores a snapshot taken from this world. The tick is not rewound, so
+    /// change detection sees every restored component as changed.
+    pub fn restore(&mut self, snapshot: &WorldSnapshot) {
+        self.snapshots
+            .restore(snapshot, &mut self.entity_manager, &mut self.resources);
+        self.entity_manager.stamp_all_entities();
+        self.entities_to_add = snapshot.entities_to_add.clone();
+        self.entities_to_remove = snapshot.entities_to_remove.clone();
+        self.current_entity = None;
+
+        self.resync_systems();
+        info!("Snapshot restored");
+    }
+
+    pub(crate) fn entity_manager(&self) -> &EntityManager<'a> {
+        &self.entity_manager
+    }
+
+    pub(crate) fn entity_manager_mut(&mut self) -> &mut EntityManager<'a> {
+        &mut self.entity_manager
+    }
+
+    pub(crate) fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    pub(crate) fn clear_pending_entities(&mut self) {
+        self.entities_to_add.clear();
+        self.entities_to_remove.clear();
+        self.current_entity = None;
+    }
+
+    /// Rebuilds every system's entity list from the current signatures.
+    pub(crate) fn resync_systems(&mut self) {
+        self.entity_manager.take_signature_changes();
+        let entity_manager = &self.entity_manager;
+        let disabled = entity_manager.disabled_mask();
+        self.queries.rebuild(
+            disabled,
+            entity_manager
+                .entity_component_signatures
+                .iter()
+                .enumerate()
+                .map(|(id, s)| (Entity(id), *s))
+                .filter(|(entity, _)| entity_manager.is_alive(entity)),
+        );
+        for (_, system) in self.systems.iter_mut() {
+            system.clear_entities();
+            system_entities(entity_manager, &self.entities_to_add, system.signature())
+                .for_each(|entity| system.add_entity(entity));
+        }
     }
 
     pub fn events(&mut self) -> &mut impl WorldEventSubscriber {
@@ -135,66 +357,113 @@ impl<'a> World<'a> {
         let system_id = TypeId::of::<T>();
         let signature = system.signature();
         if update {
-            self.entity_manager
-                .entity_component_signatures
-                .iter()
-                .enumerate()
-                .filter(|(_, s)| (*s & signature) == signature)
-                .for_each(|(id, _)| system.add_entity(Entity(id)));
+            system_entities(&self.entity_manager, &self.entities_to_add, signature)
+                .for_each(|entity| system.add_entity(entity));
         }
         info!("Adding systems {}", system.name());
-        self.systems.insert(system_id, Box::new(system));
-        println!("systems add {:?}", self.systems.len());
+        match self.system_index(&system_id) {
+            Some(index) => self.systems[index].1 = Box::new(system),
+            None => self.systems.push((system_id, Box::new(system))),
+        }
     }
 
     pub fn remove_system<T: 'static>(&mut self) {
         let system_id = TypeId::of::<T>();
-        if let Some(system) = self.systems.remove(&system_id) {
+        if let Some(index) = self.system_index(&system_id) {
+            let (_, system) = self.systems.remove(index);
                info!("Removing system {}", system.name());
         }
     }
 
     pub fn update_system<T: 'static>(&mut self) {
         let system_id = TypeId::of::<T>();
-        let mut systems = std::mem::take(&mut self.systems);
-        if let Some(system) = systems.get_mut(&system_id) {
-                info!("Updating system {}", system.name());
+        match self.system_index(&system_id) {
+            Some(index) => self.call_system(index),
+            None => info!("Skipping system {} update", type_name::<T>()),
+        }
+    }
 
-            let command_buffer = system.call(self);
-            self.handle_commands(command_buffer);
-        } else {
-                info!("Skipping system {} update", type_name::<T>());
+    /// Runs every system once, in the order they were added.
+    pub fn run_systems(&mut self) {
+        for index in 0..self.systems.len() {
+            self.call_system(index);
         }
+    }
+
+    /// Runs one system, then applies its commands with all systems back in
+    /// place so their entity lists follow the changes.
+    fn call_system(&mut self, index: usize) {
+        let mut systems = std::mem::take(&mut self.systems);
+        let system = &mut systems[index].1;
+        info!("Updating system {}", system.name());
 
-        self.systems = std::mem::take(&mut systems);
+        let command_buffer = system.call(self);
+        self.systems = systems;
+        self.handle_commands(command_buffer);
     }
 
     fn handle_commands(&mut self, command_buffer: CommandBuffer) {
-        for command in command_buffer.iterate() {
-            match command {
-                WorldCommand::RemoveEntity(id) => self.remove_entity(&Entity(*id)),
-                WorldCommand::RemoveComponent(id, comp_id) => {
-                    self.remove_component_with_id(&Entity(*id), comp_id)
+        for command in command_buffer {
+            self.handle_command(command);
+            self.sync_systems();
+        }
+    }
+
+    fn handle_command(&mut self, command: WorldCommand) {
+        match command {
+            WorldCommand::RemoveEntity(id) => self.remove_entity(&Entity(id)),
+            WorldCommand::RemoveComponent(id, comp_id) => {
+                self.remove_component_with_id(&Entity(id), &comp_id)
+            }
+            WorldCommand::SetParent(child, parent) => {
+                if let Err(err) = self.set_parent(&Entity(child), &Entity(parent)) {
+                    warn!("Failed to set parent: {err}");
+                }
+            }
+            WorldCommand::RemoveParent(id) => {
+                if let Err(err) = self.remove_parent(&Entity(id)) {
+                    warn!("Failed to remove parent: {err}");
+                }
+            }
+            WorldCommand::DespawnRecursive(id) => self.despawn_recursive(&Entity(id)),
+            WorldCommand::Spawn(bundle) => {
+                if let Err(err) = self.spawn().with_boxed(bundle).try_commit() {
+                    warn!("Failed to spawn bundle: {err}");
+                }
+            }
+            WorldCommand::InsertBundle(id, bundle) => {
+                if let Err(err) = bundle.insert_into(&mut self.entity_manager, &Entity(id)) {
+                    warn!("Failed to insert bundle: {err}");
+                }
+            }
+            WorldCommand::RemoveBundle(id, remove) => {
+                if let Err(err) = remove(&mut self.entity_manager, &Entity(id)) {
+                    warn!("Failed to remove bundle: {err}");
                 }
-                WorldCommand::AddComponent(_id, _comp) => todo!(),
-                WorldCommand::CreateEntity(_components) => todo!(),
             }
+            WorldCommand::Instantiate(prefab, overrides) => self.instantiate_command(&prefab, overrides),
+            WorldCommand::CloneEntity(id, policy) => self.clone_entity_command(&Entity(id), policy),
         }
     }
 
     pub fn has_system<T: 'static>(&self) -> bool {
         let system_id = TypeId::of::<T>();
-        self.systems.contains_key(&system_id)
+        self.system_index(&system_id).is_some()
     }
 
     pub fn get_system<T: 'static>(&self) -> &dyn InternalSystem {
         let system_id = TypeId::of::<T>();
-        self.systems.get(&system_id).unwrap().as_ref()
+        self.systems[self.system_index(&system_id).unwrap()].1.as_ref()
     }
 
     pub fn get_system_mut<T: 'static>(&mut self) -> &mut dyn InternalSystem {
         let system_id = TypeId::of::<T>();
-        self.systems.get_mut(&system_id).unwrap().as_mut()
+        let index = self.system_index(&system_id).unwrap();
+        self.systems[index].1.as_mut()
+    }
+
+    fn system_index(&self, system_id: &TypeId) -> Option<usize> {
+        self.systems.iter().position(|(id, _)| id == system_id)
     }
 
     pub fn add_resource<T: Any>(&mut self, resource: T) {
@@ -207,33 +476,136 @@ impl<'a> World<'a> {
             info!("Deleting resource {}", type_name::<T>());
     }
 
+    /// Adding `Parent` or `Children` goes through `set_parent`, so both ends
+    /// of the link stay in sync.
     pub fn add_component<T: Component + 'static>(&mut self, entity: &Entity, component: T) {
+        let any = &component as &dyn Any;
+        if let Some(parent) = any.downcast_ref::<Parent>() {
+            return self.set_parent(entity, &parent.0).unwrap();
+        }
+        if let Some(children) = any.downcast_ref::<Children>() {
+            for child in self.entity_manager.children_of(entity) {
+                self.remove_parent(&child).unwrap();
+            }
+            for child in children.iter() {
+                self.set_parent(child, entity).unwrap();
+            }
+            return;
+        }
+
         self.entity_manager
             .add_component(entity, component)
             .unwrap();
+        self.sync_systems();
 
         info!(
-            "Add component {} to Entity Id = {}",
+            "Add component {} to {}",
             type_name::<T>(),
-            entity.0
+            self.entity_manager.label(entity)
         );
     }
 
+    pub fn register_component<T: Component + 'static>(&mut self) {
+        self.try_register_component::<T>().unwrap();
+    }
+
+    /// Registers `T` and the components it requires, failing if the required
+    /// components form a cycle.
+    pub fn try_register_component<T: Component + 'static>(&mut self) -> Result<(), EcsErrors> {
+        self.entity_manager.component_manager.register_checked::<T>()?;
+        info!("Registered component {}", type_name::<T>());
+        Ok(())
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.entity_manager.component_manager.tick()
+    }
+
+    /// Removing `Parent` or `Children` detaches the entity from its parent or
+    /// its children, so the other end of the link goes as well.
     pub fn remove_component<T: Component + 'static>(&mut self, entity: &Entity) {
+        let mask = self.entity_manager.component_manager.get_mask::<T>().copied().unwrap_or(0);
+        self.entity_manager.detach_hierarchy(entity, mask).unwrap();
+
         self.entity_manager.remove_component::<T>(entity).unwrap();
+        self.sync_systems();
         info!(
-            "Removing component {} from Entity Id = {}",
+            "Removing component {} from {}",
             type_name::<T>(),
-            entity.0
+            self.entity_manager.label(entity)
         );
     }
 
+    /// Takes `entity` out of systems, cached queries and default queries
+    /// while keeping its id and components. Nothing is despawned or removed,
+    /// so `enable_entity` brings it back as it was.
+    pub fn disable_entity(&mut self, entity: &Entity) {
+        if !self.is_disabled(entity) {
+            self.add_component(entity, Disabled);
+            info!("Disabled {}", self.entity_manager.label(entity));
+        }
+    }
+
+    pub fn enable_entity(&mut self, entity: &Entity) {
+        if self.is_disabled(entity) {
+            self.remove_component::<Disabled>(entity);
+            info!("Enabled {}", self.entity_manager.label(entity));
+        }
+    }
+
+    pub fn is_disabled(&self, entity: &Entity) -> bool {
+        let disabled = self.entity_manager.disabled_mask();
+        self.entity_manager
+            .get_signature(entity)
+            .is_ok_and(|signature| signature & disabled != 0)
+    }
+
     fn remove_component_with_id(&mut self, entity: &Entity, comp_id: &TypeId) {
-        let _ = self.entity_manager.remove_component_for_id(entity, comp_id);
         info!(
-            "Removing component {} from Entity Id = {}",
-            "Unknown", entity.0
+            "Removing component {} from {}",
+            self.entity_manager.component_manager.component_name(comp_id),
+            self.entity_manager.label(entity)
         );
+        let _ = self.entity_manager.remove_component_for_id(entity, comp_id);
+    }
+
+    /// Registers a component type defined at runtime, stored as raw bytes
+    /// of `layout.size()` per entity.
+    pub fn register_dynamic_component(&mut self, name: &str, layout: Layout) -> Result<ComponentId, EcsErrors> {
+        let id = self
+            .entity_manager
+            .component_manager
+            .register_dynamic(name, layout)?;
+        info!("Registered dynamic component {name} with id {}", id.0);
+        Ok(id)
+    }
+
+    pub fn dynamic_component_id(&self, name: &str) -> Option<ComponentId> {
+        self.entity_manager
+            .component_manager
+            .dynamic_component(name)
+            .map(|info| info.id)
+    }
+
+    pub fn component_id<T: Component + 'static>(&self) -> Option<ComponentId> {
+        self.entity_manager.component_manager.component_id::<T>()
+    }
+
+    pub fn add_dynamic_component(&mut self, entity: &Entity, id: ComponentId, bytes: &[u8]) -> Result<(), EcsErrors> {
+        self.entity_manager.add_dynamic_component(entity, id, bytes)?;
+        self.sync_systems();
+        info!("Add dynamic component {} to {}", id.0, self.entity_manager.label(entity));
+        Ok(())
+    }
+
+    pub fn remove_component_by_id(&mut self, entity: &Entity, id: ComponentId) {
+        self.entity_manager.remove_component_by_id(entity, id).unwrap();
+        self.sync_systems();
+        info!("Removing component {} from {}", id.0, self.entity_manager.label(entity));
+    }
+
+    pub fn has_component_id(&self, entity: &Entity, id: ComponentId) -> bool {
+        self.entity_manager.has_component_id(entity, id).unwrap()
     }
 
     pub fn has_component<T: Component + 'static>(&self, entity: &Entity) -> bool {
@@ -244,11 +616,31 @@ impl<'a> World<'a> {
         self.entity_manager.get_component_signatures()
     }
 
-    pub fn query(&self) -> Query {
+    pub fn query(&self) -> Query<'_> {
         Query::new(
             &self.entity_manager,
             &self.entity_manager.component_manager,
             &self.resources,
+            &self.queries,
         )
     }
 }
+
+/// Alive entities matching `signature`. Pending entities are left out, as
+/// they join every system on the next `update`.
+fn system_entities<'e>(
+    entity_manager: &'e EntityManager,
+    entities_to_add: &'e BTreeSet<Entity>,
+    signature: u32,
+) -> impl Iterator<Item = Entity> + 'e {
+    let disabled = entity_manager.disabled_mask();
+    entity_manager
+        .entity_component_signatures
+        .iter()
+        .enumerate()
+        .map(|(id, s)| (Entity(id), *s))
+        .filter(|(entity, _)| entity_manager.is_alive(entity))
+        .filter(|(entity, _)| !entities_to_add.contains(entity))
+        .filter(move |(_, s)| signature_matches(*s, signature, disabled))
+        .map(|(entity, _)| entity)
+}